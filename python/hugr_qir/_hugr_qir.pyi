@@ -3,3 +3,9 @@ def opt_level_choices() -> list[str]: ...
 def opt_level_default() -> str: ...
 def compile_target_choices() -> list[str]: ...
 def compile_target_default() -> str: ...
+def ir_to_bitcode(ir: str) -> bytes: ...
+def ir_to_base64(ir: str) -> str: ...
+def result_manifest(hugr: bytes) -> str: ...
+def parse_shot_output(
+    output: str, manifest: str | None = None
+) -> list[list[tuple[str, bool | int | float]]]: ...
@@ -44,6 +44,46 @@ logger = logging.getLogger()
     show_default=True,
     help="LLVM compile target",
 )
+@click.option(
+    "--target-triple",
+    "target_triple",
+    default=None,
+    help="LLVM target triple, overriding the one implied by --target",
+)
+@click.option(
+    "--target-cpu",
+    "target_cpu",
+    default=None,
+    help="LLVM target CPU",
+)
+@click.option(
+    "--target-features",
+    "target_features",
+    default=None,
+    help="LLVM target features, e.g. +neon,-crypto",
+)
+@click.option(
+    "--target-file",
+    "target_file",
+    type=click.Path(exists=True, path_type=Path),
+    default=None,
+    help="JSON file describing the capabilities and limits of the target",
+)
+@click.option(
+    "--result-manifest",
+    "result_manifest",
+    type=click.Path(path_type=Path),
+    default=None,
+    help="File to write a JSON manifest of the recorded result tags and types to",
+)
+@click.option(
+    "--result-record-output",
+    "result_record_output",
+    is_flag=True,
+    default=False,
+    help="Record measurements flowing straight into bool results with "
+    "__quantum__rt__result_record_output",
+)
 @click.option(
     "-l",
     "--opt-level",
@@ -79,6 +119,12 @@ def hugr_qir(  # noqa: PLR0913
     output_format: str,
     hugr_file: Path,
     outfile: Path | None,
+    target_triple: str | None,
+    target_cpu: str | None,
+    target_features: str | None,
+    target_file: Path | None,
+    result_manifest: Path | None,
+    result_record_output: bool,
 ) -> None:
     """Convert a HUGR file to QIR.
 
@@ -94,6 +140,12 @@ def hugr_qir(  # noqa: PLR0913
         OutputFormat(output_format),
         hugr_file,
         outfile,
+        target_triple=target_triple,
+        target_cpu=target_cpu,
+        target_features=target_features,
+        target_file=target_file,
+        result_manifest=result_manifest,
+        result_record_output=result_record_output,
     )
 
 
@@ -105,15 +157,35 @@ def hugr_qir_impl(  # noqa: PLR0913
     output_format: OutputFormat,
     hugr_file: Path,
     outfile: Path | None,
+    *,
+    target_triple: str | None = None,
+    target_cpu: str | None = None,
+    target_features: str | None = None,
+    target_file: Path | None = None,
+    result_manifest: Path | None = None,
+    result_record_output: bool = False,
 ) -> None:
     options = ["-q"]
     options.extend(["-t", target])
+    if target_triple is not None:
+        options.extend(["--target-triple", target_triple])
+    if target_cpu is not None:
+        options.extend(["--target-cpu", target_cpu])
+    if target_features is not None:
+        options.extend(["--target-features", target_features])
+    if target_file is not None:
+        options.extend(["--target-file", str(target_file)])
+    if result_manifest is not None:
+        options.extend(["--result-manifest", str(result_manifest)])
+    if result_record_output:
+        options.append("--result-record-output")
     options.extend(["-l", opt_level])
     if opt_level == "none":
         logger.warning(
             "WARNING: Chosen optimization level"
             " `none` will generally not result"
-            " in valid QIR."
+            " in valid QIR. Use `minimal` for"
+            " unoptimized but valid QIR."
         )
     if validate_hugr:
         options.append("--validate")
@@ -16,6 +16,12 @@ def hugr_to_qir(  # noqa: PLR0913
     target: str = compile_target_default(),
     opt_level: str = opt_level_default(),
     output_format: OutputFormat = OutputFormat.BASE64,
+    target_triple: str | None = None,
+    target_cpu: str | None = None,
+    target_features: str | None = None,
+    target_file: Path | None = None,
+    result_manifest: Path | None = None,
+    result_record_output: bool = False,
 ) -> str | bytes:
     """A function for converting hugr to qir (llvm bitcode)
 
@@ -29,6 +35,16 @@ def hugr_to_qir(  # noqa: PLR0913
      run hugr-qir --help to see available options and default
     :param output_format: Output format, see OutputFormat enum
      for available options
+    :param target_triple: LLVM target triple, overriding the one
+     implied by `target`
+    :param target_cpu: LLVM target CPU
+    :param target_features: LLVM target features, e.g. "+neon,-crypto"
+    :param target_file: JSON file describing the capabilities and limits
+     of the target, see the README for the available fields
+    :param result_manifest: File to write a JSON manifest of the tags
+     and types of the recorded results to
+    :param result_record_output: Whether to record measurements flowing
+     straight into bool results with `__quantum__rt__result_record_output`
     :returns: QIR corresponding to the HUGR input in format given
      by `output_format`
     """
@@ -54,6 +70,12 @@ def hugr_to_qir(  # noqa: PLR0913
             OutputFormat.LLVM_IR,
             tmp_infile_path,
             tmp_outfile_path,
+            target_triple=target_triple,
+            target_cpu=target_cpu,
+            target_features=target_features,
+            target_file=target_file,
+            result_manifest=result_manifest,
+            result_record_output=result_record_output,
         )
         with Path.open(tmp_outfile_path, "r") as cli_output:
             qir_ir = cli_output.read()
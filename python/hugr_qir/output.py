@@ -1,10 +1,6 @@
-from base64 import b64encode
 from enum import Enum
 
-from llvmlite.binding import (  # type: ignore[import-untyped]
-    create_context,
-    parse_assembly,
-)
+from hugr_qir._hugr_qir import ir_to_base64, ir_to_bitcode
 
 
 class OutputFormat(Enum):
@@ -39,14 +35,9 @@ def ir_string_to_output_format(qir_ir: str, output_format: OutputFormat) -> str
         case OutputFormat.LLVM_IR:
             return qir_ir
         case OutputFormat.BITCODE:
-            ctx = create_context()
-            module = parse_assembly(qir_ir, context=ctx)
-            return module.as_bitcode()
+            return ir_to_bitcode(qir_ir)
         case OutputFormat.BASE64:
-            ctx = create_context()
-            module = parse_assembly(qir_ir, context=ctx)
-            qir_bitcode = module.as_bitcode()
-            return b64encode(qir_bitcode).decode("utf-8")
+            return ir_to_base64(qir_ir)
         case _:
             errmsg = "Unrecognized output format"
             raise ValueError(errmsg)
@@ -118,3 +118,16 @@ def test_guppy_files_options(
         snapshot.assert_match(
             qir, str(Path(snapshot_filename).with_suffix(file_suffix))
         )
+
+
+def test_target_triple_override() -> None:
+    guppy_file = Path(GUPPY_EXAMPLES_DIR_GENERAL) / Path("quantum-conditional-2.py")
+    hugr = guppy_to_hugr_binary(guppy_file)
+    qir = hugr_to_qir(
+        hugr,
+        output_format=OutputFormat.LLVM_IR,
+        target_triple="x86_64-unknown-linux-gnu",
+        target_cpu="x86-64",
+    )
+    assert isinstance(qir, str)
+    assert 'target triple = "x86_64-unknown-linux-gnu"' in qir
@@ -0,0 +1,816 @@
+//! Rust implementations of the QIS and runtime functions emitted by
+//! hugr-qir, backed by a simulator.
+//!
+//! The functions operate on a thread-local [Runtime], installed for the
+//! duration of a shot by [Runtime::run_shot]. Built as a static library, the
+//! crate exports them under their QIR names so that objects compiled with
+//! `--target native --emit-main` can be linked into executables.
+
+pub mod stabilizer;
+pub mod statevector;
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ffi::{CStr, c_char, c_int};
+use std::fmt::Write as _;
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use rand_chacha::ChaCha12Rng;
+
+use stabilizer::Stabilizer;
+use statevector::StateVector;
+
+/// A gate applied by a QIS function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gate {
+    H,
+    X,
+    Y,
+    Z,
+    S,
+    Sdg,
+    T,
+    Tdg,
+    Rx(f64),
+    Ry(f64),
+    Rz(f64),
+    /// `Rz(phi) Rx(theta) Rz(-phi)`, with arguments `(theta, phi)`.
+    PhasedX(f64, f64),
+    CX,
+    CY,
+    CZ,
+    /// `exp(-i theta/2 Z⊗Z)`.
+    Rzz(f64),
+}
+
+impl Gate {
+    pub fn num_qubits(&self) -> usize {
+        match self {
+            Gate::CX | Gate::CY | Gate::CZ | Gate::Rzz(_) => 2,
+            _ => 1,
+        }
+    }
+
+    /// The gate applied by the QIS function `name` with the given angle
+    /// arguments, as implemented by the runtime.
+    pub fn from_qis(name: &str, angles: &[f64]) -> Option<Self> {
+        Some(match (name, angles) {
+            ("__quantum__qis__h__body", []) => Gate::H,
+            ("__quantum__qis__x__body", []) => Gate::X,
+            ("__quantum__qis__y__body", []) => Gate::Y,
+            ("__quantum__qis__z__body", []) => Gate::Z,
+            ("__quantum__qis__s__body", []) => Gate::S,
+            ("__quantum__qis__s__adj", []) => Gate::Sdg,
+            ("__quantum__qis__t__body", []) => Gate::T,
+            ("__quantum__qis__t__adj", []) => Gate::Tdg,
+            ("__quantum__qis__rx__body", &[theta]) => Gate::Rx(theta),
+            ("__quantum__qis__ry__body", &[theta]) => Gate::Ry(theta),
+            ("__quantum__qis__rz__body", &[theta]) => Gate::Rz(theta),
+            ("__quantum__qis__phasedx__body", &[theta, phi]) => Gate::PhasedX(theta, phi),
+            ("__quantum__qis__cx__body", []) => Gate::CX,
+            ("__quantum__qis__cy__body", []) => Gate::CY,
+            ("__quantum__qis__cz__body", []) => Gate::CZ,
+            ("__quantum__qis__rzz__body", &[theta]) => Gate::Rzz(theta),
+            _ => return None,
+        })
+    }
+}
+
+/// A quantum simulator driven by the runtime functions.
+pub trait QuantumBackend {
+    /// Makes sure qubits `0..num_qubits` exist, initialised to `|0>`.
+    fn ensure_qubits(&mut self, num_qubits: usize) -> Result<()>;
+
+    fn apply(&mut self, gate: Gate, qubits: &[usize]) -> Result<()>;
+
+    /// Measures a qubit in the computational basis, collapsing its state.
+    fn measure(&mut self, qubit: usize, rng: &mut StdRng) -> Result<bool>;
+
+    /// Resets a qubit to `|0>`.
+    fn reset(&mut self, qubit: usize, rng: &mut StdRng) -> Result<()> {
+        if self.measure(qubit, rng)? {
+            self.apply(Gate::X, &[qubit])?;
+        }
+        Ok(())
+    }
+}
+
+/// The available simulator backends.
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[non_exhaustive]
+pub enum SimulatorBackend {
+    #[default]
+    StateVector,
+    /// Stabilizer tableau, supporting many qubits but only Clifford gates.
+    Stabilizer,
+}
+
+impl SimulatorBackend {
+    pub fn create(self) -> Box<dyn QuantumBackend> {
+        match self {
+            Self::StateVector => Box::new(StateVector::default()),
+            Self::Stabilizer => Box::new(Stabilizer::default()),
+        }
+    }
+}
+
+impl FromStr for SimulatorBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "state-vector" => Ok(Self::StateVector),
+            "stabilizer" => Ok(Self::Stabilizer),
+            _ => bail!("Unknown backend \"{s}\", expected state-vector or stabilizer"),
+        }
+    }
+}
+
+/// Configuration of a run.
+#[derive(Clone, Debug)]
+pub struct RunConfig {
+    /// Number of shots.
+    pub shots: u64,
+    /// Seed for all randomness of the run; a random seed is used if `None`.
+    pub seed: Option<u64>,
+    pub backend: SimulatorBackend,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            shots: 1,
+            seed: None,
+            backend: SimulatorBackend::default(),
+        }
+    }
+}
+
+impl RunConfig {
+    /// Parses `--shots`, `--seed` and `--backend` options.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "-n" | "--shots" => config.shots = value()?.parse()?,
+                "--seed" => config.seed = Some(value()?.parse()?),
+                "--backend" => config.backend = value()?.parse()?,
+                _ => bail!("Unknown argument {arg}"),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Runs `entry` for each shot, returning the records of all shots in the QIR
+/// labeled output format.
+pub fn run_shots(entry: impl Fn(), config: &RunConfig) -> Result<String> {
+    let mut seeds = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut output = String::from("HEADER\tschema_id\tlabeled\nHEADER\tschema_version\t1.0\n");
+    for shot in 0..config.shots {
+        let runtime = Runtime::new(config.backend.create(), seeds.random()).with_shot(shot);
+        let records = runtime
+            .run_shot(&entry)
+            .map_err(|e| e.context(format!("Shot {shot} failed")))?;
+        let _ = write!(output, "START\n{records}END\t0\n");
+    }
+    Ok(output)
+}
+
+/// Entry point of executables built with `--emit-main`: runs the shots
+/// configured on the command line and prints their output.
+///
+/// # Safety
+///
+/// `argv` must point to `argc` nul-terminated strings.
+#[unsafe(export_name = "hugr_qir_runtime_main")]
+pub unsafe extern "C" fn runtime_main(
+    argc: c_int,
+    argv: *const *const c_char,
+    entry: extern "C" fn(),
+) -> c_int {
+    let args = (1..argc.max(0) as usize).map(|i| {
+        // SAFETY: guaranteed by the caller
+        unsafe { CStr::from_ptr(*argv.add(i)) }
+            .to_string_lossy()
+            .into_owned()
+    });
+    match RunConfig::from_args(args).and_then(|config| run_shots(|| entry(), &config)) {
+        Ok(output) => {
+            print!("{output}");
+            0
+        }
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            1
+        }
+    }
+}
+
+/// Randomness exposed to programs through the random extension.
+pub type ProgramRng = ChaCha12Rng;
+
+/// Moves `rng` forward by `delta` steps, or back if `delta` is negative, as
+/// `random_advance` does. A step is one 32-bit word, which is what
+/// `random_int` and [random_program_float] draw.
+pub fn advance_program_rng(rng: &mut ProgramRng, delta: i64) {
+    rng.set_word_pos(rng.get_word_pos().saturating_add_signed(delta.into()));
+}
+
+/// Draws a float in `[0, 1)` from a single 32-bit word of `rng`, as
+/// `random_float` does.
+pub fn random_program_float(rng: &mut ProgramRng) -> f64 {
+    f64::from(rng.random::<u32>()) / 2f64.powi(32)
+}
+
+/// State of a single shot.
+pub struct Runtime {
+    backend: Box<dyn QuantumBackend>,
+    /// Randomness used by measurements.
+    rng: StdRng,
+    /// Randomness exposed to the program through `___random_*`.
+    program_rng: ProgramRng,
+    /// Index of the shot, returned by `get_current_shot`.
+    shot: u64,
+    results: Vec<bool>,
+    next_qubit: usize,
+    /// Released qubits, reused by later allocations.
+    free_qubits: BTreeSet<usize>,
+    output: String,
+    error: Option<anyhow::Error>,
+}
+
+thread_local! {
+    static RUNTIME: RefCell<Option<Runtime>> = const { RefCell::new(None) };
+}
+
+impl Runtime {
+    pub fn new(backend: Box<dyn QuantumBackend>, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let program_rng = ProgramRng::seed_from_u64(rng.random());
+        Self {
+            backend,
+            rng,
+            program_rng,
+            shot: 0,
+            results: Vec::new(),
+            next_qubit: 0,
+            free_qubits: BTreeSet::new(),
+            output: String::new(),
+            error: None,
+        }
+    }
+
+    /// Sets the index of the shot.
+    pub fn with_shot(mut self, shot: u64) -> Self {
+        self.shot = shot;
+        self
+    }
+
+    /// Installs the runtime for the current thread, runs `shot` and returns
+    /// the records output by the shot.
+    /// A program that calls `abort` unwinds back to here, ending the shot.
+    pub fn run_shot(self, shot: impl FnOnce()) -> Result<String> {
+        RUNTIME.with_borrow_mut(|rt| *rt = Some(self));
+        let unwound = panic::catch_unwind(AssertUnwindSafe(shot));
+        let rt = RUNTIME.with_borrow_mut(Option::take);
+        match unwound {
+            Err(payload) if !payload.is::<Aborted>() => panic::resume_unwind(payload),
+            _ => {}
+        }
+        let rt = rt.ok_or_else(|| anyhow!("runtime was uninstalled during the shot"))?;
+        match rt.error {
+            Some(err) => Err(err),
+            None => Ok(rt.output),
+        }
+    }
+
+    fn qubit(&mut self, qubit: *const Qubit) -> Result<usize> {
+        let qubit = qubit as usize;
+        self.backend.ensure_qubits(qubit + 1)?;
+        self.next_qubit = self.next_qubit.max(qubit + 1);
+        Ok(qubit)
+    }
+
+    fn apply(&mut self, gate: Gate, qubits: &[*const Qubit]) -> Result<()> {
+        let qubits = qubits
+            .iter()
+            .map(|&q| self.qubit(q))
+            .collect::<Result<Vec<_>>>()?;
+        self.backend.apply(gate, &qubits)
+    }
+
+    fn measure(&mut self, qubit: *const Qubit, result: *const QResult) -> Result<()> {
+        let qubit = self.qubit(qubit)?;
+        let result = result as usize;
+        let outcome = self.backend.measure(qubit, &mut self.rng)?;
+        if self.results.len() <= result {
+            self.results.resize(result + 1, false);
+        }
+        self.results[result] = outcome;
+        Ok(())
+    }
+
+    /// Measures a qubit, returning 2 if it leaked. The simulators do not
+    /// model leakage, so this is always the measured bit.
+    fn measure_leaked(&mut self, qubit: *const Qubit) -> Result<u64> {
+        let qubit = self.qubit(qubit)?;
+        Ok(self.backend.measure(qubit, &mut self.rng)?.into())
+    }
+
+    fn reset(&mut self, qubit: *const Qubit) -> Result<()> {
+        let qubit = self.qubit(qubit)?;
+        self.backend.reset(qubit, &mut self.rng)
+    }
+
+    fn read_result(&self, result: *const QResult) -> bool {
+        self.results.get(result as usize).copied().unwrap_or(false)
+    }
+
+    fn record(&mut self, ty: &str, value: impl std::fmt::Display, tag: *const c_char) {
+        let tag = if tag.is_null() {
+            Default::default()
+        } else {
+            // SAFETY: tags are emitted as nul-terminated string constants
+            unsafe { CStr::from_ptr(tag) }.to_string_lossy()
+        };
+        let _ = writeln!(self.output, "OUTPUT\t{ty}\t{value}\t{tag}");
+    }
+}
+
+/// Runs `f` on the installed runtime, keeping the first error so it can be
+/// reported once the shot finishes.
+fn with_runtime<T: Default>(f: impl FnOnce(&mut Runtime) -> Result<T>) -> T {
+    RUNTIME.with_borrow_mut(|rt| {
+        let Some(rt) = rt else {
+            panic!("QIR runtime function called without an installed runtime");
+        };
+        if rt.error.is_some() {
+            return T::default();
+        }
+        f(rt).unwrap_or_else(|err| {
+            rt.error = Some(err);
+            T::default()
+        })
+    })
+}
+
+/// The QIR `%Qubit` type.
+#[repr(C)]
+pub struct Qubit {
+    _private: [u8; 0],
+}
+
+/// The QIR `%Result` type.
+#[repr(C)]
+pub struct QResult {
+    _private: [u8; 0],
+}
+
+macro_rules! qis_gates {
+    ($($name:ident = $symbol:literal => $gate:expr),* $(,)?) => {
+        $(
+            #[unsafe(export_name = $symbol)]
+            extern "C" fn $name(q: *const Qubit) {
+                with_runtime(|rt| rt.apply($gate, &[q]))
+            }
+        )*
+    };
+}
+
+qis_gates! {
+    qis_h = "__quantum__qis__h__body" => Gate::H,
+    qis_x = "__quantum__qis__x__body" => Gate::X,
+    qis_y = "__quantum__qis__y__body" => Gate::Y,
+    qis_z = "__quantum__qis__z__body" => Gate::Z,
+    qis_s = "__quantum__qis__s__body" => Gate::S,
+    qis_s_adj = "__quantum__qis__s__adj" => Gate::Sdg,
+    qis_t = "__quantum__qis__t__body" => Gate::T,
+    qis_t_adj = "__quantum__qis__t__adj" => Gate::Tdg,
+}
+
+macro_rules! qis_rotations {
+    ($($name:ident = $symbol:literal => $gate:path),* $(,)?) => {
+        $(
+            #[unsafe(export_name = $symbol)]
+            extern "C" fn $name(theta: f64, q: *const Qubit) {
+                with_runtime(|rt| rt.apply($gate(theta), &[q]))
+            }
+        )*
+    };
+}
+
+qis_rotations! {
+    qis_rx = "__quantum__qis__rx__body" => Gate::Rx,
+    qis_ry = "__quantum__qis__ry__body" => Gate::Ry,
+    qis_rz = "__quantum__qis__rz__body" => Gate::Rz,
+}
+
+macro_rules! qis_two_qubit_gates {
+    ($($name:ident = $symbol:literal => $gate:expr),* $(,)?) => {
+        $(
+            #[unsafe(export_name = $symbol)]
+            extern "C" fn $name(q0: *const Qubit, q1: *const Qubit) {
+                with_runtime(|rt| rt.apply($gate, &[q0, q1]))
+            }
+        )*
+    };
+}
+
+qis_two_qubit_gates! {
+    qis_cx = "__quantum__qis__cx__body" => Gate::CX,
+    qis_cy = "__quantum__qis__cy__body" => Gate::CY,
+    qis_cz = "__quantum__qis__cz__body" => Gate::CZ,
+}
+
+#[unsafe(export_name = "__quantum__qis__phasedx__body")]
+extern "C" fn qis_phasedx(theta: f64, phi: f64, q: *const Qubit) {
+    with_runtime(|rt| rt.apply(Gate::PhasedX(theta, phi), &[q]))
+}
+
+#[unsafe(export_name = "__quantum__qis__rzz__body")]
+extern "C" fn qis_rzz(theta: f64, q0: *const Qubit, q1: *const Qubit) {
+    with_runtime(|rt| rt.apply(Gate::Rzz(theta), &[q0, q1]))
+}
+
+#[unsafe(export_name = "__quantum__qis__mz__body")]
+extern "C" fn qis_mz(q: *const Qubit, r: *const QResult) {
+    with_runtime(|rt| rt.measure(q, r))
+}
+
+#[unsafe(export_name = "__quantum__qis__mz_leaked__body")]
+extern "C" fn qis_mz_leaked(q: *const Qubit) -> u64 {
+    with_runtime(|rt| rt.measure_leaked(q))
+}
+
+#[unsafe(export_name = "__quantum__qis__reset__body")]
+extern "C" fn qis_reset(q: *const Qubit) {
+    with_runtime(|rt| rt.reset(q))
+}
+
+#[unsafe(export_name = "__quantum__qis__read_result__body")]
+extern "C" fn qis_read_result(r: *const QResult) -> bool {
+    with_runtime(|rt| Ok(rt.read_result(r)))
+}
+
+#[unsafe(export_name = "__quantum__rt__qubit_allocate")]
+extern "C" fn rt_qubit_allocate() -> *const Qubit {
+    with_runtime(|rt| {
+        let qubit = rt.free_qubits.pop_first().unwrap_or(rt.next_qubit);
+        rt.qubit(qubit as *const Qubit)?;
+        Ok(qubit)
+    }) as *const Qubit
+}
+
+#[unsafe(export_name = "__quantum__rt__qubit_release")]
+extern "C" fn rt_qubit_release(q: *const Qubit) {
+    with_runtime(|rt| {
+        rt.reset(q)?;
+        rt.free_qubits.insert(q as usize);
+        Ok(())
+    })
+}
+
+// i1 arguments are passed without an extension attribute, so only the lowest
+// bit is meaningful.
+#[unsafe(export_name = "__quantum__rt__bool_record_output")]
+extern "C" fn rt_bool_record_output(value: u8, tag: *const c_char) {
+    with_runtime(|rt| {
+        rt.record("BOOL", value & 1 == 1, tag);
+        Ok(())
+    })
+}
+
+#[unsafe(export_name = "__quantum__rt__result_record_output")]
+extern "C" fn rt_result_record_output(r: *const QResult, tag: *const c_char) {
+    with_runtime(|rt| {
+        rt.record("RESULT", u8::from(rt.read_result(r)), tag);
+        Ok(())
+    })
+}
+
+#[unsafe(export_name = "__quantum__rt__int_record_output")]
+extern "C" fn rt_int_record_output(value: i64, tag: *const c_char) {
+    with_runtime(|rt| {
+        rt.record("INT", value, tag);
+        Ok(())
+    })
+}
+
+#[unsafe(export_name = "__quantum__rt__double_record_output")]
+extern "C" fn rt_double_record_output(value: f64, tag: *const c_char) {
+    with_runtime(|rt| {
+        rt.record("DOUBLE", format_args!("{value:?}"), tag);
+        Ok(())
+    })
+}
+
+#[unsafe(export_name = "___random_seed")]
+extern "C" fn random_seed(seed: u64) {
+    with_runtime(|rt| {
+        rt.program_rng = ProgramRng::seed_from_u64(seed);
+        Ok(())
+    })
+}
+
+#[unsafe(export_name = "___random_int")]
+extern "C" fn random_int() -> u32 {
+    with_runtime(|rt| Ok(rt.program_rng.random()))
+}
+
+#[unsafe(export_name = "___random_int_bounded")]
+extern "C" fn random_int_bounded(bound: u32) -> u32 {
+    with_runtime(|rt| {
+        anyhow::ensure!(bound > 0, "___random_int_bounded called with bound 0");
+        Ok(rt.program_rng.random_range(0..bound))
+    })
+}
+
+#[unsafe(export_name = "___random_float")]
+extern "C" fn random_float() -> f64 {
+    with_runtime(|rt| Ok(random_program_float(&mut rt.program_rng)))
+}
+
+#[unsafe(export_name = "___random_advance")]
+extern "C" fn random_advance(delta: i64) {
+    with_runtime(|rt| {
+        advance_program_rng(&mut rt.program_rng, delta);
+        Ok(())
+    })
+}
+
+#[unsafe(export_name = "get_current_shot")]
+extern "C" fn get_current_shot() -> u64 {
+    with_runtime(|rt| Ok(rt.shot))
+}
+
+/// Unwind payload of a program that called `abort`.
+struct Aborted;
+
+/// Called in place of libc `abort` when the program panics, failing the shot
+/// rather than the host process. Like `abort` it does not return: it unwinds
+/// through the program back to [Runtime::run_shot]. Not exported, so that
+/// linked executables still abort.
+extern "C-unwind" fn rt_abort() -> ! {
+    with_runtime(|_| -> Result<()> { bail!("Program panicked") });
+    panic::resume_unwind(Box::new(Aborted))
+}
+
+/// Whether `name` is `__quantum__qis__barrier{n}__body` for some `n`.
+///
+/// Barriers are not provided by [runtime_function]: they take one qubit per
+/// operand and do nothing on a simulator, so callers define them as empty
+/// functions with the signature they are called with.
+pub fn is_barrier(name: &str) -> bool {
+    name.strip_prefix("__quantum__qis__barrier")
+        .and_then(|n| n.strip_suffix("__body"))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Returns the address of the implementation of the runtime function `name`,
+/// if there is one.
+pub fn runtime_function(name: &str) -> Option<usize> {
+    let addr = match name {
+        "__quantum__qis__h__body" => qis_h as *const () as usize,
+        "__quantum__qis__x__body" => qis_x as *const () as usize,
+        "__quantum__qis__y__body" => qis_y as *const () as usize,
+        "__quantum__qis__z__body" => qis_z as *const () as usize,
+        "__quantum__qis__s__body" => qis_s as *const () as usize,
+        "__quantum__qis__s__adj" => qis_s_adj as *const () as usize,
+        "__quantum__qis__t__body" => qis_t as *const () as usize,
+        "__quantum__qis__t__adj" => qis_t_adj as *const () as usize,
+        "__quantum__qis__rx__body" => qis_rx as *const () as usize,
+        "__quantum__qis__ry__body" => qis_ry as *const () as usize,
+        "__quantum__qis__rz__body" => qis_rz as *const () as usize,
+        "__quantum__qis__phasedx__body" => qis_phasedx as *const () as usize,
+        "__quantum__qis__cx__body" => qis_cx as *const () as usize,
+        "__quantum__qis__cy__body" => qis_cy as *const () as usize,
+        "__quantum__qis__cz__body" => qis_cz as *const () as usize,
+        "__quantum__qis__rzz__body" => qis_rzz as *const () as usize,
+        "__quantum__qis__mz__body" => qis_mz as *const () as usize,
+        "__quantum__qis__mz_leaked__body" => qis_mz_leaked as *const () as usize,
+        "__quantum__qis__reset__body" => qis_reset as *const () as usize,
+        "__quantum__qis__read_result__body" => qis_read_result as *const () as usize,
+        "__quantum__rt__qubit_allocate" => rt_qubit_allocate as *const () as usize,
+        "__quantum__rt__qubit_release" => rt_qubit_release as *const () as usize,
+        "__quantum__rt__bool_record_output" => rt_bool_record_output as *const () as usize,
+        "__quantum__rt__result_record_output" => rt_result_record_output as *const () as usize,
+        "__quantum__rt__int_record_output" => rt_int_record_output as *const () as usize,
+        "__quantum__rt__double_record_output" => rt_double_record_output as *const () as usize,
+        "___random_seed" => random_seed as *const () as usize,
+        "___random_int" => random_int as *const () as usize,
+        "___random_int_bounded" => random_int_bounded as *const () as usize,
+        "___random_float" => random_float as *const () as usize,
+        "___random_advance" => random_advance as *const () as usize,
+        "get_current_shot" => get_current_shot as *const () as usize,
+        "hugr_qir_runtime_main" => runtime_main as *const () as usize,
+        "abort" => rt_abort as *const () as usize,
+        _ => return None,
+    };
+    Some(addr)
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn qubit(i: usize) -> *const Qubit {
+        i as *const Qubit
+    }
+
+    fn result(i: usize) -> *const QResult {
+        i as *const QResult
+    }
+
+    /// Prepares a Bell pair, records both measurements and a random number.
+    extern "C" fn bell() {
+        qis_h(qubit(0));
+        qis_cx(qubit(0), qubit(1));
+        qis_mz(qubit(0), result(0));
+        qis_mz(qubit(1), result(1));
+        rt_bool_record_output(qis_read_result(result(0)) as u8, c"a".as_ptr());
+        rt_bool_record_output(qis_read_result(result(1)) as u8, c"b".as_ptr());
+        rt_int_record_output(random_int_bounded(10) as i64, c"r".as_ptr());
+    }
+
+    #[rstest]
+    #[case(SimulatorBackend::StateVector)]
+    #[case(SimulatorBackend::Stabilizer)]
+    fn bell_shots(#[case] backend: SimulatorBackend) {
+        let config = RunConfig {
+            shots: 20,
+            seed: Some(1),
+            backend,
+        };
+        let output = run_shots(|| bell(), &config).unwrap();
+        assert!(output.starts_with("HEADER\tschema_id\tlabeled\n"));
+        let shots: Vec<_> = output.split("START\n").skip(1).collect();
+        assert_eq!(shots.len(), 20);
+        for shot in shots {
+            let lines: Vec<_> = shot.lines().collect();
+            assert_eq!(lines[0].replace("\ta", ""), lines[1].replace("\tb", ""));
+            assert!(lines[2].starts_with("OUTPUT\tINT\t"));
+            assert_eq!(lines[3], "END\t0");
+        }
+        assert_eq!(output, run_shots(|| bell(), &config).unwrap());
+    }
+
+    #[test]
+    fn random_advance_moves_by_words() {
+        extern "C" fn draw() {
+            random_seed(7);
+            random_int();
+            random_int();
+            rt_int_record_output(random_int() as i64, c"a".as_ptr());
+            random_seed(7);
+            random_advance(2);
+            rt_int_record_output(random_int() as i64, c"b".as_ptr());
+            random_advance(-1);
+            rt_int_record_output(random_int() as i64, c"c".as_ptr());
+            random_seed(7);
+            for _ in 0..3 {
+                random_float();
+            }
+            random_advance(-1);
+            rt_int_record_output(random_int() as i64, c"d".as_ptr());
+        }
+        let output = run_shots(|| draw(), &RunConfig::default()).unwrap();
+        let values: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("OUTPUT\tINT\t"))
+            .map(|record| record.split('\t').next().unwrap())
+            .collect();
+        // a float is one word, like an int
+        assert_eq!(values.len(), 4);
+        assert!(values.iter().all(|&value| value == values[0]), "{values:?}");
+    }
+
+    #[test]
+    fn current_shot() {
+        extern "C" fn shot() {
+            rt_int_record_output(get_current_shot() as i64, c"shot".as_ptr());
+        }
+        let config = RunConfig {
+            shots: 3,
+            ..Default::default()
+        };
+        let output = run_shots(|| shot(), &config).unwrap();
+        let shots: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("OUTPUT\tINT\t"))
+            .collect();
+        assert_eq!(shots, ["0\tshot", "1\tshot", "2\tshot"]);
+    }
+
+    #[test]
+    fn measure_leaked() {
+        extern "C" fn flip() {
+            qis_x(qubit(0));
+            rt_int_record_output(qis_mz_leaked(qubit(0)) as i64, c"m".as_ptr());
+            rt_int_record_output(qis_mz_leaked(qubit(1)) as i64, c"n".as_ptr());
+        }
+        let output = run_shots(|| flip(), &RunConfig::default()).unwrap();
+        let values: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("OUTPUT\tINT\t"))
+            .collect();
+        assert_eq!(values, ["1\tm", "0\tn"]);
+    }
+
+    #[test]
+    fn released_qubits_are_reused() {
+        extern "C" fn churn() {
+            let kept = rt_qubit_allocate();
+            for _ in 0..100 {
+                let q = rt_qubit_allocate();
+                qis_x(q);
+                rt_qubit_release(q);
+            }
+            let q = rt_qubit_allocate();
+            rt_int_record_output(kept as i64, c"kept".as_ptr());
+            rt_int_record_output(q as i64, c"q".as_ptr());
+        }
+        let output = run_shots(|| churn(), &RunConfig::default()).unwrap();
+        let values: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("OUTPUT\tINT\t"))
+            .collect();
+        assert_eq!(values, ["0\tkept", "1\tq"]);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        extern "C" fn bad() {
+            qis_t(qubit(0));
+            random_int_bounded(0);
+        }
+        let config = RunConfig {
+            backend: SimulatorBackend::Stabilizer,
+            ..Default::default()
+        };
+        let err = run_shots(|| bad(), &config).unwrap_err();
+        // only the first error is kept
+        assert!(format!("{err:#}").contains("non-Clifford"), "{err:#}");
+    }
+
+    #[rstest]
+    #[case("__quantum__qis__s__adj", &[], Some(Gate::Sdg))]
+    #[case("__quantum__qis__phasedx__body", &[0.5, 0.25], Some(Gate::PhasedX(0.5, 0.25)))]
+    #[case("__quantum__qis__rz__body", &[], None)]
+    #[case("__quantum__qis__mz__body", &[], None)]
+    fn from_qis(#[case] name: &str, #[case] angles: &[f64], #[case] expected: Option<Gate>) {
+        assert_eq!(Gate::from_qis(name, angles), expected);
+        if expected.is_some() {
+            assert!(runtime_function(name).is_some());
+        }
+    }
+
+    #[rstest]
+    #[case("__quantum__qis__barrier1__body", true)]
+    #[case("__quantum__qis__barrier12__body", true)]
+    #[case("__quantum__qis__barrier__body", false)]
+    #[case("__quantum__qis__barrierx__body", false)]
+    fn barrier(#[case] name: &str, #[case] expected: bool) {
+        assert_eq!(is_barrier(name), expected);
+        assert!(runtime_function(name).is_none());
+    }
+
+    #[rstest]
+    #[case(&[], 1, None, SimulatorBackend::StateVector)]
+    #[case(&["-n", "5", "--seed", "3", "--backend", "stabilizer"], 5, Some(3), SimulatorBackend::Stabilizer)]
+    fn parse_args(
+        #[case] args: &[&str],
+        #[case] shots: u64,
+        #[case] seed: Option<u64>,
+        #[case] backend: SimulatorBackend,
+    ) {
+        let config = RunConfig::from_args(args.iter().map(|s| s.to_string())).unwrap();
+        assert_eq!(
+            (config.shots, config.seed, config.backend),
+            (shots, seed, backend)
+        );
+    }
+
+    #[rstest]
+    #[case(&["--shots"])]
+    #[case(&["--backend", "tableau"])]
+    #[case(&["--verbose"])]
+    fn invalid_args(#[case] args: &[&str]) {
+        assert!(RunConfig::from_args(args.iter().map(|s| s.to_string())).is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! A stabilizer tableau simulator for Clifford programs, following Aaronson
+//! and Gottesman, "Improved simulation of stabilizer circuits" (2004).
+
+use std::f64::consts::FRAC_PI_2;
+
+use anyhow::{Result, bail, ensure};
+use rand::Rng as _;
+use rand::rngs::StdRng;
+
+use super::{Gate, QuantumBackend};
+
+/// Largest number of qubits the simulator will allocate.
+pub const MAX_QUBITS: usize = 1 << 14;
+
+/// A Pauli operator with sign `(-1)^r`.
+#[derive(Clone, Debug, Default)]
+struct PauliRow {
+    x: Vec<bool>,
+    z: Vec<bool>,
+    r: bool,
+}
+
+impl PauliRow {
+    fn identity(num_qubits: usize) -> Self {
+        Self {
+            x: vec![false; num_qubits],
+            z: vec![false; num_qubits],
+            r: false,
+        }
+    }
+
+    fn single(num_qubits: usize, qubit: usize, x: bool, z: bool) -> Self {
+        let mut row = Self::identity(num_qubits);
+        row.x[qubit] = x;
+        row.z[qubit] = z;
+        row
+    }
+
+    /// Left-multiplies `self` by `other`, tracking the sign.
+    fn multiply(&mut self, other: &PauliRow) {
+        // exponent of i picked up by the product, modulo 4
+        let mut phase: i32 = 2 * (self.r as i32 + other.r as i32);
+        for j in 0..self.x.len() {
+            phase += g(other.x[j], other.z[j], self.x[j], self.z[j]);
+            self.x[j] ^= other.x[j];
+            self.z[j] ^= other.z[j];
+        }
+        debug_assert!(phase.rem_euclid(2) == 0);
+        self.r = phase.rem_euclid(4) == 2;
+    }
+}
+
+/// Exponent of `i` when multiplying the single-qubit Paulis `(x1, z1)` and
+/// `(x2, z2)`.
+fn g(x1: bool, z1: bool, x2: bool, z2: bool) -> i32 {
+    let (x2, z2) = (x2 as i32, z2 as i32);
+    match (x1, z1) {
+        (false, false) => 0,
+        (true, true) => z2 - x2,
+        (true, false) => z2 * (2 * x2 - 1),
+        (false, true) => x2 * (1 - 2 * z2),
+    }
+}
+
+/// The stabilizer state of `n` qubits, stored as `n` destabilizers and `n`
+/// stabilizers.
+#[derive(Clone, Debug, Default)]
+pub struct Stabilizer {
+    destabilizers: Vec<PauliRow>,
+    stabilizers: Vec<PauliRow>,
+}
+
+impl Stabilizer {
+    pub fn num_qubits(&self) -> usize {
+        self.stabilizers.len()
+    }
+
+    fn rows_mut(&mut self) -> impl Iterator<Item = &mut PauliRow> {
+        self.destabilizers
+            .iter_mut()
+            .chain(self.stabilizers.iter_mut())
+    }
+
+    fn h(&mut self, a: usize) {
+        for row in self.rows_mut() {
+            row.r ^= row.x[a] && row.z[a];
+            std::mem::swap(&mut row.x[a], &mut row.z[a]);
+        }
+    }
+
+    fn s(&mut self, a: usize) {
+        for row in self.rows_mut() {
+            row.r ^= row.x[a] && row.z[a];
+            row.z[a] ^= row.x[a];
+        }
+    }
+
+    fn s_pow(&mut self, a: usize, k: u8) {
+        for _ in 0..k % 4 {
+            self.s(a);
+        }
+    }
+
+    fn x(&mut self, a: usize) {
+        self.h(a);
+        self.s_pow(a, 2);
+        self.h(a);
+    }
+
+    fn cx(&mut self, a: usize, b: usize) {
+        for row in self.rows_mut() {
+            row.r ^= row.x[a] && row.z[b] && !(row.x[b] ^ row.z[a]);
+            row.x[b] ^= row.x[a];
+            row.z[a] ^= row.z[b];
+        }
+    }
+
+    /// `Rx(k pi/2)`.
+    fn rx_quarter(&mut self, a: usize, k: u8) {
+        self.h(a);
+        self.s_pow(a, k);
+        self.h(a);
+    }
+
+    /// `Ry(k pi/2)`, as `S Rx S^dagger`.
+    fn ry_quarter(&mut self, a: usize, k: u8) {
+        self.s_pow(a, 3);
+        self.rx_quarter(a, k);
+        self.s(a);
+    }
+}
+
+/// The number of quarter turns `theta` makes, failing if it is not a
+/// multiple of pi/2.
+fn quarter_turns(gate: Gate, theta: f64) -> Result<u8> {
+    let turns = theta / FRAC_PI_2;
+    let rounded = turns.round();
+    if (turns - rounded).abs() > 1e-9 {
+        bail!("Stabilizer simulator does not support non-Clifford gate {gate:?}");
+    }
+    Ok(rounded.rem_euclid(4.0) as u8)
+}
+
+impl QuantumBackend for Stabilizer {
+    fn ensure_qubits(&mut self, num_qubits: usize) -> Result<()> {
+        let old = self.num_qubits();
+        if num_qubits <= old {
+            return Ok(());
+        }
+        ensure!(
+            num_qubits <= MAX_QUBITS,
+            "Stabilizer simulator supports at most {MAX_QUBITS} qubits, program uses {num_qubits}"
+        );
+        for row in self.rows_mut() {
+            row.x.resize(num_qubits, false);
+            row.z.resize(num_qubits, false);
+        }
+        for q in old..num_qubits {
+            self.destabilizers
+                .push(PauliRow::single(num_qubits, q, true, false));
+            self.stabilizers
+                .push(PauliRow::single(num_qubits, q, false, true));
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, gate: Gate, qubits: &[usize]) -> Result<()> {
+        ensure!(
+            qubits.len() == gate.num_qubits(),
+            "{gate:?} applied to {} qubits",
+            qubits.len()
+        );
+        ensure!(
+            qubits.iter().all(|&q| q < self.num_qubits()),
+            "{gate:?} applied to unallocated qubit"
+        );
+        match (gate, qubits) {
+            (Gate::H, &[a]) => self.h(a),
+            (Gate::S, &[a]) => self.s(a),
+            (Gate::Sdg, &[a]) => self.s_pow(a, 3),
+            (Gate::Z, &[a]) => self.s_pow(a, 2),
+            (Gate::X, &[a]) => self.x(a),
+            (Gate::Y, &[a]) => {
+                self.s_pow(a, 2);
+                self.x(a);
+            }
+            (Gate::Rz(theta), &[a]) => self.s_pow(a, quarter_turns(gate, theta)?),
+            (Gate::Rx(theta), &[a]) => self.rx_quarter(a, quarter_turns(gate, theta)?),
+            (Gate::Ry(theta), &[a]) => self.ry_quarter(a, quarter_turns(gate, theta)?),
+            (Gate::PhasedX(theta, phi), &[a]) => {
+                let (theta, phi) = (quarter_turns(gate, theta)?, quarter_turns(gate, phi)?);
+                self.s_pow(a, 4 - phi);
+                self.rx_quarter(a, theta);
+                self.s_pow(a, phi);
+            }
+            (Gate::CX | Gate::CY | Gate::CZ | Gate::Rzz(_), &[a, b]) => {
+                ensure!(a != b, "{gate:?} applied to the same qubit twice");
+                match gate {
+                    Gate::CX => self.cx(a, b),
+                    Gate::CY => {
+                        self.s_pow(b, 3);
+                        self.cx(a, b);
+                        self.s(b);
+                    }
+                    Gate::CZ => {
+                        self.h(b);
+                        self.cx(a, b);
+                        self.h(b);
+                    }
+                    Gate::Rzz(theta) => {
+                        let k = quarter_turns(gate, theta)?;
+                        self.cx(a, b);
+                        self.s_pow(b, k);
+                        self.cx(a, b);
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            (Gate::T | Gate::Tdg, _) => {
+                bail!("Stabilizer simulator does not support non-Clifford gate {gate:?}")
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize, rng: &mut StdRng) -> Result<bool> {
+        let n = self.num_qubits();
+        ensure!(qubit < n, "Measured unallocated qubit");
+        if let Some(p) = self.stabilizers.iter().position(|row| row.x[qubit]) {
+            // random outcome
+            let pivot = self.stabilizers[p].clone();
+            for (i, row) in self.rows_mut().enumerate() {
+                if i != n + p && row.x[qubit] {
+                    row.multiply(&pivot);
+                }
+            }
+            let outcome = rng.random_bool(0.5);
+            self.destabilizers[p] = pivot;
+            self.stabilizers[p] = PauliRow::single(n, qubit, false, true);
+            self.stabilizers[p].r = outcome;
+            Ok(outcome)
+        } else {
+            // deterministic outcome, the sign of +-Z_qubit in the stabilizer
+            // group
+            let mut scratch = PauliRow::identity(n);
+            for (destab, stab) in self.destabilizers.iter().zip(&self.stabilizers) {
+                if destab.x[qubit] {
+                    scratch.multiply(stab);
+                }
+            }
+            Ok(scratch.r)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use rand::SeedableRng as _;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::statevector::StateVector;
+
+    /// Applies `gates` to both simulators and checks that every qubit has
+    /// the same measurement statistics, which for stabilizer states is one
+    /// of 0, 1/2 and 1.
+    fn assert_agrees(num_qubits: usize, gates: &[(Gate, Vec<usize>)]) {
+        let mut stab = Stabilizer::default();
+        let mut sv = StateVector::default();
+        stab.ensure_qubits(num_qubits).unwrap();
+        sv.ensure_qubits(num_qubits).unwrap();
+        for (gate, qubits) in gates {
+            stab.apply(*gate, qubits).unwrap();
+            sv.apply(*gate, qubits).unwrap();
+        }
+        let mut rng = StdRng::seed_from_u64(3);
+        for q in 0..num_qubits {
+            let p1 = sv.probability_one(q);
+            let outcome = stab.clone().measure(q, &mut rng).unwrap();
+            if p1 > 1.0 - 1e-9 {
+                assert!(outcome, "qubit {q}");
+            } else if p1 < 1e-9 {
+                assert!(!outcome, "qubit {q}");
+            } else {
+                assert!((p1 - 0.5).abs() < 1e-9, "qubit {q}: {p1}");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(vec![(Gate::X, vec![0])])]
+    #[case(vec![(Gate::Y, vec![1])])]
+    #[case(vec![(Gate::H, vec![0]), (Gate::S, vec![0]), (Gate::S, vec![0]), (Gate::H, vec![0])])]
+    #[case(vec![(Gate::H, vec![0]), (Gate::Sdg, vec![0]), (Gate::Rx(PI / 2.0), vec![0])])]
+    #[case(vec![(Gate::H, vec![0]), (Gate::CZ, vec![0, 1]), (Gate::H, vec![1])])]
+    #[case(vec![(Gate::H, vec![0]), (Gate::CY, vec![0, 1]), (Gate::Ry(-PI / 2.0), vec![1])])]
+    #[case(vec![(Gate::PhasedX(PI / 2.0, -PI / 2.0), vec![0]), (Gate::Rz(PI), vec![0]), (Gate::H, vec![0])])]
+    #[case(vec![(Gate::PhasedX(PI, PI / 2.0), vec![0]), (Gate::CX, vec![0, 1])])]
+    #[case(vec![(Gate::H, vec![0]), (Gate::H, vec![1]), (Gate::Rzz(PI / 2.0), vec![0, 1]), (Gate::Rx(PI / 2.0), vec![0])])]
+    fn agrees_with_statevector(#[case] gates: Vec<(Gate, Vec<usize>)>) {
+        assert_agrees(2, &gates);
+    }
+
+    #[test]
+    fn large_ghz() {
+        let n = 200;
+        let mut stab = Stabilizer::default();
+        stab.ensure_qubits(n).unwrap();
+        stab.apply(Gate::H, &[0]).unwrap();
+        for q in 1..n {
+            stab.apply(Gate::CX, &[q - 1, q]).unwrap();
+        }
+        let mut rng = StdRng::seed_from_u64(11);
+        let first = stab.measure(0, &mut rng).unwrap();
+        for q in 1..n {
+            assert_eq!(stab.measure(q, &mut rng).unwrap(), first);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let mut stab = Stabilizer::default();
+        stab.ensure_qubits(1).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        stab.apply(Gate::H, &[0]).unwrap();
+        stab.reset(0, &mut rng).unwrap();
+        assert!(!stab.measure(0, &mut rng).unwrap());
+    }
+
+    #[rstest]
+    #[case(Gate::T)]
+    #[case(Gate::Rz(0.1))]
+    #[case(Gate::PhasedX(PI / 2.0, PI / 4.0))]
+    fn non_clifford(#[case] gate: Gate) {
+        let mut stab = Stabilizer::default();
+        stab.ensure_qubits(1).unwrap();
+        let err = stab.apply(gate, &[0]).unwrap_err();
+        assert!(err.to_string().contains("non-Clifford"), "{err}");
+    }
+}
@@ -0,0 +1,305 @@
+//! A dense state-vector simulator.
+
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use anyhow::{Result, ensure};
+use num_complex::Complex64;
+use rand::Rng as _;
+use rand::rngs::StdRng;
+
+use super::{Gate, QuantumBackend};
+
+/// Largest number of qubits the simulator will allocate.
+pub const MAX_QUBITS: usize = 28;
+
+type Matrix2 = [[Complex64; 2]; 2];
+
+/// A state vector over `n` qubits, where qubit `k` is bit `k` of the basis
+/// state index.
+#[derive(Clone, Debug)]
+pub struct StateVector {
+    num_qubits: usize,
+    amplitudes: Vec<Complex64>,
+}
+
+impl Default for StateVector {
+    fn default() -> Self {
+        Self {
+            num_qubits: 0,
+            amplitudes: vec![Complex64::ONE],
+        }
+    }
+}
+
+impl StateVector {
+    pub fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    pub fn amplitudes(&self) -> &[Complex64] {
+        &self.amplitudes
+    }
+
+    /// Probability of measuring `1` on `qubit`.
+    pub fn probability_one(&self, qubit: usize) -> f64 {
+        let mask = 1 << qubit;
+        self.amplitudes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i & mask != 0)
+            .map(|(_, a)| a.norm_sqr())
+            .sum()
+    }
+
+    fn apply_1q(&mut self, m: Matrix2, qubit: usize) {
+        let mask = 1 << qubit;
+        for i in (0..self.amplitudes.len()).filter(|i| i & mask == 0) {
+            let (a0, a1) = (self.amplitudes[i], self.amplitudes[i | mask]);
+            self.amplitudes[i] = m[0][0] * a0 + m[0][1] * a1;
+            self.amplitudes[i | mask] = m[1][0] * a0 + m[1][1] * a1;
+        }
+    }
+
+    /// Applies `m` to `target` where `control` is set.
+    fn apply_controlled(&mut self, m: Matrix2, control: usize, target: usize) {
+        let (cmask, tmask) = (1 << control, 1 << target);
+        for i in (0..self.amplitudes.len()).filter(|i| i & cmask != 0 && i & tmask == 0) {
+            let (a0, a1) = (self.amplitudes[i], self.amplitudes[i | tmask]);
+            self.amplitudes[i] = m[0][0] * a0 + m[0][1] * a1;
+            self.amplitudes[i | tmask] = m[1][0] * a0 + m[1][1] * a1;
+        }
+    }
+
+    fn apply_phase(&mut self, f: impl Fn(usize) -> Complex64) {
+        for (i, a) in self.amplitudes.iter_mut().enumerate() {
+            *a *= f(i);
+        }
+    }
+}
+
+fn c(re: f64, im: f64) -> Complex64 {
+    Complex64::new(re, im)
+}
+
+fn rz(theta: f64) -> Matrix2 {
+    let z = Complex64::ZERO;
+    [
+        [Complex64::from_polar(1.0, -theta / 2.0), z],
+        [z, Complex64::from_polar(1.0, theta / 2.0)],
+    ]
+}
+
+fn rx(theta: f64) -> Matrix2 {
+    let (cos, sin) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    [[c(cos, 0.0), c(0.0, -sin)], [c(0.0, -sin), c(cos, 0.0)]]
+}
+
+fn ry(theta: f64) -> Matrix2 {
+    let (cos, sin) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    [[c(cos, 0.0), c(-sin, 0.0)], [c(sin, 0.0), c(cos, 0.0)]]
+}
+
+fn mul(a: Matrix2, b: Matrix2) -> Matrix2 {
+    let entry = |i: usize, j: usize| a[i][0] * b[0][j] + a[i][1] * b[1][j];
+    [[entry(0, 0), entry(0, 1)], [entry(1, 0), entry(1, 1)]]
+}
+
+fn phase(phi: f64) -> Matrix2 {
+    let z = Complex64::ZERO;
+    [[Complex64::ONE, z], [z, Complex64::from_polar(1.0, phi)]]
+}
+
+const X: Matrix2 = [
+    [Complex64::ZERO, Complex64::ONE],
+    [Complex64::ONE, Complex64::ZERO],
+];
+const Y: Matrix2 = [
+    [Complex64::ZERO, Complex64::new(0.0, -1.0)],
+    [Complex64::I, Complex64::ZERO],
+];
+
+/// The matrix of a single-qubit gate, or the target matrix of a controlled
+/// gate.
+fn matrix(gate: Gate) -> Matrix2 {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+    match gate {
+        Gate::H => [
+            [c(FRAC_1_SQRT_2, 0.0), c(FRAC_1_SQRT_2, 0.0)],
+            [c(FRAC_1_SQRT_2, 0.0), c(-FRAC_1_SQRT_2, 0.0)],
+        ],
+        Gate::X | Gate::CX => X,
+        Gate::Y | Gate::CY => Y,
+        Gate::Z | Gate::CZ => phase(std::f64::consts::PI),
+        Gate::S => phase(FRAC_PI_2),
+        Gate::Sdg => phase(-FRAC_PI_2),
+        Gate::T => phase(FRAC_PI_4),
+        Gate::Tdg => phase(-FRAC_PI_4),
+        Gate::Rx(theta) => rx(theta),
+        Gate::Ry(theta) => ry(theta),
+        Gate::Rz(theta) => rz(theta),
+        Gate::PhasedX(theta, phi) => mul(rz(phi), mul(rx(theta), rz(-phi))),
+        Gate::Rzz(_) => unreachable!("Rzz is applied as a phase"),
+    }
+}
+
+impl QuantumBackend for StateVector {
+    fn ensure_qubits(&mut self, num_qubits: usize) -> Result<()> {
+        if num_qubits <= self.num_qubits {
+            return Ok(());
+        }
+        ensure!(
+            num_qubits <= MAX_QUBITS,
+            "State-vector simulator supports at most {MAX_QUBITS} qubits, program uses {num_qubits}"
+        );
+        // new qubits are in |0>, so existing amplitudes keep their indices
+        self.amplitudes.resize(1 << num_qubits, Complex64::ZERO);
+        self.num_qubits = num_qubits;
+        Ok(())
+    }
+
+    fn apply(&mut self, gate: Gate, qubits: &[usize]) -> Result<()> {
+        ensure!(
+            qubits.len() == gate.num_qubits(),
+            "{gate:?} applied to {} qubits",
+            qubits.len()
+        );
+        ensure!(
+            qubits.iter().all(|&q| q < self.num_qubits),
+            "{gate:?} applied to unallocated qubit"
+        );
+        match (gate, qubits) {
+            (Gate::Rzz(theta), &[q0, q1]) => {
+                ensure!(q0 != q1, "{gate:?} applied to the same qubit twice");
+                let (same, diff) = (
+                    Complex64::from_polar(1.0, -theta / 2.0),
+                    Complex64::from_polar(1.0, theta / 2.0),
+                );
+                self.apply_phase(|i| {
+                    if (i >> q0) & 1 == (i >> q1) & 1 {
+                        same
+                    } else {
+                        diff
+                    }
+                });
+            }
+            (Gate::CX | Gate::CY | Gate::CZ, &[control, target]) => {
+                ensure!(
+                    control != target,
+                    "{gate:?} applied to the same qubit twice"
+                );
+                self.apply_controlled(matrix(gate), control, target);
+            }
+            (_, &[qubit]) => self.apply_1q(matrix(gate), qubit),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize, rng: &mut StdRng) -> Result<bool> {
+        ensure!(qubit < self.num_qubits, "Measured unallocated qubit");
+        let p1 = self.probability_one(qubit).clamp(0.0, 1.0);
+        let outcome = rng.random_bool(p1);
+        let norm = if outcome { p1 } else { 1.0 - p1 }.sqrt();
+        let mask = 1 << qubit;
+        for (i, a) in self.amplitudes.iter_mut().enumerate() {
+            if (i & mask != 0) == outcome {
+                *a /= norm;
+            } else {
+                *a = Complex64::ZERO;
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::PI;
+
+    use rand::SeedableRng as _;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn assert_close(state: &StateVector, expected: &[Complex64]) {
+        assert_eq!(state.amplitudes().len(), expected.len());
+        for (a, e) in state.amplitudes().iter().zip(expected) {
+            assert!(
+                (a - e).norm() < 1e-9,
+                "{:?} != {expected:?}",
+                state.amplitudes()
+            );
+        }
+    }
+
+    #[test]
+    fn bell_state() {
+        let mut state = StateVector::default();
+        state.ensure_qubits(2).unwrap();
+        state.apply(Gate::H, &[0]).unwrap();
+        state.apply(Gate::CX, &[0, 1]).unwrap();
+        let r = c(FRAC_1_SQRT_2, 0.0);
+        assert_close(&state, &[r, Complex64::ZERO, Complex64::ZERO, r]);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let m0 = state.measure(0, &mut rng).unwrap();
+        assert_eq!(state.measure(1, &mut rng).unwrap(), m0);
+    }
+
+    // Gates which are equal up to global phase, as emitted by QSystemPass.
+    #[rstest]
+    #[case(Gate::X, Gate::PhasedX(PI, 0.0))]
+    #[case(Gate::Y, Gate::PhasedX(PI, PI / 2.0))]
+    #[case(Gate::Z, Gate::Rz(PI))]
+    #[case(Gate::T, Gate::Rz(PI / 4.0))]
+    #[case(Gate::Rx(0.3), Gate::PhasedX(0.3, 0.0))]
+    #[case(Gate::Ry(0.3), Gate::PhasedX(0.3, PI / 2.0))]
+    fn equivalent_gates(#[case] a: Gate, #[case] b: Gate) {
+        let prepare = |gate| {
+            let mut state = StateVector::default();
+            state.ensure_qubits(1).unwrap();
+            state.apply(Gate::H, &[0]).unwrap();
+            state.apply(Gate::T, &[0]).unwrap();
+            state.apply(gate, &[0]).unwrap();
+            state
+        };
+        let (a, b) = (prepare(a), prepare(b));
+        let overlap: Complex64 = a
+            .amplitudes()
+            .iter()
+            .zip(b.amplitudes())
+            .map(|(x, y)| x.conj() * y)
+            .sum();
+        assert!((overlap.norm() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rzz_decomposition() {
+        let prepare = |gates: &[(Gate, &[usize])]| {
+            let mut state = StateVector::default();
+            state.ensure_qubits(2).unwrap();
+            state.apply(Gate::H, &[0]).unwrap();
+            state.apply(Gate::Ry(0.7), &[1]).unwrap();
+            for (gate, qubits) in gates {
+                state.apply(*gate, qubits).unwrap();
+            }
+            state
+        };
+        let expected = prepare(&[
+            (Gate::CX, &[0, 1]),
+            (Gate::Rz(0.4), &[1]),
+            (Gate::CX, &[0, 1]),
+        ]);
+        assert_close(
+            &prepare(&[(Gate::Rzz(0.4), &[0, 1])]),
+            expected.amplitudes(),
+        );
+    }
+
+    #[test]
+    fn too_many_qubits() {
+        let mut state = StateVector::default();
+        assert!(state.ensure_qubits(MAX_QUBITS + 1).is_err());
+        assert!(state.apply(Gate::H, &[0]).is_err());
+    }
+}
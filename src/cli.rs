@@ -1,24 +1,34 @@
 use std::{io::Write, path::Path};
 
-use anyhow::Result;
+use anyhow::{Result, ensure};
 use clap::Parser;
 use clap_verbosity_flag::log::Level;
+use hugr::Hugr;
 use hugr::llvm::inkwell;
 use hugr::package::PackageValidationError;
 
 use crate::CompileArgs;
+use crate::difftest::differential_test;
+use crate::output::module_to_base64;
+use crate::results::ResultManifest;
+use crate::run::{RunConfig, run_hugr};
+use crate::runtime::SimulatorBackend;
 
 use clap_verbosity_flag::InfoLevel;
 use clap_verbosity_flag::Verbosity;
 use hugr_cli::hugr_io::HugrInputArgs;
 
-use crate::target::CompileTarget;
+use crate::target::description::TargetDescription;
+use crate::target::{CompileTarget, TargetMachineOptions};
 use hugr_cli::CliError;
 use inkwell::OptimizationLevel;
+use inkwell::targets::FileType;
 /// Main command line interface
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
     /// Hugr input.
     #[command(flatten)]
     pub input_args: HugrInputArgs,
@@ -41,7 +51,12 @@ pub struct Cli {
     #[arg(long, help = "Save transformed HUGR to a file")]
     pub save_hugr: Option<String>,
 
-    #[clap(value_parser, short = 'f', long)]
+    #[clap(
+        value_parser,
+        short = 'f',
+        long,
+        help = "Output format, inferred from the output file extension by default: .ll or .asm for LLVM IR, .b64 for base64, .s for assembly, .o or .obj for an object file, and bitcode otherwise"
+    )]
     pub output_format: Option<OutputFormat>,
 
     #[clap(long, help = "Validate hugr before and after each pass")]
@@ -53,19 +68,186 @@ pub struct Cli {
     #[arg(value_parser, short, long, help = "Target machine")]
     pub target: Option<CompileTarget>,
 
+    #[arg(
+        long,
+        help = "LLVM target triple, overriding the one implied by --target"
+    )]
+    pub target_triple: Option<String>,
+
+    #[arg(long, help = "LLVM target CPU")]
+    pub target_cpu: Option<String>,
+
+    #[arg(long, help = "LLVM target features, e.g. +neon,-crypto")]
+    pub target_features: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = |path: &str| TargetDescription::from_file(path),
+        help = "JSON file describing the capabilities and limits of the target"
+    )]
+    pub target_file: Option<TargetDescription>,
+
     #[arg(value_parser, short = 'l', long, help = "LLVM optimization level")]
     pub optimization_level: Option<CliOptimizationLevel>,
+
+    #[arg(
+        long,
+        help = "Add a C main function, for linking with the hugr-qir-runtime library"
+    )]
+    pub emit_main: bool,
+
+    #[arg(
+        value_parser,
+        long,
+        help = "Write a JSON manifest of the tags and types of the recorded results to this file"
+    )]
+    pub result_manifest: Option<clio::OutputPath>,
+
+    #[arg(
+        long,
+        help = "Record measurements flowing straight into bool results with __quantum__rt__result_record_output"
+    )]
+    pub result_record_output: bool,
 }
 
-#[derive(clap::ValueEnum, Clone, Debug, Copy)]
+#[derive(clap::Subcommand, Debug)]
+pub enum CliCommand {
+    /// Compile a HUGR for the host and run it on a local simulator.
+    Run(RunArgs),
+    /// Compare the results of a compiled HUGR with those of the reference
+    /// interpreter.
+    Difftest(DiffTestArgs),
+}
+
+/// Arguments of the `run` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+    /// Hugr input.
+    #[command(flatten)]
+    pub input_args: HugrInputArgs,
+    /// Verbosity.
+    #[command(flatten)]
+    pub verbose: Verbosity<InfoLevel>,
+
+    #[clap(
+        value_parser,
+        default_value = "-",
+        short,
+        long,
+        help = "Output file for the shot results, or - for stdout"
+    )]
+    pub output: clio::Output,
+
+    #[arg(short = 'n', long, default_value_t = 1, help = "Number of shots")]
+    pub shots: u64,
+
+    #[arg(long, help = "Seed for the simulator, random if not given")]
+    pub seed: Option<u64>,
+
+    #[arg(
+        value_parser,
+        long,
+        default_value = "state-vector",
+        help = "Simulator backend"
+    )]
+    pub backend: SimulatorBackend,
+
+    #[arg(value_parser, short = 'l', long, help = "LLVM optimization level")]
+    pub optimization_level: Option<CliOptimizationLevel>,
+}
+
+impl RunArgs {
+    pub fn verbosity(&self, level: Level) -> bool {
+        self.verbose.log_level_filter() >= level
+    }
+
+    pub fn compile_args(&self) -> CompileArgs {
+        let default_args = CompileArgs::default();
+        CompileArgs {
+            verbosity: self.verbose.log_level(),
+            opt_level: self.optimization_level.unwrap_or(default_args.opt_level),
+            ..default_args
+        }
+    }
+
+    pub fn run_config(&self) -> RunConfig {
+        RunConfig {
+            shots: self.shots,
+            seed: self.seed,
+            backend: self.backend,
+        }
+    }
+
+    /// Runs the HUGR and writes the shot results to the output.
+    pub fn run(&mut self) -> Result<()> {
+        let mut hugr = load_hugr(&mut self.input_args)?;
+        let output = run_hugr(&mut hugr, &self.compile_args(), &self.run_config())?;
+        self.output.write_all(output.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Arguments of the `difftest` subcommand.
+#[derive(clap::Args, Debug)]
+pub struct DiffTestArgs {
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[arg(
+        long,
+        default_value_t = 0.1,
+        help = "Largest total variation distance accepted between the distributions of a result"
+    )]
+    pub tolerance: f64,
+}
+
+impl DiffTestArgs {
+    /// Runs the differential test and writes the report to the output,
+    /// failing if the distributions differ by more than the tolerance.
+    pub fn run(&mut self) -> Result<()> {
+        let hugr = load_hugr(&mut self.run.input_args)?;
+        let report = differential_test(&hugr, &self.run.compile_args(), &self.run.run_config())?;
+        writeln!(self.run.output, "{report}")?;
+        ensure!(
+            report.distance() <= self.tolerance,
+            "Compiled program disagrees with the reference interpreter: \
+             total variation distance {:.3} of a result exceeds {}",
+            report.distance(),
+            self.tolerance
+        );
+        Ok(())
+    }
+}
+
+/// Loads and validates the HUGR to compile.
+fn load_hugr(input_args: &mut HugrInputArgs) -> Result<Hugr> {
+    let (desc, package) = input_args.get_described_package()?;
+    let generator = desc.generator();
+
+    package
+        .validate()
+        .map_err(|val_err| Cli::wrap_generator(generator, val_err))?;
+    Ok(package.modules[0].clone())
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, Copy, PartialEq, Eq)]
 pub enum OutputFormat {
     Bitcode,
     LlvmIr,
+    /// Base64 encoded bitcode.
+    Base64,
+    /// Object file for the target machine.
+    Object,
+    /// Assembly for the target machine.
+    Assembly,
 }
 
 #[derive(clap::ValueEnum, Clone, Debug, Copy)]
 pub enum CliOptimizationLevel {
     None,
+    /// Run only the passes required to produce valid QIR, keeping the output
+    /// close to the unoptimised module.
+    Minimal,
     Less,
     Default,
     Aggressive,
@@ -74,7 +256,7 @@ pub enum CliOptimizationLevel {
 impl From<CliOptimizationLevel> for OptimizationLevel {
     fn from(cli_level: CliOptimizationLevel) -> Self {
         match cli_level {
-            CliOptimizationLevel::None => OptimizationLevel::None,
+            CliOptimizationLevel::None | CliOptimizationLevel::Minimal => OptimizationLevel::None,
             CliOptimizationLevel::Less => OptimizationLevel::Less,
             CliOptimizationLevel::Default => OptimizationLevel::Default,
             CliOptimizationLevel::Aggressive => OptimizationLevel::Aggressive,
@@ -91,16 +273,22 @@ impl Cli {
         &mut self,
         context: &'c inkwell::context::Context,
     ) -> Result<inkwell::module::Module<'c>> {
-        let (desc, package) = self.input_args.get_described_package()?;
-        let generator = desc.generator();
-
-        package
-            .validate()
-            .map_err(|val_err| Self::wrap_generator(generator, val_err))?;
-        let mut hugr = package.modules[0].clone();
+        let mut hugr = load_hugr(&mut self.input_args)?;
+        // collected before compilation transforms the HUGR, so that the
+        // nodes refer to the input, but only written once it succeeds
+        let manifest = match &self.result_manifest {
+            Some(_) => Some(ResultManifest::from_entry_point(&hugr)?),
+            None => None,
+        };
 
         let args = self.compile_args();
-        args.compile(&mut hugr, context)
+        let module = args.compile(&mut hugr, context)?;
+
+        if let (Some(path), Some(manifest)) = (&self.result_manifest, manifest) {
+            let mut output = path.clone().create()?;
+            writeln!(output, "{}", manifest.to_json()?)?;
+        }
+        Ok(module)
     }
 
     pub fn write_module(&mut self, module: &inkwell::module::Module<'_>) -> Result<()> {
@@ -113,6 +301,22 @@ impl Cli {
                 let str = module.print_to_string();
                 self.output.write_all(str.to_bytes())?;
             }
+            OutputFormat::Base64 => {
+                let str = module_to_base64(module);
+                self.output.write_all(str.as_bytes())?;
+            }
+            OutputFormat::Object => {
+                let memory = self
+                    .compile_args()
+                    .write_to_memory_buffer(module, FileType::Object)?;
+                self.output.write_all(memory.as_slice())?;
+            }
+            OutputFormat::Assembly => {
+                let memory = self
+                    .compile_args()
+                    .write_to_memory_buffer(module, FileType::Assembly)?;
+                self.output.write_all(memory.as_slice())?;
+            }
         }
         Ok(())
     }
@@ -129,6 +333,12 @@ impl Cli {
             {
                 if ["ll", "asm"].contains(&extension) {
                     return OutputFormat::LlvmIr;
+                } else if extension == "b64" {
+                    return OutputFormat::Base64;
+                } else if extension == "s" {
+                    return OutputFormat::Assembly;
+                } else if ["o", "obj"].contains(&extension) {
+                    return OutputFormat::Object;
                 }
             }
             OutputFormat::Bitcode
@@ -143,7 +353,15 @@ impl Cli {
             validate: self.validate,
             qsystem_pass: self.qsystem_pass,
             target: self.target.unwrap_or(default_args.target),
+            target_options: TargetMachineOptions {
+                triple: self.target_triple.clone(),
+                cpu: self.target_cpu.clone(),
+                features: self.target_features.clone(),
+            },
+            target_description: self.target_file.clone(),
             opt_level: self.optimization_level.unwrap_or(default_args.opt_level),
+            emit_main: self.emit_main,
+            result_record_output: self.result_record_output,
         }
     }
 
@@ -159,3 +377,96 @@ impl Cli {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use hugr::HugrView as _;
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::envelope::EnvelopeConfig;
+    use hugr::ops::Value;
+    use hugr::types::Signature;
+    use rstest::rstest;
+    use tempfile::TempDir;
+    use tket_qsystem::extension::result::ResultOp;
+
+    use super::*;
+
+    /// A module whose `main` records a bool as `tag`, next to a dead function
+    /// recording one as "dead".
+    fn write_hugr(dir: &TempDir, tag: &str) -> PathBuf {
+        let mut module = ModuleBuilder::new();
+        for (name, tag) in [("main", tag), ("unused", "dead")] {
+            let mut func = module
+                .define_function(name, Signature::new_endo(vec![]))
+                .unwrap();
+            let bit = func.add_load_value(Value::true_val());
+            func.add_dataflow_op(ResultOp::new_bool(tag), [bit])
+                .unwrap();
+            func.finish_with_outputs([]).unwrap();
+        }
+        let hugr = module.finish_hugr().unwrap();
+        let path = dir.path().join("program.hugr");
+        let file = std::fs::File::create(&path).unwrap();
+        hugr.store_with_exts(file, EnvelopeConfig::text(), hugr.extensions())
+            .unwrap();
+        path
+    }
+
+    #[rstest]
+    #[case::compiled("c", true)]
+    #[case::failed("c-0", false)]
+    fn result_manifest(#[case] tag: &str, #[case] compiles: bool) {
+        let dir = TempDir::new().unwrap();
+        let input = write_hugr(&dir, tag);
+        let target = dir.path().join("target.json");
+        std::fs::write(&target, r#"{"result_tag_chars": "a-z"}"#).unwrap();
+        let manifest = dir.path().join("results.json");
+        let mut cli = Cli::try_parse_from([
+            "hugr-qir".as_ref(),
+            input.as_os_str(),
+            "-o".as_ref(),
+            dir.path().join("program.ll").as_os_str(),
+            "--target-file".as_ref(),
+            target.as_os_str(),
+            "--result-manifest".as_ref(),
+            manifest.as_os_str(),
+        ])
+        .unwrap();
+        let context = inkwell::context::Context::create();
+        assert_eq!(cli.run(&context).is_ok(), compiles);
+        if !compiles {
+            assert!(!manifest.exists());
+            return;
+        }
+        let manifest = ResultManifest::from_json(&std::fs::read_to_string(manifest).unwrap());
+        let tags: Vec<_> = manifest
+            .unwrap()
+            .results
+            .into_iter()
+            .map(|entry| entry.tag)
+            .collect();
+        assert_eq!(tags, [tag]);
+    }
+
+    #[rstest]
+    #[case("program.ll", OutputFormat::LlvmIr)]
+    #[case("program.asm", OutputFormat::LlvmIr)]
+    #[case("program.s", OutputFormat::Assembly)]
+    #[case("program.obj", OutputFormat::Object)]
+    #[case("program.b64", OutputFormat::Base64)]
+    #[case("program.bc", OutputFormat::Bitcode)]
+    fn output_format_from_extension(#[case] file: &str, #[case] expected: OutputFormat) {
+        let dir = TempDir::new().unwrap();
+        let input = write_hugr(&dir, "c");
+        let cli = Cli::try_parse_from([
+            "hugr-qir".as_ref(),
+            input.as_os_str(),
+            "-o".as_ref(),
+            dir.path().join(file).as_os_str(),
+        ])
+        .unwrap();
+        assert_eq!(cli.output_format(), expected);
+    }
+}
@@ -0,0 +1,216 @@
+//! Differential testing of the compiler against the
+//! [reference interpreter](crate::reference).
+//!
+//! A HUGR is run on the reference interpreter and, compiled for the host, on
+//! the local runtime, and the distributions of each result recorded by the
+//! two are compared. This catches miscompilations such as wrong angle units
+//! or swapped gate operands. Randomness seeded by the program through
+//! `NewRNGContext` is reproduced exactly on both sides.
+//!
+//! Comparing the marginal distribution of each result rather than the joint
+//! distribution keeps the sampling error independent of the number of
+//! results, so a fixed tolerance works for programs recording many results.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use anyhow::{Context as _, Result};
+use hugr::Hugr;
+
+use crate::CompileArgs;
+use crate::reference::run_reference;
+use crate::results::{ResultManifest, Shot, ShotOutput};
+use crate::run::{RunConfig, run_hugr};
+
+/// The number of shots recording each outcome of a result, where an outcome
+/// is the sequence of values recorded by a shot under the result's tag.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Distribution {
+    counts: BTreeMap<String, u64>,
+    shots: u64,
+}
+
+impl Distribution {
+    /// The distribution of the values recorded under `tag`.
+    pub fn from_output(output: &ShotOutput, tag: &str) -> Self {
+        let mut dist = Self::default();
+        for shot in &output.shots {
+            *dist.counts.entry(outcome(shot, tag)).or_default() += 1;
+            dist.shots += 1;
+        }
+        dist
+    }
+
+    pub fn probability(&self, outcome: &str) -> f64 {
+        match self.shots {
+            0 => 0.0,
+            shots => self.counts.get(outcome).copied().unwrap_or(0) as f64 / shots as f64,
+        }
+    }
+
+    /// The total variation distance to `other`.
+    pub fn distance(&self, other: &Self) -> f64 {
+        let outcomes: BTreeSet<_> = self.counts.keys().chain(other.counts.keys()).collect();
+        outcomes
+            .into_iter()
+            .map(|o| (self.probability(o) - other.probability(o)).abs())
+            .sum::<f64>()
+            / 2.0
+    }
+}
+
+fn outcome(shot: &Shot, tag: &str) -> String {
+    let values: Vec<_> = shot
+        .results
+        .iter()
+        .filter(|(t, _)| t == tag)
+        .map(|(_, value)| value.to_string())
+        .collect();
+    values.join(",")
+}
+
+/// The distributions of a result in the reference interpreter and the
+/// compiled program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultDiff {
+    pub reference: Distribution,
+    pub compiled: Distribution,
+}
+
+impl ResultDiff {
+    /// The total variation distance between the two distributions.
+    pub fn distance(&self) -> f64 {
+        self.reference.distance(&self.compiled)
+    }
+}
+
+/// The distributions of each result, by tag.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiffReport {
+    pub results: BTreeMap<String, ResultDiff>,
+}
+
+impl DiffReport {
+    /// The largest total variation distance between the distributions of a
+    /// result.
+    pub fn distance(&self) -> f64 {
+        self.results
+            .values()
+            .map(ResultDiff::distance)
+            .fold(0.0, f64::max)
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "reference\tcompiled\tresult\toutcome")?;
+        for (tag, diff) in &self.results {
+            let outcomes: BTreeSet<_> = diff
+                .reference
+                .counts
+                .keys()
+                .chain(diff.compiled.counts.keys())
+                .collect();
+            for outcome in outcomes {
+                writeln!(
+                    f,
+                    "{:.3}\t\t{:.3}\t\t{tag}\t{outcome}",
+                    diff.reference.probability(outcome),
+                    diff.compiled.probability(outcome)
+                )?;
+            }
+        }
+        write!(
+            f,
+            "largest total variation distance of a result: {:.3}",
+            self.distance()
+        )
+    }
+}
+
+/// Runs `hugr` on the reference interpreter and compiled with `args`, with
+/// the same configuration.
+pub fn differential_test(
+    hugr: &Hugr,
+    args: &CompileArgs,
+    config: &RunConfig,
+) -> Result<DiffReport> {
+    let manifest = ResultManifest::from_hugr(hugr)?;
+    let reference = run_reference(hugr, config)?;
+    let output = run_hugr(&mut hugr.clone(), args, config)?;
+    let mut compiled = ShotOutput::parse(&output)?;
+    compiled
+        .check(&manifest)
+        .context("Compiled program recorded unexpected results")?;
+    let tags: BTreeSet<_> = [&reference, &compiled]
+        .into_iter()
+        .flat_map(|output| &output.shots)
+        .flat_map(|shot| shot.results.iter().map(|(tag, _)| tag.clone()))
+        .collect();
+    let results = tags
+        .into_iter()
+        .map(|tag| {
+            let diff = ResultDiff {
+                reference: Distribution::from_output(&reference, &tag),
+                compiled: Distribution::from_output(&compiled, &tag),
+            };
+            (tag, diff)
+        })
+        .collect();
+    Ok(DiffReport { results })
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use tket::TketOp;
+
+    use super::*;
+    use crate::runtime::SimulatorBackend;
+    use crate::test::{circuit_hugr, loop_hugr};
+
+    fn config(shots: u64) -> RunConfig {
+        RunConfig {
+            shots,
+            seed: Some(7),
+            backend: SimulatorBackend::StateVector,
+        }
+    }
+
+    #[rstest]
+    #[case::ry(1, vec![(TketOp::Ry, vec![0], Some(0.25))])]
+    #[case::rx_third(1, vec![(TketOp::Rx, vec![0], Some(1.0 / 3.0))])]
+    #[case::rz(1, vec![(TketOp::H, vec![0], None), (TketOp::Rz, vec![0], Some(0.3)), (TketOp::H, vec![0], None)])]
+    #[case::operand_order(2, vec![(TketOp::X, vec![0], None), (TketOp::CX, vec![0, 1], None), (TketOp::Ry, vec![1], Some(0.4))])]
+    #[case::crz(2, vec![(TketOp::X, vec![0], None), (TketOp::H, vec![1], None), (TketOp::CRz, vec![0, 1], Some(0.5)), (TketOp::H, vec![1], None)])]
+    #[case::many_results(8, (0..8).map(|q| (TketOp::H, vec![q], None)).collect())]
+    #[case::toffoli(3, vec![(TketOp::H, vec![0], None), (TketOp::X, vec![1], None), (TketOp::Toffoli, vec![0, 1, 2], None)])]
+    fn agrees(#[case] num_qubits: usize, #[case] ops: Vec<(TketOp, Vec<usize>, Option<f64>)>) {
+        let hugr = circuit_hugr(num_qubits, &ops);
+        let report = differential_test(&hugr, &CompileArgs::default(), &config(1000)).unwrap();
+        assert!(report.distance() < 0.1, "{report}");
+    }
+
+    #[test]
+    fn seeded_randomness() {
+        let report = differential_test(&loop_hugr(), &CompileArgs::default(), &config(5)).unwrap();
+        assert_eq!(report.distance(), 0.0, "{report}");
+        for diff in report.results.values() {
+            assert_eq!(diff.compiled.counts.len(), 1, "{report}");
+        }
+    }
+
+    #[test]
+    fn detects_differences() {
+        let run = |ops: &[(TketOp, Vec<usize>, Option<f64>)]| {
+            let output = run_reference(&circuit_hugr(1, ops), &config(100)).unwrap();
+            Distribution::from_output(&output, "0")
+        };
+        let zero = run(&[]);
+        let one = run(&[(TketOp::X, vec![0], None)]);
+        let plus = run(&[(TketOp::H, vec![0], None)]);
+        assert_eq!(zero.distance(&one), 1.0);
+        assert_eq!(zero.distance(&zero), 0.0);
+        assert!((zero.distance(&plus) - plus.probability("true")).abs() < 1e-9);
+    }
+}
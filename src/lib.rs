@@ -1,11 +1,14 @@
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 
 use crate::inkwell::passes::PassBuilderOptions;
-use crate::inkwell::values::CallSiteValue;
 use crate::inkwell::values::PointerValue;
+use crate::inkwell::values::{BasicValue as _, BasicValueEnum, CallSiteValue, UnnamedAddress};
 use crate::inline::inline;
 use anyhow::Result;
 use anyhow::anyhow;
+use anyhow::bail;
 use clap_verbosity_flag::log::Level;
 use hugr::HugrView;
 use hugr::algorithms::{ComposablePass, RemoveDeadFuncsPass};
@@ -15,16 +18,36 @@ use hugr::llvm::utils::fat::FatExt;
 use hugr::llvm::{CodegenExtsBuilder, inkwell};
 use hugr::{Hugr, Node};
 use hugr_llvm::inkwell::attributes::AttributeLoc;
+use inkwell::AddressSpace;
+use inkwell::attributes::Attribute;
+use inkwell::basic_block::BasicBlock;
 use inkwell::context::Context;
+use inkwell::intrinsics::Intrinsic;
+use inkwell::memory_buffer::MemoryBuffer;
 use inkwell::module::{Linkage, Module};
-use qir::{QirCodegenExtension, QirPreludeCodegen};
+use inkwell::targets::{FileType, TargetMachine};
+use inkwell::values::{AnyValueEnum, InstructionOpcode, InstructionValue};
+use qir::{QirCodegenExtension, QirPreludeCodegen, reset_live_qubits};
+use results::ResultManifest;
 use rotation::RotationCodegenExtension;
-use target::CompileTarget;
+use target::description::TargetDescription;
+use target::{CompileTarget, TargetMachineOptions};
 pub mod cli;
+pub mod difftest;
+pub mod output;
 pub mod qir;
+pub mod reference;
+pub mod results;
+pub mod run;
+pub use hugr_qir_runtime as runtime;
 pub mod target;
+pub mod unitary;
 use crate::cli::CliOptimizationLevel;
+use crate::qir::array_ext::QirArrayCodegen;
+use crate::qir::bool_ext::BoolCodegenExtension;
+use crate::qir::qsystem_ext::lazy_measurements_run_once;
 use crate::qir::random_ext::RandomCodegenExtension;
+use crate::qir::wasm_ext::WasmCodegenExtension;
 use itertools::Itertools;
 
 #[cfg(feature = "py")]
@@ -33,6 +56,7 @@ mod py;
 // TODO this was copy pasted, ideally it would live in tket2-hseries
 pub mod rotation;
 
+#[derive(Clone)]
 #[non_exhaustive]
 pub struct CompileArgs {
     pub debug: u8,
@@ -42,7 +66,16 @@ pub struct CompileArgs {
     pub validate: bool,
     pub qsystem_pass: bool,
     pub target: CompileTarget,
+    pub target_options: TargetMachineOptions,
+    /// Capabilities and limits of the target, enforced during compilation.
+    pub target_description: Option<TargetDescription>,
     pub opt_level: CliOptimizationLevel,
+    /// Add a C `main` function running the entry point with the
+    /// hugr-qir-runtime library.
+    pub emit_main: bool,
+    /// Record measurements flowing straight into bool results with
+    /// `__quantum__rt__result_record_output`.
+    pub result_record_output: bool,
 }
 
 impl Default for CompileArgs {
@@ -53,14 +86,40 @@ impl Default for CompileArgs {
             validate: false,
             qsystem_pass: true,
             target: CompileTarget::QuantinuumHardware,
+            target_options: TargetMachineOptions::default(),
+            target_description: None,
             opt_level: CliOptimizationLevel::Aggressive,
+            emit_main: false,
+            result_record_output: false,
         }
     }
 }
 
 impl CompileArgs {
-    pub fn codegen_extensions(&self) -> CodegenExtsMap<'static, Hugr> {
+    pub fn codegen_extensions(&self, hugr: &Hugr) -> CodegenExtsMap<'static, Hugr> {
         let pcg = QirPreludeCodegen;
+        let mut qir =
+            QirCodegenExtension::default().with_lazy_reads(lazy_measurements_run_once(hugr));
+        let mut random = RandomCodegenExtension::default();
+        let mut wasm = WasmCodegenExtension::default();
+        if let Some(desc) = &self.target_description {
+            if let Some(func) = &desc.current_shot_function {
+                qir = qir.with_current_shot_func(func);
+            }
+            qir = qir.with_barriers(!desc.drop_barriers);
+            if desc.check_qubit_capacity
+                && let Some(max_qubits) = desc.max_qubits
+            {
+                qir = qir.with_qubit_capacity(max_qubits);
+            }
+            if let Some(prefix) = &desc.random_prefix {
+                random = random.with_prefix(prefix);
+            }
+            random = random.with_stateful_context(desc.stateful_rng_context);
+            if let Some(desc_wasm) = &desc.wasm {
+                wasm = desc_wasm.clone();
+            }
+        }
 
         CodegenExtsBuilder::default()
             .add_prelude_extensions(pcg.clone())
@@ -68,9 +127,13 @@ impl CompileArgs {
             .add_float_extensions()
             .add_conversion_extensions()
             .add_logic_extensions()
+            .add_extension(BoolCodegenExtension)
+            .add_array_extensions(QirArrayCodegen)
+            .add_borrow_array_extensions(QirArrayCodegen)
             .add_extension(RotationCodegenExtension::new(QirPreludeCodegen))
-            .add_extension(QirCodegenExtension)
-            .add_extension(RandomCodegenExtension)
+            .add_extension(qir)
+            .add_extension(random)
+            .add_extension(wasm)
             .finish()
     }
 
@@ -84,6 +147,12 @@ impl CompileArgs {
         if self.validate {
             hugr.validate()?;
         }
+        for (tag, types) in ResultManifest::from_hugr(hugr)?.conflicting_tags() {
+            self.warn(format_args!(
+                "Result tag \"{tag}\" is recorded with conflicting types {}",
+                types.join(", ")
+            ));
+        }
         if self.qsystem_pass {
             let pass = tket_qsystem::QSystemPass::default();
             pass.run(hugr)?;
@@ -93,9 +162,20 @@ impl CompileArgs {
         }
         self.inline_calls(hugr)?;
         self.remove_dead_functions(hugr)?;
+        if let Some(desc) = &self.target_description {
+            desc.check_classical_ops(hugr)?;
+            desc.check_result_tags(hugr)?;
+        }
         Ok(())
     }
 
+    /// Prints a warning, unless warnings are silenced by the verbosity.
+    pub fn warn(&self, msg: impl std::fmt::Display) {
+        if self.verbosity.is_some_and(|level| level >= Level::Warn) {
+            eprintln!("Warning: {msg}");
+        }
+    }
+
     pub fn inline_calls(&self, hugr: &mut Hugr) -> Result<()> {
         let all_calls: Vec<_> = hugr
             .nodes()
@@ -118,17 +198,48 @@ impl CompileArgs {
         Ok(())
     }
 
-    /// Optimize the module using LLVM passes
-    fn optimize_module_llvm(&self, module: &Module) -> Result<()> {
+    /// The target machine options, falling back to those of the target
+    /// description where not given explicitly.
+    pub fn target_machine_options(&self) -> TargetMachineOptions {
+        let desc = self.target_description.clone().unwrap_or_default();
+        let options = self.target_options.clone();
+        TargetMachineOptions {
+            triple: options.triple.or(desc.triple),
+            cpu: options.cpu.or(desc.cpu),
+            features: options.features.or(desc.features),
+        }
+    }
+
+    /// Initialise and create the LLVM target machine for this compilation.
+    pub fn target_machine(&self) -> Result<TargetMachine> {
         self.target.initialise();
+        self.target
+            .machine(&self.target_machine_options(), self.opt_level.into())
+    }
 
-        let ctm = self.target.machine(self.opt_level.into());
+    /// Emit the module as an object file or assembly for the target machine.
+    pub fn write_to_memory_buffer(
+        &self,
+        module: &Module,
+        file_type: FileType,
+    ) -> Result<MemoryBuffer> {
+        self.target_machine()?
+            .write_to_memory_buffer(module, file_type)
+            .map_err(|e| anyhow!("Failed to emit {file_type:?}: {e}"))
+    }
+
+    /// Optimize the module using LLVM passes
+    fn optimize_module_llvm(&self, module: &Module) -> Result<()> {
+        let ctm = self.target_machine()?;
 
         module.set_triple(&ctm.get_triple());
         module.set_data_layout(&ctm.get_target_data().get_data_layout());
 
         let mut opt_str = String::from(match self.opt_level {
             CliOptimizationLevel::None => "default<O0>",
+            // only what is needed to get rid of allocas and fold constant
+            // qubit/result pointers
+            CliOptimizationLevel::Minimal => "mem2reg,sccp,instsimplify,simplifycfg,dce",
             CliOptimizationLevel::Less => "default<O1>",
             CliOptimizationLevel::Default => "default<O2>",
             CliOptimizationLevel::Aggressive => "default<O3>",
@@ -139,21 +250,35 @@ impl CompileArgs {
     }
 
     pub fn hugr_to_llvm<'c>(&self, hugr: &Hugr, context: &'c Context) -> Result<Module<'c>> {
-        let extensions = self.codegen_extensions().into();
+        let extensions = self.codegen_extensions(hugr).into();
         let namer = Rc::new(Namer::new("__hugr__.", true));
         let module = context.create_module(self.module_name().as_ref());
         let emit = EmitHugr::new(context, module, namer.clone(), extensions);
         let module = emit.emit_module(hugr.fat_root().unwrap())?.finish();
 
+        pool_string_constants(&module);
+        inline_helper_functions(&module, &self.target_machine()?)?;
+        hoist_static_allocas(&module);
         let qubit_count: u64 = replace_int_opque_pointer(&module, "__quantum__rt__qubit_allocate");
         let result_count: u64 = replace_int_opque_pointer(&module, "__QIR__CONV_Qubit_TO_Result");
+        if let Some(desc) = &self.target_description {
+            desc.check_counts(qubit_count, result_count)?;
+        }
 
         add_module_metadata(&namer, hugr, &module, qubit_count, result_count)?;
+        let (entry_node, entry_name) = find_entry_point_name(hugr)?;
+        if let Some(entry) = module.get_function(&namer.name_func(entry_name, entry_node)) {
+            reset_live_qubits(&module, entry)?;
+        }
 
         // This is a workaround to an issue in hugr-llvm: https://github.com/CQCL/hugr/issues/2615
         // Can be removed when that issue is resolved
         set_explicit_entrypoint_linkage(&namer, hugr, &module)?;
 
+        if self.emit_main {
+            add_runtime_main(&namer, hugr, &module)?;
+        }
+
         Ok(module)
     }
 
@@ -162,6 +287,15 @@ impl CompileArgs {
         let module = self.hugr_to_llvm(hugr, context)?;
 
         self.optimize_module_llvm(&module)?;
+        restore_stack_in_loops(&module);
+        if self.result_record_output {
+            record_results_directly(&module)?;
+        }
+
+        if let Some(desc) = &self.target_description {
+            desc.check_native_gates(&module)?;
+            desc.apply(&module);
+        }
 
         Ok(module)
     }
@@ -254,6 +388,360 @@ pub fn replace_int_opque_pointer(module: &Module, funcname: &str) -> u64 {
     pointer_counter
 }
 
+/// Inlines and removes the internal helper functions emitted by some
+/// lowerings, e.g. for array bounds checks, leaving only the entry point.
+pub fn inline_helper_functions(module: &Module, target_machine: &TargetMachine) -> Result<()> {
+    let context = module.get_context();
+    let always_inline =
+        context.create_enum_attribute(Attribute::get_named_enum_kind_id("alwaysinline"), 0);
+    let helpers = module
+        .get_functions()
+        .filter(|f| f.get_linkage() == Linkage::Internal && f.count_basic_blocks() > 0)
+        .collect_vec();
+    if helpers.is_empty() {
+        return Ok(());
+    }
+    for func in helpers {
+        func.add_attribute(AttributeLoc::Function, always_inline);
+    }
+    module
+        .run_passes(
+            "always-inline",
+            target_machine,
+            PassBuilderOptions::create(),
+        )
+        .map_err(|e| anyhow!("Failed to inline helper functions: {e}"))
+}
+
+/// Replaces each private string constant with the first identical one in the
+/// module, so that e.g. a result tag recorded in many places is stored once.
+pub fn pool_string_constants(module: &Module) {
+    let mut strings = HashMap::new();
+    for global in module.get_globals().collect_vec() {
+        if !global.is_constant()
+            || global.get_linkage() != Linkage::Private
+            || global.get_unnamed_address() != UnnamedAddress::Global
+        {
+            continue;
+        }
+        let Some(BasicValueEnum::ArrayValue(init)) = global.get_initializer() else {
+            continue;
+        };
+        let Some(bytes) = init.as_const_string() else {
+            continue;
+        };
+        match strings.entry(bytes.to_vec()) {
+            Entry::Vacant(entry) => {
+                entry.insert(global);
+            }
+            Entry::Occupied(entry) => {
+                global
+                    .as_pointer_value()
+                    .replace_all_uses_with(entry.get().as_pointer_value());
+                // SAFETY: the global has no uses left
+                unsafe { global.delete() };
+            }
+        }
+    }
+}
+
+/// Moves allocas of a constant size that run at most once per call, i.e. are
+/// not in a block on a cycle, to the entry block of their function, so that
+/// LLVM can promote them to registers.
+pub fn hoist_static_allocas(module: &Module) {
+    let builder = module.get_context().create_builder();
+    for func in module.get_functions() {
+        let Some(terminator) = func
+            .get_first_basic_block()
+            .and_then(|entry| entry.get_terminator())
+        else {
+            continue;
+        };
+        for block in func.get_basic_blocks().into_iter().skip(1) {
+            if on_cycle(block) {
+                continue;
+            }
+            for ins in block.get_instructions().collect_vec() {
+                let constant_size = ins.get_opcode() == InstructionOpcode::Alloca
+                    && ins
+                        .get_operand(0)
+                        .and_then(|size| size.left())
+                        .is_some_and(|size| size.into_int_value().is_const());
+                if constant_size {
+                    ins.remove_from_basic_block();
+                    builder.position_before(&terminator);
+                    builder.insert_instruction(&ins, None);
+                }
+            }
+        }
+    }
+}
+
+/// Releases the stack allocations made in each iteration of a loop at the end
+/// of the iteration, so that arrays created in loops do not grow the stack
+/// without bound. Loops where an allocation may outlive its iteration, i.e. it
+/// is stored, carried by a phi or used after the loop, are left unchanged.
+/// Expects allocas to have been promoted to registers by optimisation.
+pub fn restore_stack_in_loops(module: &Module) {
+    let builder = module.get_context().create_builder();
+    for func in module.get_functions() {
+        let Some(entry) = func.get_first_basic_block() else {
+            continue;
+        };
+        let mut predecessors: HashMap<_, Vec<_>> = HashMap::new();
+        for block in func.get_basic_blocks() {
+            for succ in successors(block) {
+                predecessors.entry(succ).or_default().push(block);
+            }
+        }
+        for (header, latches) in back_edges(entry) {
+            // the stack pointer saved in the header must be available in the
+            // latches
+            let outside = reachable_avoiding(entry, header);
+            if latches.iter().any(|latch| outside.contains(latch)) {
+                continue;
+            }
+            let mut body = HashSet::from([header]);
+            let mut stack = latches.clone();
+            while let Some(block) = stack.pop() {
+                if body.insert(block) {
+                    stack.extend(predecessors.get(&block).into_iter().flatten());
+                }
+            }
+            let allocas = body
+                .iter()
+                .flat_map(|block| block.get_instructions())
+                .filter(|ins| ins.get_opcode() == InstructionOpcode::Alloca)
+                .collect_vec();
+            if allocas.is_empty() || allocas.iter().any(|&ins| outlives_iteration(ins, &body)) {
+                continue;
+            }
+            let intrinsic =
+                |name| Intrinsic::find(name).and_then(|i| i.get_declaration(module, &[]));
+            let (Some(save), Some(restore)) =
+                (intrinsic("llvm.stacksave"), intrinsic("llvm.stackrestore"))
+            else {
+                continue;
+            };
+            let Some(first) = header
+                .get_instructions()
+                .find(|ins| ins.get_opcode() != InstructionOpcode::Phi)
+            else {
+                continue;
+            };
+            builder.position_before(&first);
+            let Some(sp) = builder
+                .build_call(save, &[], "")
+                .ok()
+                .and_then(|call| call.try_as_basic_value().left())
+            else {
+                continue;
+            };
+            for latch in latches {
+                if let Some(terminator) = latch.get_terminator() {
+                    builder.position_before(&terminator);
+                    let _ = builder.build_call(restore, &[sp.into()], "");
+                }
+            }
+        }
+    }
+}
+
+/// The targets of the back edges of a depth-first search from `entry`, with
+/// the blocks the edges come from, in the order they are found.
+fn back_edges(entry: BasicBlock<'_>) -> Vec<(BasicBlock<'_>, Vec<BasicBlock<'_>>)> {
+    let mut edges: Vec<(BasicBlock, Vec<BasicBlock>)> = vec![];
+    let mut visited = HashSet::from([entry]);
+    let mut on_stack = HashSet::from([entry]);
+    let mut stack = vec![(entry, successors(entry).into_iter())];
+    while let Some((block, succs)) = stack.last_mut() {
+        let block = *block;
+        let Some(succ) = succs.next() else {
+            on_stack.remove(&block);
+            stack.pop();
+            continue;
+        };
+        if on_stack.contains(&succ) {
+            match edges.iter_mut().find(|(header, _)| *header == succ) {
+                Some((_, latches)) => latches.push(block),
+                None => edges.push((succ, vec![block])),
+            }
+        } else if visited.insert(succ) {
+            on_stack.insert(succ);
+            stack.push((succ, successors(succ).into_iter()));
+        }
+    }
+    edges
+}
+
+/// The blocks reachable from `entry` without passing through `avoid`.
+fn reachable_avoiding<'c>(entry: BasicBlock<'c>, avoid: BasicBlock<'c>) -> HashSet<BasicBlock<'c>> {
+    let mut seen = HashSet::from([entry]);
+    let mut stack = vec![entry];
+    while let Some(block) = stack.pop() {
+        for succ in successors(block) {
+            if succ != avoid && seen.insert(succ) {
+                stack.push(succ);
+            }
+        }
+    }
+    seen
+}
+
+/// Whether the memory allocated by `alloca` may be used after the iteration of
+/// the loop `body` that allocated it.
+fn outlives_iteration(alloca: InstructionValue, body: &HashSet<BasicBlock>) -> bool {
+    let mut seen = HashSet::from([alloca]);
+    let mut derived = vec![alloca];
+    while let Some(value) = derived.pop() {
+        let mut next_use = value.get_first_use();
+        while let Some(value_use) = next_use {
+            next_use = value_use.get_next_use();
+            let Some(user) = as_instruction(value_use.get_user()) else {
+                return true;
+            };
+            if !user.get_parent().is_some_and(|block| body.contains(&block)) {
+                return true;
+            }
+            let stored = || {
+                user.get_operand(0)
+                    .and_then(|op| op.left())
+                    .and_then(|op| op.as_instruction_value())
+                    == Some(value)
+            };
+            let intrinsic = || {
+                CallSiteValue::try_from(user)
+                    .ok()
+                    .and_then(|call| call.get_called_fn_value())
+                    .is_some_and(|func| func.get_name().to_bytes().starts_with(b"llvm."))
+            };
+            match user.get_opcode() {
+                InstructionOpcode::Load | InstructionOpcode::ICmp => {}
+                InstructionOpcode::Store if !stored() => {}
+                InstructionOpcode::Call if intrinsic() => {}
+                InstructionOpcode::GetElementPtr
+                | InstructionOpcode::BitCast
+                | InstructionOpcode::InsertValue
+                | InstructionOpcode::ExtractValue
+                | InstructionOpcode::Select => {
+                    if seen.insert(user) {
+                        derived.push(user);
+                    }
+                }
+                _ => return true,
+            }
+        }
+    }
+    false
+}
+
+fn as_instruction(value: AnyValueEnum) -> Option<InstructionValue> {
+    match value {
+        AnyValueEnum::InstructionValue(ins) => Some(ins),
+        AnyValueEnum::PhiValue(phi) => Some(phi.as_instruction()),
+        value => BasicValueEnum::try_from(value).ok()?.as_instruction_value(),
+    }
+}
+
+/// Whether `block` can be reached from itself.
+fn on_cycle(block: BasicBlock) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = successors(block);
+    while let Some(b) = stack.pop() {
+        if b == block {
+            return true;
+        }
+        if seen.insert(b) {
+            stack.extend(successors(b));
+        }
+    }
+    false
+}
+
+/// The blocks the terminator of `block` may branch to.
+fn successors(block: BasicBlock<'_>) -> Vec<BasicBlock<'_>> {
+    let Some(terminator) = block.get_terminator() else {
+        return vec![];
+    };
+    (0..terminator.get_num_operands())
+        .filter_map(|i| terminator.get_operand(i)?.right())
+        .collect()
+}
+
+/// Replaces each `__quantum__rt__bool_record_output` of a bool that is only
+/// read from a measurement result with `__quantum__rt__result_record_output`
+/// of the result, removing the read.
+pub fn record_results_directly(module: &Module) -> Result<()> {
+    let (Some(read_fn), Some(record_fn)) = (
+        module.get_function("__quantum__qis__read_result__body"),
+        module.get_function("__quantum__rt__bool_record_output"),
+    ) else {
+        return Ok(());
+    };
+
+    let mut records = vec![];
+    for func in module.get_functions() {
+        for block in func.get_basic_blocks() {
+            for ins in block.get_instructions() {
+                let Ok(call) = CallSiteValue::try_from(ins) else {
+                    continue;
+                };
+                if call.get_called_fn_value() != Some(record_fn) {
+                    continue;
+                }
+                let Some(read) = ins
+                    .get_operand(0)
+                    .and_then(|op| op.left())
+                    .and_then(|val| val.as_instruction_value())
+                else {
+                    continue;
+                };
+                let is_read = CallSiteValue::try_from(read)
+                    .is_ok_and(|read| read.get_called_fn_value() == Some(read_fn));
+                let single_use = read
+                    .get_first_use()
+                    .is_some_and(|u| u.get_next_use().is_none());
+                if !(is_read && single_use) {
+                    continue;
+                }
+                let (Some(result), Some(tag)) = (
+                    read.get_operand(0).and_then(|op| op.left()),
+                    ins.get_operand(1).and_then(|op| op.left()),
+                ) else {
+                    continue;
+                };
+                records.push((ins, read, result, tag));
+            }
+        }
+    }
+    if records.is_empty() {
+        return Ok(());
+    }
+
+    let context = module.get_context();
+    let record_params = record_fn.get_type().get_param_types();
+    let read_params = read_fn.get_type().get_param_types();
+    let result_record_fn = module
+        .get_function("__quantum__rt__result_record_output")
+        .unwrap_or_else(|| {
+            module.add_function(
+                "__quantum__rt__result_record_output",
+                context
+                    .void_type()
+                    .fn_type(&[read_params[0], record_params[1]], false),
+                None,
+            )
+        });
+    let builder = context.create_builder();
+    for (record, read, result, tag) in records {
+        builder.position_before(&record);
+        builder.build_call(result_record_fn, &[result.into(), tag.into()], "")?;
+        record.erase_from_basic_block();
+        read.erase_from_basic_block();
+    }
+    Ok(())
+}
+
 pub fn add_module_metadata(
     namer: &Namer,
     hugr: &impl HugrView<Node = Node>,
@@ -371,6 +859,575 @@ pub fn set_explicit_entrypoint_linkage(
     Ok(())
 }
 
+/// Adds `int main(int argc, char **argv)`, handing the entry point to
+/// `hugr_qir_runtime_main` of the hugr-qir-runtime library.
+pub fn add_runtime_main(
+    namer: &Namer,
+    hugr: &impl HugrView<Node = Node>,
+    module: &Module,
+) -> Result<()> {
+    let (entry_node, entry_name) = find_entry_point_name(hugr)?;
+    let entry_func_name = namer.name_func(entry_name, entry_node);
+    let entry = module.get_function(&entry_func_name).ok_or_else(|| {
+        anyhow!("expected main function: \"{entry_func_name}\" not found in HUGR")
+    })?;
+    if entry.count_params() != 0 {
+        bail!("Cannot emit main: entry point \"{entry_func_name}\" takes parameters");
+    }
+    if module.get_function("main").is_some() {
+        bail!("Cannot emit main: module already defines main");
+    }
+
+    let context = module.get_context();
+    let i32_ty = context.i32_type();
+    let argv_ty = context
+        .i8_type()
+        .ptr_type(AddressSpace::default())
+        .ptr_type(AddressSpace::default());
+    let entry_ptr_ty = context
+        .void_type()
+        .fn_type(&[], false)
+        .ptr_type(AddressSpace::default());
+    let runtime_main = module.add_function(
+        "hugr_qir_runtime_main",
+        i32_ty.fn_type(&[i32_ty.into(), argv_ty.into(), entry_ptr_ty.into()], false),
+        Some(Linkage::External),
+    );
+    let main = module.add_function(
+        "main",
+        i32_ty.fn_type(&[i32_ty.into(), argv_ty.into()], false),
+        Some(Linkage::External),
+    );
+
+    let builder = context.create_builder();
+    builder.position_at_end(context.append_basic_block(main, "entry"));
+    let entry_ptr = builder.build_pointer_cast(
+        entry.as_global_value().as_pointer_value(),
+        entry_ptr_ty,
+        "entry",
+    )?;
+    let ret = builder
+        .build_call(
+            runtime_main,
+            &[
+                main.get_nth_param(0).unwrap().into(),
+                main.get_nth_param(1).unwrap().into(),
+                entry_ptr.into(),
+            ],
+            "ret",
+        )?
+        .try_as_basic_value()
+        .left()
+        .ok_or_else(|| anyhow!("hugr_qir_runtime_main returns no value"))?;
+    builder.build_return(Some(&ret))?;
+    Ok(())
+}
+
 mod inline;
 #[cfg(test)]
 pub(crate) mod test;
+
+#[cfg(test)]
+mod tests {
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::bool_t;
+    use hugr::llvm::inkwell::basic_block::BasicBlock;
+    use hugr::llvm::inkwell::context::Context;
+    use hugr::llvm::inkwell::targets::FileType;
+    use hugr::llvm::inkwell::values::InstructionOpcode;
+    use hugr::ops::Value;
+    use hugr::types::Signature;
+    use hugr::{Hugr, Wire};
+    use rstest::rstest;
+    use tket::TketOp;
+    use tket::extension::bool::{BoolOp, ConstBool};
+    use tket_qsystem::extension::result::ResultOp;
+
+    use crate::cli::CliOptimizationLevel;
+    use crate::target::description::TargetDescription;
+    use crate::test::{held_qubits_hugr, single_op_hugr};
+    use crate::{CompileArgs, hoist_static_allocas};
+
+    #[rstest]
+    #[case(CliOptimizationLevel::None, true)]
+    #[case(CliOptimizationLevel::Minimal, false)]
+    fn compile_opt_level(#[case] opt_level: CliOptimizationLevel, #[case] has_alloca: bool) {
+        let mut hugr = single_op_hugr(TketOp::Measure.into());
+        let args = CompileArgs {
+            opt_level,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert_eq!(ir.contains("= alloca"), has_alloca);
+        assert!(ir.contains("__quantum__qis__mz__body"));
+        // the allocas that are left have been hoisted to the entry block
+        let main = module
+            .get_functions()
+            .find(|f| f.count_basic_blocks() > 0)
+            .unwrap();
+        let allocas = |block: BasicBlock| {
+            block
+                .get_instructions()
+                .filter(|ins| ins.get_opcode() == InstructionOpcode::Alloca)
+                .count()
+        };
+        let blocks = main.get_basic_blocks();
+        assert_eq!(allocas(blocks[0]) > 0, has_alloca, "{ir}");
+        assert!(blocks[1..].iter().all(|&b| allocas(b) == 0), "{ir}");
+    }
+
+    #[rstest]
+    #[case(TketOp::QAlloc, r#"{"max_qubits": 0}"#, "requires 1 qubits")]
+    #[case(TketOp::MeasureFree, r#"{"max_results": 0}"#, "requires 1 results")]
+    #[case(
+        TketOp::H,
+        r#"{"native_gates": ["rz"]}"#,
+        "\"phasedx\" is not in the native gate set"
+    )]
+    #[case(
+        TketOp::Rz,
+        r#"{"classical_ops": []}"#,
+        "fmul from extension arithmetic.float"
+    )]
+    fn compile_target_description_limits(
+        #[case] op: TketOp,
+        #[case] json: &str,
+        #[case] expected: &str,
+    ) {
+        let mut hugr = single_op_hugr(op.into());
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(json).unwrap()),
+            ..Default::default()
+        };
+        let context = Context::create();
+        let err = args.compile(&mut hugr, &context).unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
+    }
+
+    /// A borrow array of qubits is promoted to registers.
+    #[test]
+    fn compile_qubit_array() {
+        use hugr::extension::prelude::{ConstUsize, qb_t};
+        use hugr::std_extensions::collections::borrow_array::BArrayOpBuilder as _;
+        use tket::extension::bool::bool_type;
+
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![], vec![bool_type(); 2]))
+            .unwrap();
+        let qbs = (0..2)
+            .map(|_| {
+                func.add_dataflow_op(TketOp::QAlloc, [])
+                    .unwrap()
+                    .out_wire(0)
+            })
+            .collect::<Vec<_>>();
+        let arr = func.add_new_borrow_array(qb_t(), qbs).unwrap();
+        let index = func.add_load_value(ConstUsize::new(1));
+        let (arr, qb) = func.add_borrow_array_borrow(qb_t(), 2, arr, index).unwrap();
+        let [qb] = func.add_dataflow_op(TketOp::X, [qb]).unwrap().outputs_arr();
+        let arr = func
+            .add_borrow_array_return(qb_t(), 2, arr, index, qb)
+            .unwrap();
+        let bits = func
+            .add_borrow_array_unpack(qb_t(), 2, arr)
+            .unwrap()
+            .into_iter()
+            .map(|qb| {
+                func.add_dataflow_op(TketOp::MeasureFree, [qb])
+                    .unwrap()
+                    .out_wire(0)
+            })
+            .collect::<Vec<_>>();
+        func.finish_with_outputs(bits).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+
+        let context = Context::create();
+        let module = CompileArgs::default().compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert!(!ir.contains("alloca"), "{ir}");
+        assert!(!ir.contains("malloc"), "{ir}");
+        assert_eq!(ir.matches("call void @__quantum__qis__mz__body").count(), 2);
+    }
+
+    /// An array created in each iteration of a loop and read at a dynamic
+    /// index stays on the stack, which is restored at the end of the
+    /// iteration, unless the array is carried out of the iteration.
+    #[rstest]
+    fn compile_array_in_loop(#[values(false, true)] carried: bool) {
+        use hugr::extension::prelude::{UnwrapBuilder as _, option_type};
+        use hugr::std_extensions::arithmetic::conversions::ConvertOpDef;
+        use hugr::std_extensions::arithmetic::int_ops::IntOpDef;
+        use hugr::std_extensions::arithmetic::int_types::{ConstInt, int_type};
+        use hugr::std_extensions::collections::array::{ArrayOpBuilder as _, array_type};
+
+        let int = int_type(6);
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![int.clone()], vec![]))
+            .unwrap();
+        let [start] = func.input_wires_arr();
+        let mut state = vec![(int.clone(), start)];
+        if carried {
+            let arr = func.add_new_array(int.clone(), [start, start]).unwrap();
+            state.push((array_type(2, int.clone()), arr));
+        }
+        let mut tail_loop = func.tail_loop_builder([], state, vec![].into()).unwrap();
+        let mut inputs = tail_loop.input_wires();
+        let n = inputs.next().unwrap();
+        if let Some(old) = inputs.next() {
+            tail_loop.add_array_discard(int.clone(), 2, old).unwrap();
+        }
+        let zero = tail_loop.add_load_value(ConstInt::new_u(6, 0).unwrap());
+        let arr = tail_loop.add_new_array(int.clone(), [n, zero]).unwrap();
+        let two = tail_loop.add_load_value(ConstInt::new_u(6, 2).unwrap());
+        let [index] = tail_loop
+            .add_dataflow_op(IntOpDef::imod_u.with_log_width(6), [n, two])
+            .unwrap()
+            .outputs_arr();
+        let [index] = tail_loop
+            .add_dataflow_op(ConvertOpDef::itousize.without_log_width(), [index])
+            .unwrap()
+            .outputs_arr();
+        let (elem, arr) = tail_loop.add_array_get(int.clone(), 2, arr, index).unwrap();
+        let [elem] = tail_loop
+            .build_unwrap_sum(1, option_type(int.clone()), elem)
+            .unwrap();
+        tail_loop
+            .add_dataflow_op(ResultOp::new_uint("x", 6), [elem])
+            .unwrap();
+        let one = tail_loop.add_load_value(ConstInt::new_u(6, 1).unwrap());
+        let [n] = tail_loop
+            .add_dataflow_op(IntOpDef::isub.with_log_width(6), [n, one])
+            .unwrap()
+            .outputs_arr();
+        let [done] = tail_loop
+            .add_dataflow_op(IntOpDef::ieq.with_log_width(6), [n, zero])
+            .unwrap()
+            .outputs_arr();
+        if carried {
+            let [_, arr] = tail_loop
+                .finish_with_outputs(done, [n, arr])
+                .unwrap()
+                .outputs_arr();
+            let [index] = func
+                .add_dataflow_op(ConvertOpDef::itousize.without_log_width(), [start])
+                .unwrap()
+                .outputs_arr();
+            let (elem, arr) = func.add_array_get(int.clone(), 2, arr, index).unwrap();
+            let [elem] = func
+                .build_unwrap_sum(1, option_type(int.clone()), elem)
+                .unwrap();
+            func.add_dataflow_op(ResultOp::new_uint("last", 6), [elem])
+                .unwrap();
+            func.add_array_discard(int, 2, arr).unwrap();
+        } else {
+            tail_loop.add_array_discard(int, 2, arr).unwrap();
+            tail_loop.finish_with_outputs(done, [n]).unwrap();
+        }
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+
+        let context = Context::create();
+        let module = CompileArgs::default().compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert!(ir.contains("alloca"), "{ir}");
+        assert_eq!(ir.contains("call i8* @llvm.stacksave()"), !carried, "{ir}");
+        assert_eq!(
+            ir.contains("call void @llvm.stackrestore"),
+            !carried,
+            "{ir}"
+        );
+    }
+
+    /// Only allocas outside of loops are moved to the entry block.
+    #[test]
+    fn hoist_allocas_outside_loops() {
+        let context = Context::create();
+        let module = context.create_module("hoist");
+        let func_ty = context
+            .void_type()
+            .fn_type(&[context.bool_type().into()], false);
+        let func = module.add_function("f", func_ty, None);
+        let [entry, straight, looping, exit] = ["entry", "straight", "looping", "exit"]
+            .map(|name| context.append_basic_block(func, name));
+        let builder = context.create_builder();
+        let size = context.i64_type().const_int(4, false);
+        builder.position_at_end(entry);
+        builder.build_unconditional_branch(straight).unwrap();
+        builder.position_at_end(straight);
+        builder
+            .build_array_alloca(context.i8_type(), size, "")
+            .unwrap();
+        builder.build_unconditional_branch(looping).unwrap();
+        builder.position_at_end(looping);
+        builder
+            .build_array_alloca(context.i8_type(), size, "")
+            .unwrap();
+        let cond = func.get_first_param().unwrap().into_int_value();
+        builder
+            .build_conditional_branch(cond, looping, exit)
+            .unwrap();
+        builder.position_at_end(exit);
+        builder.build_return(None).unwrap();
+        module.verify().unwrap();
+
+        hoist_static_allocas(&module);
+        module.verify().unwrap();
+        let allocas = |block: BasicBlock| {
+            block
+                .get_instructions()
+                .filter(|ins| ins.get_opcode() == InstructionOpcode::Alloca)
+                .count()
+        };
+        assert_eq!([entry, straight, looping].map(allocas), [1, 0, 1]);
+        let first = entry.get_first_instruction().unwrap();
+        assert_eq!(first.get_opcode(), InstructionOpcode::Alloca);
+    }
+
+    /// Allocations are checked at runtime on top of the static check of the
+    /// allocation sites.
+    #[test]
+    fn compile_qubit_capacity() {
+        let mut hugr = single_op_hugr(TketOp::QAlloc.into());
+        let desc = r#"{"max_qubits": 1, "check_qubit_capacity": true}"#;
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(desc).unwrap()),
+            // keep the check the single allocation is known to pass
+            opt_level: CliOptimizationLevel::None,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert!(ir.contains("call void @abort()"));
+        assert!(ir.contains("\"required_num_qubits\"=\"1\""));
+
+        // more allocation sites than qubits still fail compilation
+        let mut hugr = single_op_hugr(TketOp::QAlloc.into());
+        let desc = r#"{"max_qubits": 0, "check_qubit_capacity": true}"#;
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(desc).unwrap()),
+            ..Default::default()
+        };
+        assert!(args.compile(&mut hugr, &context).is_err());
+    }
+
+    /// Qubits allocated in a loop are checked against the qubits in use at
+    /// runtime, which each run of the entry point starts counting from zero.
+    #[test]
+    fn compile_qubit_capacity_loop() {
+        let mut hugr = held_qubits_hugr();
+        let desc = r#"{"max_qubits": 1, "check_qubit_capacity": true}"#;
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(desc).unwrap()),
+            // keep the reset from being folded into the first allocation
+            opt_level: CliOptimizationLevel::None,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert!(ir.contains("\"required_num_qubits\"=\"1\""), "{ir}");
+        assert!(
+            ir.contains("store i64 0, i64* @__hugr_qir_live_qubits"),
+            "{ir}"
+        );
+    }
+
+    /// Combines two measurements with `tket.bool` ops, converting between
+    /// `tket.bool` and the prelude bool on the way.
+    fn bool_ops_hugr() -> Hugr {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![], vec![bool_t()]))
+            .unwrap();
+        let [a, b] = [(); 2].map(|_| {
+            let qb = func
+                .add_dataflow_op(TketOp::QAlloc, [])
+                .unwrap()
+                .out_wire(0);
+            func.add_dataflow_op(TketOp::MeasureFree, [qb])
+                .unwrap()
+                .out_wire(0)
+        });
+        let t = func.add_load_value(Value::from(ConstBool::new(true)));
+        let mut bool_op = |op: BoolOp, inputs: &[Wire]| {
+            func.add_dataflow_op(op, inputs.iter().copied())
+                .unwrap()
+                .out_wire(0)
+        };
+        let a_read = bool_op(BoolOp::read, &[a]);
+        let a = bool_op(BoolOp::make_opaque, &[a_read]);
+        let x = bool_op(BoolOp::xor, &[a, b]);
+        let y = bool_op(BoolOp::and, &[x, t]);
+        let n = bool_op(BoolOp::not, &[y]);
+        let e = bool_op(BoolOp::eq, &[n, a]);
+        let o = bool_op(BoolOp::or, &[e, b]);
+        let c = bool_op(BoolOp::read, &[o]);
+        func.add_dataflow_op(ResultOp::new_bool("c"), [c]).unwrap();
+        func.finish_with_outputs([c]).unwrap();
+        module.finish_hugr().unwrap()
+    }
+
+    #[rstest]
+    fn compile_bool_ops(#[values(false, true)] qsystem_pass: bool) {
+        let mut hugr = bool_ops_hugr();
+        let args = CompileArgs {
+            qsystem_pass,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert_eq!(
+            ir.matches("call void @__quantum__qis__mz__body").count(),
+            2,
+            "{ir}"
+        );
+        assert!(ir.contains("@__quantum__rt__bool_record_output"), "{ir}");
+    }
+
+    #[test]
+    fn compile_target_description_naming() {
+        let mut hugr = single_op_hugr(TketOp::MeasureFree.into());
+        let desc = r#"{"qis_prefix": "__device__qis__", "qir_profile": "adaptive_profile"}"#;
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(desc).unwrap()),
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert!(ir.contains("@__device__qis__mz__body"));
+        assert!(!ir.contains("__quantum__qis__"));
+        assert!(ir.contains(r#""qir_profiles"="adaptive_profile""#));
+    }
+
+    /// Measures a fresh qubit and records the bool as "c", also returning it
+    /// if `returned`.
+    fn measure_result_hugr(returned: bool) -> Hugr {
+        let outputs = if returned { vec![bool_t()] } else { vec![] };
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![], outputs))
+            .unwrap();
+        let qb = func
+            .add_dataflow_op(TketOp::QAlloc, [])
+            .unwrap()
+            .out_wire(0);
+        let bit = func
+            .add_dataflow_op(TketOp::MeasureFree, [qb])
+            .unwrap()
+            .out_wire(0);
+        let bit = func
+            .add_dataflow_op(BoolOp::read, [bit])
+            .unwrap()
+            .out_wire(0);
+        func.add_dataflow_op(ResultOp::new_bool("c"), [bit])
+            .unwrap();
+        let outputs = if returned { vec![bit] } else { vec![] };
+        func.finish_with_outputs(outputs).unwrap();
+        module.finish_hugr().unwrap()
+    }
+
+    #[rstest]
+    fn result_record_output(
+        #[values(CliOptimizationLevel::Minimal, CliOptimizationLevel::Aggressive)]
+        opt_level: CliOptimizationLevel,
+        #[values(false, true)] returned: bool,
+    ) {
+        let mut hugr = measure_result_hugr(returned);
+        let args = CompileArgs {
+            opt_level,
+            result_record_output: true,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        let recorded = ir.contains("call void @__quantum__rt__result_record_output(%Result* null");
+        let read = ir.contains("call i1 @__quantum__qis__read_result__body");
+        // the bool is read if it is used by more than the result
+        assert_eq!((recorded, read), (!returned, returned), "{ir}");
+    }
+
+    /// Repeated result tags share one global string.
+    #[test]
+    fn pool_string_constants() {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![bool_t()], vec![]))
+            .unwrap();
+        let [b] = func.input_wires_arr();
+        for tag in ["c", "d", "c"] {
+            func.add_dataflow_op(ResultOp::new_bool(tag), [b]).unwrap();
+        }
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+        let args = CompileArgs {
+            opt_level: CliOptimizationLevel::None,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert_eq!(ir.matches(r#"c"c\00""#).count(), 1, "{ir}");
+        assert_eq!(ir.matches(r#"c"d\00""#).count(), 1, "{ir}");
+        assert_eq!(
+            ir.matches("call void @__quantum__rt__bool_record_output")
+                .count(),
+            3,
+            "{ir}"
+        );
+    }
+
+    #[test]
+    fn emit_main() {
+        let mut hugr = single_op_hugr(TketOp::QAlloc.into());
+        let args = CompileArgs {
+            emit_main: true,
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+        module.verify().unwrap();
+        let ir = module.print_to_string().to_string();
+        assert!(ir.contains("define i32 @main(i32 %0, i8** %1)"), "{ir}");
+        assert!(ir.contains("call i32 @hugr_qir_runtime_main("), "{ir}");
+
+        let mut hugr = single_op_hugr(TketOp::H.into());
+        let err = args.compile(&mut hugr, &context).unwrap_err();
+        assert!(err.to_string().contains("takes parameters"), "{err}");
+    }
+
+    #[test]
+    fn write_object_and_assembly() {
+        let mut hugr = single_op_hugr(TketOp::MeasureFree.into());
+        let args = CompileArgs::default();
+        let context = Context::create();
+        let module = args.compile(&mut hugr, &context).unwrap();
+
+        let object = args
+            .write_to_memory_buffer(&module, FileType::Object)
+            .unwrap();
+        assert!(object.as_slice().starts_with(b"\x7fELF"));
+
+        let assembly = args
+            .write_to_memory_buffer(&module, FileType::Assembly)
+            .unwrap();
+        let assembly = String::from_utf8_lossy(assembly.as_slice());
+        assert!(assembly.contains("bl\t__quantum__qis__mz__body"));
+    }
+}
@@ -4,9 +4,14 @@ use anyhow::Result;
 use clap::Parser as _;
 use clap_verbosity_flag::log::Level;
 use hugr::llvm::inkwell;
-use hugr_qir::cli::Cli;
+use hugr_qir::cli::{Cli, CliCommand};
 
 fn main_impl(mut args: Cli) -> Result<()> {
+    match &mut args.command {
+        Some(CliCommand::Run(run_args)) => return run_args.run(),
+        Some(CliCommand::Difftest(diff_args)) => return diff_args.run(),
+        None => {}
+    }
     let context = inkwell::context::Context::create();
     let module = args.run(&context)?;
     args.write_module(&module)?;
@@ -15,7 +20,11 @@ fn main_impl(mut args: Cli) -> Result<()> {
 
 fn main() -> ExitCode {
     let args = Cli::parse();
-    let report_err = args.verbosity(Level::Error);
+    let report_err = match &args.command {
+        Some(CliCommand::Run(run_args)) => run_args.verbosity(Level::Error),
+        Some(CliCommand::Difftest(diff_args)) => diff_args.run.verbosity(Level::Error),
+        None => args.verbosity(Level::Error),
+    };
     match main_impl(args) {
         Ok(_) => ExitCode::SUCCESS,
         Err(e) => {
@@ -0,0 +1,63 @@
+//! Conversions of compiled modules to the supported output formats.
+
+use anyhow::{Result, anyhow};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::inkwell::context::Context;
+use crate::inkwell::memory_buffer::MemoryBuffer;
+use crate::inkwell::module::Module;
+
+/// Returns the bitcode of the module, base64 encoded.
+pub fn module_to_base64(module: &Module) -> String {
+    STANDARD.encode(module.write_bitcode_to_memory().as_slice())
+}
+
+/// Parses a module from LLVM IR text.
+pub fn ir_to_module<'c>(context: &'c Context, ir: &str) -> Result<Module<'c>> {
+    let buffer = MemoryBuffer::create_from_memory_range_copy(ir.as_bytes(), "hugr-qir");
+    context
+        .create_module_from_ir(buffer)
+        .map_err(|e| anyhow!("Failed to parse LLVM IR: {e}"))
+}
+
+/// Converts LLVM IR text to bitcode.
+pub fn ir_to_bitcode(ir: &str) -> Result<Vec<u8>> {
+    let context = Context::create();
+    let module = ir_to_module(&context, ir)?;
+    Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+}
+
+/// Converts LLVM IR text to base64 encoded bitcode.
+pub fn ir_to_base64(ir: &str) -> Result<String> {
+    Ok(STANDARD.encode(ir_to_bitcode(ir)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IR: &str = r#"; ModuleID = 'hugr-qir'
+source_filename = "hugr-qir"
+
+define void @main() {
+  ret void
+}
+"#;
+
+    #[test]
+    fn base64_roundtrip() {
+        let bitcode = ir_to_bitcode(IR).unwrap();
+        assert!(bitcode.starts_with(b"BC\xc0\xde"));
+        assert_eq!(STANDARD.decode(ir_to_base64(IR).unwrap()).unwrap(), bitcode);
+
+        let context = Context::create();
+        let module = ir_to_module(&context, IR).unwrap();
+        assert_eq!(module_to_base64(&module), ir_to_base64(IR).unwrap());
+    }
+
+    #[test]
+    fn invalid_ir() {
+        assert!(ir_to_bitcode("define void @main(").is_err());
+    }
+}
@@ -2,13 +2,15 @@ use std::{ffi::OsString, iter};
 
 use crate::CompileArgs;
 use crate::cli::{Cli, CliOptimizationLevel};
+use crate::output;
+use crate::results::{ResultManifest, ResultValue, ShotOutput};
 use crate::target::CompileTarget;
 use clap::{Parser, ValueEnum};
 use hugr::llvm::inkwell;
 use itertools::Itertools as _;
 use pyo3::{
-    Bound, PyResult, pyfunction, pymodule,
-    types::{PyAnyMethods as _, PyModule, PyModuleMethods as _, PyTuple},
+    Bound, IntoPyObjectExt as _, PyAny, PyResult, Python, pyfunction, pymodule,
+    types::{PyAnyMethods as _, PyBytes, PyModule, PyModuleMethods as _, PyTuple},
     wrap_pyfunction,
 };
 
@@ -59,6 +61,65 @@ pub fn compile_target_default() -> String {
         .unwrap()
 }
 
+#[pyfunction]
+pub fn ir_to_bitcode<'py>(py: Python<'py>, ir: &str) -> PyResult<Bound<'py, PyBytes>> {
+    Ok(PyBytes::new(py, &output::ir_to_bitcode(ir)?))
+}
+
+#[pyfunction]
+pub fn ir_to_base64(ir: &str) -> PyResult<String> {
+    Ok(output::ir_to_base64(ir)?)
+}
+
+/// The JSON manifest of the results recorded by a HUGR package.
+#[pyfunction]
+pub fn result_manifest(hugr: &[u8]) -> PyResult<String> {
+    let package = hugr::package::Package::load(hugr, None).map_err(anyhow::Error::from)?;
+    let mut manifest = ResultManifest::default();
+    for module in &package.modules {
+        manifest
+            .results
+            .extend(ResultManifest::from_hugr(module)?.results);
+    }
+    Ok(manifest.to_json()?)
+}
+
+/// The `(tag, value)` records of one shot.
+type PyShot<'py> = Vec<(String, Bound<'py, PyAny>)>;
+
+/// Parses shot output into a list of `(tag, value)` lists, one per shot,
+/// checking it against a JSON result manifest if given.
+#[pyfunction]
+#[pyo3(signature = (output, manifest=None))]
+pub fn parse_shot_output<'py>(
+    py: Python<'py>,
+    output: &str,
+    manifest: Option<&str>,
+) -> PyResult<Vec<PyShot<'py>>> {
+    let mut output = ShotOutput::parse(output)?;
+    if let Some(manifest) = manifest {
+        output.check(&ResultManifest::from_json(manifest)?)?;
+    }
+    output
+        .shots
+        .into_iter()
+        .map(|shot| {
+            shot.results
+                .into_iter()
+                .map(|(tag, value)| {
+                    let value = match value {
+                        ResultValue::Bool(b) => b.into_bound_py_any(py),
+                        ResultValue::Int(i) => i.into_bound_py_any(py),
+                        ResultValue::UInt(u) => u.into_bound_py_any(py),
+                        ResultValue::Double(d) => d.into_bound_py_any(py),
+                    }?;
+                    Ok((tag, value))
+                })
+                .collect()
+        })
+        .collect()
+}
+
 #[pymodule]
 pub fn _hugr_qir(m: &Bound<PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(cli, m)?)?;
@@ -66,5 +127,9 @@ pub fn _hugr_qir(m: &Bound<PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(opt_level_default, m)?)?;
     m.add_function(wrap_pyfunction!(compile_target_choices, m)?)?;
     m.add_function(wrap_pyfunction!(compile_target_default, m)?)?;
+    m.add_function(wrap_pyfunction!(ir_to_bitcode, m)?)?;
+    m.add_function(wrap_pyfunction!(ir_to_base64, m)?)?;
+    m.add_function(wrap_pyfunction!(result_manifest, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_shot_output, m)?)?;
     Ok(())
 }
@@ -1,9 +1,12 @@
-mod boolcodegenextension_workaround;
+pub mod array_ext;
+pub mod bool_ext;
 pub mod futures_ext;
 pub mod qsystem_ext;
 pub mod random_ext;
 pub mod result_ext;
 pub mod tket2_ext;
+pub mod utils_ext;
+pub mod wasm_ext;
 
 use anyhow::{Result, bail, ensure};
 use hugr::{
@@ -16,10 +19,11 @@ use hugr::{Node, llvm as hugr_llvm};
 use hugr_llvm::emit::RowPromise;
 use hugr_llvm::emit::libc::emit_libc_abort;
 use hugr_llvm::inkwell;
-use hugr_llvm::inkwell::values::BasicValueEnum;
+use hugr_llvm::inkwell::module::{Linkage, Module};
+use hugr_llvm::inkwell::values::{BasicValueEnum, FunctionValue, PointerValue};
 use inkwell::{context::Context, types::BasicType};
 use itertools::Itertools;
-use tket_qsystem::extension::futures;
+use tket_qsystem::extension::{futures, qsystem};
 
 use hugr_llvm::{
     emit::{EmitFuncContext, emit_value},
@@ -146,6 +150,27 @@ fn emit_qis_measure_to_result<'c, H: HugrView<Node = Node>>(
     Ok(result)
 }
 
+/// A helper to emit a qir __quantum__qis__mz_leaked__body call, measuring a
+/// qubit or detecting its leakage. Returns the outcome as an i64, 2 if the
+/// qubit leaked.
+fn emit_qis_measure_leaked<'c, H: HugrView<Node = Node>>(
+    context: &mut EmitFuncContext<'c, '_, H>,
+    qb: BasicValueEnum<'c>,
+) -> Result<BasicValueEnum<'c>> {
+    let iw_ctx = context.iw_context();
+    let measure_t = iw_ctx.i64_type().fn_type(&[qb.get_type().into()], false);
+    let measure_func = context.get_extern_func("__quantum__qis__mz_leaked__body", measure_t)?;
+    let Some(outcome) = context
+        .builder()
+        .build_call(measure_func, &[qb.into()], "")?
+        .try_as_basic_value()
+        .left()
+    else {
+        bail!("expected an outcome from mz_leaked")
+    };
+    Ok(outcome)
+}
+
 /// A helper to convert a Result pointer to a (representation of) a hugr bool.
 fn emit_qis_read_result<'c, H: HugrView<Node = Node>>(
     context: &mut EmitFuncContext<'c, '_, H>,
@@ -203,8 +228,133 @@ fn emit_qis_qalloc<'c, H: HugrView<Node = Node>>(
     Ok(qb)
 }
 
+/// Default runtime function returning the index of the current shot.
+pub const CURRENT_SHOT_FUNC: &str = "get_current_shot";
+
+/// Private global counting the qubits in use when there is a qubit capacity.
+const LIVE_QUBITS: &str = "__hugr_qir_live_qubits";
+
 #[derive(Clone, Debug)]
-pub struct QirCodegenExtension;
+pub struct QirCodegenExtension {
+    current_shot_func: String,
+    barriers: bool,
+    qubit_capacity: Option<u64>,
+    lazy_reads: bool,
+}
+
+impl Default for QirCodegenExtension {
+    fn default() -> Self {
+        Self {
+            current_shot_func: CURRENT_SHOT_FUNC.to_owned(),
+            barriers: true,
+            qubit_capacity: None,
+            lazy_reads: true,
+        }
+    }
+}
+
+impl QirCodegenExtension {
+    /// Lowers `tket.qsystem.utils.GetCurrentShot` to a call to `func`
+    /// instead of [CURRENT_SHOT_FUNC].
+    pub fn with_current_shot_func(mut self, func: impl Into<String>) -> Self {
+        self.current_shot_func = func.into();
+        self
+    }
+
+    /// Sets whether runtime barriers are lowered to
+    /// `__quantum__qis__barrier{n}__body` calls, or dropped.
+    pub fn with_barriers(mut self, barriers: bool) -> Self {
+        self.barriers = barriers;
+        self
+    }
+
+    /// Makes `tket.qsystem.TryQAlloc` return `None` once `capacity` qubits
+    /// are in use, instead of always succeeding.
+    pub fn with_qubit_capacity(mut self, capacity: u64) -> Self {
+        self.qubit_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets whether a `tket.futures.future<bool>` holds the result of its
+    /// measurement, read only when the future is, or the outcome, read
+    /// eagerly. Lazy reads are only correct if each measurement runs at most
+    /// once, since the result of a measurement is the same on every run.
+    pub fn with_lazy_reads(mut self, lazy_reads: bool) -> Self {
+        self.lazy_reads = lazy_reads;
+        self
+    }
+
+    /// With a qubit capacity, the global counting the qubits in use.
+    fn live_qubits<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &EmitFuncContext<'c, '_, H>,
+    ) -> Result<Option<PointerValue<'c>>> {
+        if self.qubit_capacity.is_none() {
+            return Ok(None);
+        }
+        let i64_ty = context.iw_context().i64_type();
+        let global = context.get_global(LIVE_QUBITS, i64_ty, false)?;
+        global.set_linkage(Linkage::Private);
+        global.set_initializer(&i64_ty.const_zero());
+        Ok(Some(global.as_pointer_value()))
+    }
+
+    /// Adds `delta` to the number of qubits in use, if there is a qubit
+    /// capacity.
+    fn count_live_qubits<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        delta: i64,
+    ) -> Result<()> {
+        if let Some(live) = self.live_qubits(context)? {
+            let builder = context.builder();
+            let count = builder.build_load(live, "")?.into_int_value();
+            let delta = count.get_type().const_int(delta as u64, true);
+            builder.build_store(live, builder.build_int_add(count, delta, "")?)?;
+        }
+        Ok(())
+    }
+
+    /// Emits a qubit allocation as [emit_qis_qalloc], counting the qubit as
+    /// in use.
+    fn emit_qalloc<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+    ) -> Result<BasicValueEnum<'c>> {
+        self.count_live_qubits(context, 1)?;
+        emit_qis_qalloc(context)
+    }
+
+    /// Emits a qubit release as [emit_qis_qfree], counting the qubit as no
+    /// longer in use.
+    fn emit_qfree<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        qb: BasicValueEnum<'c>,
+    ) -> Result<()> {
+        self.count_live_qubits(context, -1)?;
+        emit_qis_qfree(context, qb)
+    }
+}
+
+/// Sets the count of qubits in use to zero at the start of `entry`, if the
+/// module counts them, so that each run starts with no qubits in use.
+pub fn reset_live_qubits(module: &Module, entry: FunctionValue) -> Result<()> {
+    let Some(live) = module.get_global(LIVE_QUBITS) else {
+        return Ok(());
+    };
+    let Some(block) = entry.get_first_basic_block() else {
+        bail!("entry point has no body")
+    };
+    let builder = module.get_context().create_builder();
+    match block.get_first_instruction() {
+        Some(first) => builder.position_before(&first),
+        None => builder.position_at_end(block),
+    }
+    let zero = module.get_context().i64_type().const_zero();
+    builder.build_store(live.as_pointer_value(), zero)?;
+    Ok(())
+}
 
 impl CodegenExtension for QirCodegenExtension {
     fn add_extension<'a, H: HugrView<Node = Node> + 'a>(
@@ -227,10 +377,18 @@ impl CodegenExtension for QirCodegenExtension {
                 let s = self.clone();
                 move |context, args, op| s.emit_qsystem_op(context, args, op)
             })
+            .extension_op(qsystem::EXTENSION_ID, qsystem::RUNTIME_BARRIER_NAME, {
+                let s = self.clone();
+                move |context, args| s.emit_runtime_barrier(context, args)
+            })
             .simple_extension_op::<tket_qsystem::extension::futures::FutureOpDef>({
                 let s = self.clone();
                 move |context, args, op| s.emit_futures_op(context, args, op)
             })
+            .simple_extension_op::<tket_qsystem::extension::utils::UtilsOp>({
+                let s = self.clone();
+                move |context, args, op| s.emit_utils_op(context, args, op)
+            })
             .custom_type(
                 (
                     futures::EXTENSION_ID,
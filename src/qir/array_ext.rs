@@ -0,0 +1,133 @@
+//! LLVM lowering of the `collections.array` and `collections.borrow_arr`
+//! extensions with stack allocated storage.
+
+use anyhow::{Result, anyhow};
+use hugr::llvm::emit::EmitFuncContext;
+use hugr::llvm::extension::PreludeCodegen as _;
+use hugr::llvm::extension::collections::array::ArrayCodegen;
+use hugr::llvm::extension::collections::borrow_array::BorrowArrayCodegen;
+use hugr::llvm::inkwell::values::{BasicValueEnum, IntValue, PointerValue};
+use hugr::{HugrView, Node};
+
+use super::QirPreludeCodegen;
+
+/// Alignment of the storage of arrays, enough for any element type.
+const ARRAY_ALIGNMENT: u32 = 16;
+
+/// Lowers arrays like the default hugr-llvm lowering, but allocates their
+/// storage on the stack rather than with `malloc`, which QIR programs cannot
+/// call.
+///
+/// The storage is allocated where the array is created, and released when the
+/// function returns. [crate::hoist_static_allocas] moves allocations that are
+/// not in loops to the entry block, from where LLVM can promote arrays accessed
+/// at constant indices to registers. Allocations in loops stay where they are,
+/// and [crate::restore_stack_in_loops] releases them at the end of each
+/// iteration unless the array outlives it.
+#[derive(Clone, Debug, Default)]
+pub struct QirArrayCodegen;
+
+impl QirArrayCodegen {
+    fn emit_alloca<'c, H: HugrView<Node = Node>>(
+        &self,
+        ctx: &mut EmitFuncContext<'c, '_, H>,
+        size: IntValue<'c>,
+    ) -> Result<PointerValue<'c>> {
+        let i8_ty = ctx.iw_context().i8_type();
+        let ptr = ctx.builder().build_array_alloca(i8_ty, size, "array")?;
+        ptr.as_instruction()
+            .ok_or_else(|| anyhow!("expected an alloca instruction"))?
+            .set_alignment(ARRAY_ALIGNMENT)
+            .map_err(|e| anyhow!(e))?;
+        Ok(ptr)
+    }
+}
+
+impl ArrayCodegen for QirArrayCodegen {
+    fn emit_allocate_array<'c, H: HugrView<Node = Node>>(
+        &self,
+        ctx: &mut EmitFuncContext<'c, '_, H>,
+        size: IntValue<'c>,
+    ) -> Result<PointerValue<'c>> {
+        self.emit_alloca(ctx, size)
+    }
+
+    fn emit_free_array<'c, H: HugrView<Node = Node>>(
+        &self,
+        _ctx: &mut EmitFuncContext<'c, '_, H>,
+        _ptr: PointerValue<'c>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl BorrowArrayCodegen for QirArrayCodegen {
+    fn emit_panic<H: HugrView<Node = Node>>(
+        &self,
+        ctx: &mut EmitFuncContext<H>,
+        err: BasicValueEnum,
+    ) -> Result<()> {
+        QirPreludeCodegen.emit_panic(ctx, err)
+    }
+
+    fn emit_allocate_array<'c, H: HugrView<Node = Node>>(
+        &self,
+        ctx: &mut EmitFuncContext<'c, '_, H>,
+        size: IntValue<'c>,
+    ) -> Result<PointerValue<'c>> {
+        self.emit_alloca(ctx, size)
+    }
+
+    fn emit_free_array<'c, H: HugrView<Node = Node>>(
+        &self,
+        _ctx: &mut EmitFuncContext<'c, '_, H>,
+        _ptr: PointerValue<'c>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::qb_t;
+    use hugr::llvm::check_emission;
+    use hugr::llvm::test::{TestContext, llvm_ctx};
+    use hugr::std_extensions::collections::array::ArrayOpBuilder as _;
+    use hugr::std_extensions::collections::borrow_array::BArrayOpBuilder as _;
+    use hugr::types::Signature;
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Packs two qubits into an array and unpacks them again.
+    #[rstest]
+    #[case::array(1, false)]
+    #[case::borrow_array(2, true)]
+    fn emit_array_codegen(
+        #[case] _i: i32,
+        #[with(_i)] mut llvm_ctx: TestContext,
+        #[case] borrow: bool,
+    ) {
+        llvm_ctx.add_extensions(|ceb| {
+            ceb.add_prelude_extensions(QirPreludeCodegen)
+                .add_array_extensions(QirArrayCodegen)
+                .add_borrow_array_extensions(QirArrayCodegen)
+        });
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![qb_t(), qb_t()]))
+            .unwrap();
+        let qbs = func.input_wires();
+        let qbs = if borrow {
+            let arr = func.add_new_borrow_array(qb_t(), qbs).unwrap();
+            func.add_borrow_array_unpack(qb_t(), 2, arr).unwrap()
+        } else {
+            let arr = func.add_new_array(qb_t(), qbs).unwrap();
+            func.add_array_unpack(qb_t(), 2, arr).unwrap()
+        };
+        func.finish_with_outputs(qbs).unwrap();
+        let hugr = module.finish_hugr().unwrap();
+        check_emission!(hugr, llvm_ctx);
+    }
+}
@@ -1,3 +1,5 @@
+//! LLVM lowering implementations for the "tket.bool" extension.
+
 use anyhow::anyhow;
 use hugr_core::ops::{ExtensionOp, Value};
 use hugr_core::types::{SumType, TypeName};
@@ -10,23 +12,26 @@ use hugr_llvm::sum::LLVMSumValue;
 use hugr_llvm::types::TypingSession;
 use tket::extension::bool::{BOOL_EXTENSION_ID, BoolOp, ConstBool};
 
-// This is copied from https://github.com/CQCL/guppylang/blob/main/execute_llvm/src/bool.rs
-// And is a workaround until the below issues are resolved
+// Adapted from https://github.com/CQCL/guppylang/blob/main/execute_llvm/src/bool.rs
+// until tket provides a lowering, see
 // https://github.com/CQCL/tket2/issues/909
 // https://github.com/CQCL/tket2/issues/910
 
-#[allow(dead_code)]
 const BOOL_TYPE_ID: TypeName = TypeName::new_inline("bool");
 
-#[allow(dead_code)]
 fn llvm_bool_type<'c>(ts: &TypingSession<'c, '_>) -> IntType<'c> {
     ts.iw_context().bool_type()
 }
-#[allow(dead_code)]
+
+/// Lowers `tket.bool` ops and constants.
+///
+/// A `tket.bool` is represented as an `i1`, the same as the prelude bool sum
+/// type and the value of a read bool future, so `read` and `make_opaque` only
+/// convert between the representations of the hugr types.
+#[derive(Clone, Debug, Default)]
 pub struct BoolCodegenExtension;
 
 impl BoolCodegenExtension {
-    #[allow(dead_code)]
     fn emit_bool_op<'c, H: HugrView<Node = Node>>(
         &self,
         context: &mut EmitFuncContext<'c, '_, H>,
@@ -110,3 +115,58 @@ impl CodegenExtension for BoolCodegenExtension {
             .simple_extension_op(move |context, args, op| self.emit_bool_op(context, args, op))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::llvm::check_emission;
+    use hugr::llvm::test::{TestContext, llvm_ctx};
+    use hugr::ops::{OpType, Value};
+    use hugr::types::Signature;
+    use rstest::rstest;
+    use tket::extension::bool::{BoolOp, ConstBool, bool_type};
+
+    use super::*;
+    use crate::qir::QirPreludeCodegen;
+    use crate::test::single_op_hugr;
+
+    #[rstest::fixture]
+    fn ctx(mut llvm_ctx: TestContext) -> TestContext {
+        llvm_ctx.add_extensions(|builder| {
+            builder
+                .add_prelude_extensions(QirPreludeCodegen)
+                .add_extension(BoolCodegenExtension)
+        });
+        llvm_ctx
+    }
+
+    #[rstest]
+    #[case(BoolOp::read)]
+    #[case(BoolOp::make_opaque)]
+    #[case(BoolOp::not)]
+    #[case(BoolOp::and)]
+    #[case(BoolOp::or)]
+    #[case(BoolOp::xor)]
+    #[case(BoolOp::eq)]
+    fn emit(ctx: TestContext, #[case] op: BoolOp) {
+        let op: OpType = op.into();
+        let mut insta = insta::Settings::clone_current();
+        insta.set_snapshot_suffix(format!("{}_{}", insta.snapshot_suffix().unwrap_or(""), op));
+        insta.bind(|| {
+            let hugr = single_op_hugr(op);
+            check_emission!(hugr, ctx);
+        })
+    }
+
+    #[rstest]
+    fn emit_const(ctx: TestContext) {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![], vec![bool_type()]))
+            .unwrap();
+        let b = func.add_load_value(Value::from(ConstBool::new(true)));
+        func.finish_with_outputs([b]).unwrap();
+        let hugr = module.finish_hugr().unwrap();
+        check_emission!(hugr, ctx);
+    }
+}
@@ -1,21 +1,23 @@
-use anyhow::{Result, bail, ensure};
+use anyhow::{Result, anyhow, bail, ensure};
 use hugr::{
     HugrView, Node,
-    extension::prelude::bool_t,
-    ops::{ExtensionOp, Value},
+    extension::{prelude::bool_t, simple_op::MakeExtensionOp as _},
+    ops::ExtensionOp,
     types::CustomType,
 };
 use hugr_llvm::{
-    emit::{EmitFuncContext, EmitOpArgs, emit_value},
-    inkwell::types::BasicTypeEnum,
+    emit::{EmitFuncContext, EmitOpArgs},
+    inkwell::types::{BasicType as _, BasicTypeEnum},
     types::TypingSession,
 };
-use tket_qsystem::extension::futures::{EXTENSION_ID, FUTURE_TYPE_NAME, FutureOpDef};
+use tket_qsystem::extension::futures::{EXTENSION_ID, FUTURE_TYPE_NAME, FutureOp, FutureOpDef};
 
-use super::QirCodegenExtension;
+use super::{QirCodegenExtension, emit_qis_read_result, result_type};
 
 impl QirCodegenExtension {
-    /// We represent a hugr `tket.futures.future<bool>` as an i1.
+    /// With lazy reads, we represent a hugr `tket.futures.future<bool>` as the
+    /// Result pointer of the measurement, which is only read when the future
+    /// is. Other futures hold their value, computed eagerly.
     pub fn convert_future_type<'c>(
         &self,
         session: TypingSession<'c, '_>,
@@ -24,9 +26,18 @@ impl QirCodegenExtension {
         ensure!(
             custom_type.extension() == &EXTENSION_ID
                 && custom_type.name() == FUTURE_TYPE_NAME.as_str()
-                && custom_type.args() == [bool_t().into()]
         );
-        Ok(session.iw_context().bool_type().into())
+        let [arg] = custom_type.args() else {
+            bail!("Future type {custom_type} expects one type argument")
+        };
+        let ty = arg
+            .as_runtime()
+            .ok_or_else(|| anyhow!("Future type {custom_type} expects a runtime type"))?;
+        if self.lazy_reads && ty == bool_t() {
+            Ok(result_type(session.iw_context()).as_basic_type_enum())
+        } else {
+            session.llvm_type(&ty)
+        }
     }
 
     pub fn emit_futures_op<'c, H: HugrView<Node = Node>>(
@@ -35,19 +46,13 @@ impl QirCodegenExtension {
         args: EmitOpArgs<'c, '_, ExtensionOp, H>,
         op: FutureOpDef,
     ) -> Result<()> {
+        let future_op = FutureOp::from_extension_op(&args.node())?;
         match op {
-            FutureOpDef::Read => {
-                let true_val = emit_value(context, &Value::true_val())?;
-                let false_val = emit_value(context, &Value::false_val())?;
-
-                let bool_r = context.builder().build_select(
-                    args.inputs[0].into_int_value(),
-                    true_val,
-                    false_val,
-                    "",
-                )?;
-                args.outputs.finish(context.builder(), [bool_r])
+            FutureOpDef::Read if self.lazy_reads && future_op.typ == bool_t() => {
+                let result_bool = emit_qis_read_result(context, args.inputs[0])?;
+                args.outputs.finish(context.builder(), [result_bool])
             }
+            FutureOpDef::Read => args.outputs.finish(context.builder(), args.inputs),
             FutureOpDef::Dup => {
                 let input = args.inputs[0];
                 args.outputs.finish(context.builder(), [input, input])
@@ -60,7 +65,11 @@ impl QirCodegenExtension {
 
 #[cfg(test)]
 mod test {
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::qb_t;
     use hugr::extension::simple_op::HasConcrete as _;
+    use hugr::std_extensions::arithmetic::int_types::int_type;
+    use hugr::types::Signature;
     use hugr::{extension::prelude::bool_t, ops::OpType};
     use hugr_llvm::{
         check_emission,
@@ -69,6 +78,7 @@ mod test {
     use rstest::rstest;
 
     use tket_qsystem::extension::futures::FutureOpDef;
+    use tket_qsystem::extension::qsystem::QSystemOp;
 
     use crate::qir::{QirCodegenExtension, QirPreludeCodegen};
     use crate::test::single_op_hugr;
@@ -77,8 +87,9 @@ mod test {
     fn ctx(mut llvm_ctx: TestContext) -> TestContext {
         llvm_ctx.add_extensions(|builder| {
             builder
-                .add_extension(QirCodegenExtension)
+                .add_extension(QirCodegenExtension::default())
                 .add_prelude_extensions(QirPreludeCodegen)
+                .add_default_int_extensions()
         });
         llvm_ctx
     }
@@ -96,4 +107,47 @@ mod test {
             check_emission!(hugr, ctx);
         })
     }
+
+    /// Futures of other types than bool hold their value.
+    #[rstest]
+    #[case(FutureOpDef::Read)]
+    #[case(FutureOpDef::Dup)]
+    #[case(FutureOpDef::Free)]
+    fn emit_int_future(ctx: TestContext, #[case] op: FutureOpDef) {
+        let op: OpType = op.instantiate(&[int_type(6).into()]).unwrap().into();
+        let mut insta = insta::Settings::clone_current();
+        insta.set_snapshot_suffix(format!("{}_{}", insta.snapshot_suffix().unwrap_or(""), op));
+        insta.bind(|| {
+            let hugr = single_op_hugr(op);
+            check_emission!(hugr, ctx);
+        })
+    }
+
+    /// The measurement is only read after the reset of the other qubit.
+    #[rstest]
+    fn emit_deferred_read(ctx: TestContext) {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function(
+                "main",
+                Signature::new(vec![qb_t(), qb_t()], vec![qb_t(), bool_t()]),
+            )
+            .unwrap();
+        let [q0, q1] = func.input_wires_arr();
+        let measure = func.add_dataflow_op(QSystemOp::LazyMeasure, [q0]).unwrap();
+        let reset = func.add_dataflow_op(QSystemOp::Reset, [q1]).unwrap();
+        func.set_order(&measure, &reset);
+        let [future] = measure.outputs_arr();
+        let [q1] = reset.outputs_arr();
+        let [bit] = func
+            .add_dataflow_op(
+                FutureOpDef::Read.instantiate(&[bool_t().into()]).unwrap(),
+                [future],
+            )
+            .unwrap()
+            .outputs_arr();
+        func.finish_with_outputs([q1, bit]).unwrap();
+        let hugr = module.finish_hugr().unwrap();
+        check_emission!(hugr, ctx);
+    }
 }
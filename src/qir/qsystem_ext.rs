@@ -1,18 +1,24 @@
+use std::collections::HashSet;
+
 use crate::qir::{
-    emit_qis_gate, emit_qis_gate_finish, emit_qis_measure_to_result, emit_qis_qalloc,
-    emit_qis_qfree, emit_qis_read_result,
+    emit_qis_gate, emit_qis_gate_finish, emit_qis_measure_leaked, emit_qis_measure_to_result,
+    emit_qis_read_result,
 };
 use anyhow::Result;
 use hugr::{
     HugrView, Node,
-    extension::prelude::{option_type, qb_t},
-    ops::ExtensionOp,
-};
-use hugr_llvm::{
-    emit::{EmitFuncContext, EmitOpArgs},
-    sum::LLVMSumValue,
-    types::HugrSumType,
+    extension::{
+        prelude::{option_type, qb_t},
+        simple_op::MakeExtensionOp as _,
+    },
+    ops::{ExtensionOp, OpType},
+    types::TypeArg,
 };
+use hugr_llvm::emit::{EmitFuncContext, EmitOpArgs};
+use hugr_llvm::extension::collections::array::decompose_array_fat_pointer;
+use hugr_llvm::inkwell::IntPredicate;
+use hugr_llvm::inkwell::values::BasicValueEnum;
+use itertools::Itertools;
 use tket_qsystem::extension::qsystem::QSystemOp;
 
 use super::QirCodegenExtension;
@@ -30,26 +36,18 @@ impl QirCodegenExtension {
                 let qb = args.inputs[0];
                 // i.e. Result*
                 let result = emit_qis_measure_to_result(context, qb)?;
+                self.count_live_qubits(context, -1)?;
 
                 let result_bool = emit_qis_read_result(context, result)?;
                 args.outputs.finish(context.builder(), [result_bool])
             }
             LazyMeasure => {
                 let qb = args.inputs[0];
-                // i.e. Result*
+                // i.e. Result*, read when the future is with lazy reads
                 let result = emit_qis_measure_to_result(context, qb)?;
-
-                let result_bool = emit_qis_read_result(context, result)?;
-                let result_sum = LLVMSumValue::try_new(
-                    result_bool,
-                    context.llvm_sum_type(HugrSumType::new_unary(2))?,
-                )?;
-
-                let result_i32 = result_sum.build_get_tag(context.builder())?;
-                let i1 = context.iw_context().bool_type();
-                let result_i1 = context.builder().build_int_truncate(result_i32, i1, "")?;
-                // futures are i1s, so this is fine
-                args.outputs.finish(context.builder(), [result_i1.into()])
+                self.count_live_qubits(context, -1)?;
+                let future = self.emit_future_result(context, result)?;
+                args.outputs.finish(context.builder(), [future])
             }
             MeasureReset => {
                 let qb = args.inputs[0];
@@ -61,19 +59,18 @@ impl QirCodegenExtension {
             }
             LazyMeasureReset => {
                 let qb = args.inputs[0];
-                // i.e. Result*
+                // i.e. Result*, read when the future is with lazy reads
                 let result = emit_qis_measure_to_result(context, qb)?;
                 let _ = emit_qis_gate(context, "__quantum__qis__reset__body", [], [qb])?;
-                let result_bool = emit_qis_read_result(context, result)?;
-                let result_sum = LLVMSumValue::try_new(
-                    result_bool,
-                    context.llvm_sum_type(HugrSumType::new_unary(2))?,
-                )?;
-                let result_i32 = result_sum.build_get_tag(context.builder())?;
-                let i1 = context.iw_context().bool_type();
-                let result_i1 = context.builder().build_int_truncate(result_i32, i1, "")?;
-                args.outputs
-                    .finish(context.builder(), [qb, result_i1.into()])
+                let future = self.emit_future_result(context, result)?;
+                args.outputs.finish(context.builder(), [qb, future])
+            }
+            LazyMeasureLeaked => {
+                let qb = args.inputs[0];
+                // i.e. the outcome, 0 or 1, or 2 if the qubit leaked
+                let outcome = emit_qis_measure_leaked(context, qb)?;
+                self.count_live_qubits(context, -1)?;
+                args.outputs.finish(context.builder(), [outcome])
             }
             Rz => emit_qis_gate_finish(
                 context,
@@ -96,13 +93,8 @@ impl QirCodegenExtension {
                 &args.inputs[0..2],
                 args.outputs,
             ),
-            TryQAlloc => {
-                let qb = emit_qis_qalloc(context)?;
-                let option_ty = context.llvm_sum_type(option_type(qb_t()))?;
-                let qb = option_ty.build_tag(context.builder(), 1, vec![qb])?.into();
-                args.outputs.finish(context.builder(), [qb])
-            }
-            QFree => emit_qis_qfree(context, args.inputs[0]),
+            TryQAlloc => self.emit_try_qalloc(context, args),
+            QFree => self.emit_qfree(context, args.inputs[0]),
             Reset => emit_qis_gate_finish(
                 context,
                 "__quantum__qis__reset__body",
@@ -113,6 +105,162 @@ impl QirCodegenExtension {
             _ => anyhow::bail!("Unknown op: {op:?}"),
         }
     }
+
+    /// The future of the measurement `result`: the result itself with lazy
+    /// reads, or its outcome.
+    fn emit_future_result<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        result: BasicValueEnum<'c>,
+    ) -> Result<BasicValueEnum<'c>> {
+        if self.lazy_reads {
+            Ok(result)
+        } else {
+            emit_qis_read_result(context, result)
+        }
+    }
+
+    /// Lowers `tket.qsystem.TryQAlloc`. Without a qubit capacity the
+    /// allocation always succeeds. Otherwise it checks the number of qubits in
+    /// use at runtime, which LLVM folds where allocations are static.
+    fn emit_try_qalloc<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        args: EmitOpArgs<'c, '_, ExtensionOp, H>,
+    ) -> Result<()> {
+        let option_ty = context.llvm_sum_type(option_type(qb_t()))?;
+        let (Some(capacity), Some(live)) = (self.qubit_capacity, self.live_qubits(context)?) else {
+            let qb = self.emit_qalloc(context)?;
+            let some = option_ty.build_tag(context.builder(), 1, vec![qb])?;
+            return args.outputs.finish(context.builder(), [some.into()]);
+        };
+        let func = context.func();
+        let [alloc_bb, none_bb, exit_bb] = ["alloc", "no_qubit", ""]
+            .map(|name| context.iw_context().append_basic_block(func, name));
+        let builder = context.builder();
+        let count = builder.build_load(live, "")?.into_int_value();
+        let capacity = count.get_type().const_int(capacity, false);
+        let available = builder.build_int_compare(IntPredicate::ULT, count, capacity, "")?;
+        builder.build_conditional_branch(available, alloc_bb, none_bb)?;
+
+        context.builder().position_at_end(alloc_bb);
+        let qb = self.emit_qalloc(context)?;
+        let builder = context.builder();
+        let some = option_ty.build_tag(builder, 1, vec![qb])?;
+        // allocation may have moved to another block
+        let some_bb = builder.get_insert_block().unwrap();
+        builder.build_unconditional_branch(exit_bb)?;
+
+        builder.position_at_end(none_bb);
+        let none = option_ty.build_tag(builder, 0, vec![])?;
+        builder.build_unconditional_branch(exit_bb)?;
+
+        builder.position_at_end(exit_bb);
+        let maybe_qb = builder.build_phi(some.get_type(), "")?;
+        maybe_qb.add_incoming(&[(&some, some_bb), (&none, none_bb)]);
+        args.outputs.finish(builder, [maybe_qb.as_basic_value()])
+    }
+
+    /// Lowers a `tket.qsystem.RuntimeBarrier` on an array of `n` qubits to a
+    /// call to `__quantum__qis__barrier{n}__body`, or drops it if barriers
+    /// are disabled.
+    pub fn emit_runtime_barrier<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        args: EmitOpArgs<'c, '_, ExtensionOp, H>,
+    ) -> Result<()> {
+        if !self.barriers {
+            return args.outputs.finish(context.builder(), args.inputs);
+        }
+        let Some(&TypeArg::BoundedNat(n)) = args.node().args().first() else {
+            anyhow::bail!("RuntimeBarrier expects an array size argument")
+        };
+        let array = args.inputs[0];
+        let builder = context.builder();
+        let (ptr, offset) = decompose_array_fat_pointer(builder, array)?;
+        let qbs = (0..n)
+            .map(|i| {
+                let index =
+                    builder.build_int_add(offset, offset.get_type().const_int(i, false), "")?;
+                // SAFETY: the array holds `n` qubits from `offset`
+                let elem_ptr = unsafe { builder.build_in_bounds_gep(ptr, &[index], "")? };
+                Ok(builder.build_load(elem_ptr, "")?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        emit_qis_gate(
+            context,
+            format!("__quantum__qis__barrier{n}__body"),
+            [],
+            qbs,
+        )?;
+        args.outputs.finish(context.builder(), [array])
+    }
+}
+
+/// Whether every lazy measurement in `hugr` runs at most once, so that its
+/// result can be read when its future is.
+pub fn lazy_measurements_run_once(hugr: &impl HugrView<Node = Node>) -> bool {
+    hugr.nodes()
+        .filter(|&node| {
+            hugr.get_optype(node)
+                .as_extension_op()
+                .and_then(|op| QSystemOp::from_extension_op(op).ok())
+                .is_some_and(|op| {
+                    matches!(op, QSystemOp::LazyMeasure | QSystemOp::LazyMeasureReset)
+                })
+        })
+        .all(|node| runs_once(hugr, node, &mut HashSet::new()))
+}
+
+/// Whether `node` runs at most once: it is not in a loop, and its function is
+/// only called from nodes that run at most once.
+fn runs_once(hugr: &impl HugrView<Node = Node>, node: Node, seen: &mut HashSet<Node>) -> bool {
+    if in_loop(hugr, node) {
+        return false;
+    }
+    let Some(func) = std::iter::successors(Some(node), |&n| hugr.get_parent(n))
+        .find(|&n| hugr.get_optype(n).is_func_defn())
+    else {
+        return true;
+    };
+    // Recursive functions run any number of times
+    if !seen.insert(func) {
+        return false;
+    }
+    hugr.static_targets(func)
+        .into_iter()
+        .flatten()
+        .all(|(caller, _)| hugr.get_optype(caller).is_call() && runs_once(hugr, caller, seen))
+}
+
+/// Whether `node` may run more than once per call of its function, i.e. it is
+/// in a loop or in a basic block on a cycle.
+fn in_loop(hugr: &impl HugrView<Node = Node>, node: Node) -> bool {
+    let mut node = node;
+    while let Some(parent) = hugr.get_parent(node) {
+        match hugr.get_optype(parent) {
+            OpType::TailLoop(_) => return true,
+            OpType::DataflowBlock(_) if on_cycle(hugr, parent) => return true,
+            OpType::FuncDefn(_) => return false,
+            _ => node = parent,
+        }
+    }
+    false
+}
+
+/// Whether the basic block `block` can be reached from itself.
+fn on_cycle(hugr: &impl HugrView<Node = Node>, block: Node) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = hugr.output_neighbours(block).collect_vec();
+    while let Some(n) = stack.pop() {
+        if n == block {
+            return true;
+        }
+        if seen.insert(n) {
+            stack.extend(hugr.output_neighbours(n));
+        }
+    }
+    false
 }
 
 #[cfg(test)]
@@ -125,7 +273,11 @@ mod test {
     };
     use rstest::rstest;
 
-    use tket_qsystem::extension::qsystem::QSystemOp;
+    use hugr::Hugr;
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::{option_type, qb_t};
+    use hugr::types::{Signature, Type, TypeArg};
+    use tket_qsystem::extension::qsystem::{self, QSystemOp};
 
     use crate::qir::{QirCodegenExtension, QirPreludeCodegen};
     use crate::test::single_op_hugr;
@@ -134,7 +286,7 @@ mod test {
     fn ctx(mut llvm_ctx: TestContext) -> TestContext {
         llvm_ctx.add_extensions(|builder| {
             builder
-                .add_extension(QirCodegenExtension)
+                .add_extension(QirCodegenExtension::default())
                 .add_prelude_extensions(QirPreludeCodegen)
                 .add_default_int_extensions()
                 .add_float_extensions()
@@ -153,6 +305,7 @@ mod test {
     #[case(QSystemOp::LazyMeasure)]
     #[case(QSystemOp::LazyMeasureReset)]
     #[case(QSystemOp::Measure)]
+    #[case(QSystemOp::LazyMeasureLeaked)]
     fn emit(ctx: TestContext, #[case] op: impl Into<OpType>) {
         let op = op.into();
         let mut insta = insta::Settings::clone_current();
@@ -165,4 +318,86 @@ mod test {
             check_emission!(hugr, ctx);
         })
     }
+    fn runtime_barrier(n: u64) -> OpType {
+        qsystem::EXTENSION
+            .instantiate_extension_op(&qsystem::RUNTIME_BARRIER_NAME, [TypeArg::BoundedNat(n)])
+            .unwrap()
+            .into()
+    }
+
+    #[rstest]
+    #[case(1, 1, true)]
+    #[case(2, 3, true)]
+    #[case(3, 3, false)]
+    fn emit_runtime_barrier(
+        #[case] _i: i32,
+        #[with(_i)] mut llvm_ctx: TestContext,
+        #[case] n: u64,
+        #[case] barriers: bool,
+    ) {
+        llvm_ctx.add_extensions(move |builder| {
+            builder
+                .add_extension(QirCodegenExtension::default().with_barriers(barriers))
+                .add_prelude_extensions(QirPreludeCodegen)
+                .add_default_array_extensions()
+        });
+        let hugr = single_op_hugr(runtime_barrier(n));
+        check_emission!(hugr, llvm_ctx);
+    }
+
+    /// Two qubit allocations, in a loop or not.
+    fn try_qalloc_hugr(in_loop: bool) -> Hugr {
+        let maybe_qb: Type = option_type(qb_t()).into();
+        let maybe_qbs = vec![maybe_qb.clone(), maybe_qb];
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new(vec![], maybe_qbs.clone()))
+            .unwrap();
+        let outputs = if in_loop {
+            let mut tail_loop = func.tail_loop_builder([], [], maybe_qbs.into()).unwrap();
+            let [a] = tail_loop
+                .add_dataflow_op(QSystemOp::TryQAlloc, [])
+                .unwrap()
+                .outputs_arr();
+            let [b] = tail_loop
+                .add_dataflow_op(QSystemOp::TryQAlloc, [])
+                .unwrap()
+                .outputs_arr();
+            let loop_signature = tail_loop.loop_signature().unwrap().clone();
+            let control = tail_loop.make_break(loop_signature, [a, b]).unwrap();
+            tail_loop
+                .finish_with_outputs(control, [])
+                .unwrap()
+                .outputs_arr()
+        } else {
+            let [a] = func
+                .add_dataflow_op(QSystemOp::TryQAlloc, [])
+                .unwrap()
+                .outputs_arr();
+            let [b] = func
+                .add_dataflow_op(QSystemOp::TryQAlloc, [])
+                .unwrap()
+                .outputs_arr();
+            [a, b]
+        };
+        func.finish_with_outputs(outputs).unwrap();
+        module.finish_hugr().unwrap()
+    }
+
+    #[rstest]
+    #[case(1, false)]
+    #[case(2, true)]
+    fn emit_try_qalloc_capacity(
+        #[case] _i: i32,
+        #[with(_i)] mut llvm_ctx: TestContext,
+        #[case] in_loop: bool,
+    ) {
+        llvm_ctx.add_extensions(|builder| {
+            builder
+                .add_extension(QirCodegenExtension::default().with_qubit_capacity(1))
+                .add_prelude_extensions(QirPreludeCodegen)
+        });
+        let hugr = try_qalloc_hugr(in_loop);
+        check_emission!(hugr, llvm_ctx);
+    }
 }
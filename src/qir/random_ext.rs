@@ -3,19 +3,67 @@
 use tket::hugr::{self, llvm::inkwell};
 
 use anyhow::{Result, anyhow};
+use hugr::extension::prelude::option_type;
 use hugr::llvm::custom::CodegenExtension;
 use hugr::llvm::emit::EmitOpArgs;
 use hugr::llvm::emit::func::EmitFuncContext;
+use hugr::types::Type;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::types::{BasicTypeEnum, IntType};
-use inkwell::values::{BasicValueEnum, FunctionValue};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, IntType};
+use inkwell::values::{BasicMetadataValueEnum, BasicValueEnum, FunctionValue};
 use tket::hugr::ops::ExtensionOp;
 use tket::hugr::{HugrView, Node};
 use tket_qsystem::extension::random::{self, CONTEXT_TYPE_NAME, RandomOp};
 
+/// Default prefix of the runtime functions implementing the RNG.
+pub const RANDOM_PREFIX: &str = "___random_";
+
 /// Codegen extension for `tket.qsystem.random` extension.
-pub struct RandomCodegenExtension;
+///
+/// By default the RNG is global state of the runtime and the RNG context is
+/// an empty struct. With a stateful context, the context is an opaque
+/// `%RNGContext*` created by the runtime and passed to every call.
+#[derive(Clone, Debug)]
+pub struct RandomCodegenExtension {
+    prefix: String,
+    stateful_context: bool,
+}
+
+impl Default for RandomCodegenExtension {
+    fn default() -> Self {
+        Self {
+            prefix: RANDOM_PREFIX.to_owned(),
+            stateful_context: false,
+        }
+    }
+}
+
+impl RandomCodegenExtension {
+    /// Uses `prefix` for the runtime functions instead of [RANDOM_PREFIX].
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets whether the RNG context is a runtime object passed to every call.
+    pub fn with_stateful_context(mut self, stateful_context: bool) -> Self {
+        self.stateful_context = stateful_context;
+        self
+    }
+
+    fn context_type<'c>(&self, context: &'c Context) -> BasicTypeEnum<'c> {
+        if self.stateful_context {
+            context
+                .get_struct_type("RNGContext")
+                .unwrap_or_else(|| context.opaque_struct_type("RNGContext"))
+                .ptr_type(Default::default())
+                .into()
+        } else {
+            context.struct_type(&[], false).into()
+        }
+    }
+}
 
 impl CodegenExtension for RandomCodegenExtension {
     fn add_extension<'a, H: HugrView<Node = Node> + 'a>(
@@ -25,21 +73,31 @@ impl CodegenExtension for RandomCodegenExtension {
     where
         Self: 'a,
     {
+        let type_ext = self.clone();
         builder
             .custom_type(
                 (random::EXTENSION_ID, CONTEXT_TYPE_NAME.to_owned()),
-                |session, _| Ok(rng_context_type(session.iw_context())),
+                move |session, _| Ok(type_ext.context_type(session.iw_context())),
             )
-            .simple_extension_op(|context, args, op| RandomEmitter(context).emit(args, op))
+            .simple_extension_op(move |context, args, op| {
+                RandomEmitter(context, &self).emit(args, op)
+            })
     }
 }
 
-fn rng_context_type(context: &Context) -> BasicTypeEnum<'_> {
-    context.struct_type(&[], false).into()
+/// Returns the HUGR type of the RNG context.
+fn hugr_context_type() -> Result<Type> {
+    let def = random::EXTENSION
+        .get_type(&CONTEXT_TYPE_NAME)
+        .ok_or_else(|| anyhow!("Missing type {}", *CONTEXT_TYPE_NAME))?;
+    Ok(def.instantiate([])?.into())
 }
 
 /// Lower the `tket.qsystem.random` extension.
-struct RandomEmitter<'c, 'd, 'e, H: HugrView<Node = Node>>(&'d mut EmitFuncContext<'c, 'e, H>);
+struct RandomEmitter<'c, 'd, 'e, H: HugrView<Node = Node>>(
+    &'d mut EmitFuncContext<'c, 'e, H>,
+    &'d RandomCodegenExtension,
+);
 
 impl<'c, H: HugrView<Node = Node>> RandomEmitter<'c, '_, '_, H> {
     fn iw_context(&self) -> &'c Context {
@@ -58,69 +116,123 @@ impl<'c, H: HugrView<Node = Node>> RandomEmitter<'c, '_, '_, H> {
         self.iw_context().bool_type()
     }
 
-    fn rng_context(&self) -> BasicValueEnum<'c> {
-        self.iw_context().const_struct(&[], false).into()
+    fn context_type(&self) -> BasicTypeEnum<'c> {
+        self.1.context_type(self.iw_context())
     }
 
     fn builder(&self) -> &Builder<'c> {
         self.0.builder()
     }
 
-    /// Helper function to `emit` an RNG operation.
+    /// Declares the runtime function `{prefix}{name}`, taking the RNG
+    /// context before `params` if it is stateful.
+    fn runtime_func(
+        &self,
+        name: &str,
+        ret: Option<BasicTypeEnum<'c>>,
+        params: &[BasicMetadataTypeEnum<'c>],
+    ) -> Result<FunctionValue<'c>> {
+        let mut all_params = Vec::new();
+        if self.1.stateful_context {
+            all_params.push(self.context_type().into());
+        }
+        all_params.extend_from_slice(params);
+        let fn_type = match ret {
+            Some(ret) => ret.fn_type(&all_params, false),
+            None => self.iw_context().void_type().fn_type(&all_params, false),
+        };
+        self.0
+            .get_extern_func(format!("{}{name}", self.1.prefix), fn_type)
+    }
+
+    /// Helper function to `emit` an RNG operation, which takes the context
+    /// as its first input and returns it as its last output.
     fn emit_op(
         &self,
         args: EmitOpArgs<'c, '_, ExtensionOp, H>,
         name: &str,
-        func: Result<FunctionValue<'c>>,
-        input_indices: &[usize],
+        label: &str,
+        ret: Option<BasicTypeEnum<'c>>,
+        params: &[BasicMetadataTypeEnum<'c>],
     ) -> Result<()> {
-        let inputs: Vec<_> = input_indices.iter().map(|&i| args.inputs[i]).collect();
-        let result = self
+        let func = self.runtime_func(name, ret, params)?;
+        let ctx = args.inputs[0];
+        let skip = usize::from(!self.1.stateful_context);
+        let inputs: Vec<BasicMetadataValueEnum> =
+            args.inputs[skip..].iter().map(|&v| v.into()).collect();
+        let call = self.builder().build_call(func, &inputs, label)?;
+        let outputs = match ret {
+            Some(_) => vec![call.try_as_basic_value().unwrap_left(), ctx],
+            None => vec![ctx],
+        };
+        args.outputs.finish(self.builder(), outputs)
+    }
+
+    fn emit_new_context(&self, args: EmitOpArgs<'c, '_, ExtensionOp, H>) -> Result<()> {
+        let [seed] = args
+            .inputs
+            .try_into()
+            .map_err(|_| anyhow!("NewRNGContext expects a seed argument"))?;
+        if !self.1.stateful_context {
+            let fn_random_seed = self.runtime_func("seed", None, &[self.i64_type().into()])?;
+            self.builder()
+                .build_call(fn_random_seed, &[seed.into()], "rseed")?;
+            return args.outputs.finish(
+                self.builder(),
+                [self.bool_type().const_int(1, false).into()],
+            );
+        }
+        // The runtime returns null if it cannot create another context.
+        let fn_new_context = self.0.get_extern_func(
+            format!("{}new_context", self.1.prefix),
+            self.context_type()
+                .fn_type(&[self.i64_type().into()], false),
+        )?;
+        let ctx = self
             .builder()
-            .build_call(
-                func?,
-                &inputs.iter().map(|&v| v.into()).collect::<Vec<_>>(),
-                name,
-            )?
+            .build_call(fn_new_context, &[seed.into()], "rctx")?
             .try_as_basic_value()
-            .unwrap_left();
-        args.outputs
-            .finish(self.builder(), [result, self.rng_context()])
+            .unwrap_left()
+            .into_pointer_value();
+        let option_ty = self.0.llvm_sum_type(option_type(hugr_context_type()?))?;
+        let some: BasicValueEnum = option_ty
+            .build_tag(self.builder(), 1, vec![ctx.into()])?
+            .into();
+        let none: BasicValueEnum = option_ty.build_tag(self.builder(), 0, vec![])?.into();
+        let is_some = self.builder().build_is_not_null(ctx, "")?;
+        let result = self.builder().build_select(is_some, some, none, "")?;
+        args.outputs.finish(self.builder(), [result])
     }
 
     /// Function to help lower the `tket.qsystem.random` extension.
     fn emit(&self, args: EmitOpArgs<'c, '_, ExtensionOp, H>, op: RandomOp) -> Result<()> {
         match op {
             RandomOp::RandomInt => {
-                let fn_random_int = self
-                    .0
-                    .get_extern_func("___random_int", self.i32_type().fn_type(&[], false));
-                self.emit_op(args, "rint", fn_random_int, &[])
+                self.emit_op(args, "int", "rint", Some(self.i32_type().into()), &[])
             }
-            RandomOp::RandomIntBounded => {
-                let fn_random_int_bounded = self.0.get_extern_func(
-                    "___random_int_bounded",
-                    self.i32_type().fn_type(&[self.i32_type().into()], false),
-                );
-                self.emit_op(args, "rintb", fn_random_int_bounded, &[1])
+            RandomOp::RandomIntBounded => self.emit_op(
+                args,
+                "int_bounded",
+                "rintb",
+                Some(self.i32_type().into()),
+                &[self.i32_type().into()],
+            ),
+            RandomOp::RandomFloat => self.emit_op(
+                args,
+                "float",
+                "rfloat",
+                Some(self.iw_context().f64_type().into()),
+                &[],
+            ),
+            RandomOp::RandomAdvance => {
+                self.emit_op(args, "advance", "", None, &[self.i64_type().into()])
             }
-            RandomOp::NewRNGContext => {
-                let fn_random_seed = self.0.get_extern_func(
-                    "___random_seed",
-                    self.iw_context()
-                        .void_type()
-                        .fn_type(&[self.i64_type().into()], false),
-                )?;
-                let [seed] = args
-                    .inputs
-                    .try_into()
-                    .map_err(|_| anyhow!("NewRNGContext expects a seed argument"))?;
+            RandomOp::NewRNGContext => self.emit_new_context(args),
+            RandomOp::DeleteRNGContext if self.1.stateful_context => {
+                let fn_delete_context = self.runtime_func("delete_context", None, &[])?;
                 self.builder()
-                    .build_call(fn_random_seed, &[seed.into()], "rseed")?;
-                args.outputs.finish(
-                    self.builder(),
-                    [self.bool_type().const_int(1, false).into()],
-                )
+                    .build_call(fn_delete_context, &[args.inputs[0].into()], "")?;
+                args.outputs.finish(self.builder(), [])
             }
             RandomOp::DeleteRNGContext => args.outputs.finish(self.builder(), []),
             _ => anyhow::bail!("Unknown op: {op:?}"),
@@ -144,13 +256,15 @@ mod test {
     #[case::random_int_bounded(3, RandomOp::RandomIntBounded)]
     #[case::new_rng_context(4, RandomOp::NewRNGContext)]
     #[case::delete_rng_context(5, RandomOp::DeleteRNGContext)]
+    #[case::random_float(6, RandomOp::RandomFloat)]
+    #[case::random_advance(7, RandomOp::RandomAdvance)]
     fn emit_random_codegen(
         #[case] _i: i32,
         #[with(_i)] mut llvm_ctx: TestContext,
         #[case] op: RandomOp,
     ) {
         llvm_ctx.add_extensions(|ceb| {
-            ceb.add_extension(RandomCodegenExtension)
+            ceb.add_extension(RandomCodegenExtension::default())
                 .add_default_int_extensions()
                 .add_float_extensions()
                 .add_logic_extensions()
@@ -159,4 +273,31 @@ mod test {
         let hugr = single_op_hugr(ext_op);
         check_emission!(hugr, llvm_ctx);
     }
+
+    #[rstest]
+    #[case::random_int(1, RandomOp::RandomInt)]
+    #[case::random_int_bounded(2, RandomOp::RandomIntBounded)]
+    #[case::new_rng_context(3, RandomOp::NewRNGContext)]
+    #[case::delete_rng_context(4, RandomOp::DeleteRNGContext)]
+    #[case::random_float(5, RandomOp::RandomFloat)]
+    #[case::random_advance(6, RandomOp::RandomAdvance)]
+    fn emit_stateful_random_codegen(
+        #[case] _i: i32,
+        #[with(_i)] mut llvm_ctx: TestContext,
+        #[case] op: RandomOp,
+    ) {
+        llvm_ctx.add_extensions(|ceb| {
+            ceb.add_extension(
+                RandomCodegenExtension::default()
+                    .with_prefix("__rng_")
+                    .with_stateful_context(true),
+            )
+            .add_default_int_extensions()
+            .add_float_extensions()
+            .add_logic_extensions()
+        });
+        let ext_op = op.to_extension_op().unwrap().into();
+        let hugr = single_op_hugr(ext_op);
+        check_emission!(hugr, llvm_ctx);
+    }
 }
@@ -1,4 +1,4 @@
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Result, anyhow, bail, ensure};
 use hugr::{
     HugrView, Node,
     extension::{prelude::ConstString, simple_op::MakeExtensionOp as _},
@@ -10,7 +10,7 @@ use hugr_llvm::{
     sum::LLVMSumValue,
     types::HugrSumType,
 };
-use tket_qsystem::extension::result::{ResultOp, ResultOpDef};
+use tket_qsystem::extension::result::{ResultArgs, ResultOp, ResultOpDef, SimpleArgs};
 
 use super::QirCodegenExtension;
 impl QirCodegenExtension {
@@ -25,6 +25,10 @@ impl QirCodegenExtension {
         if tag_str.is_empty() {
             bail!("Empty result tag received")
         }
+        // tabs and line breaks would corrupt the labeled output format
+        if tag_str.contains(['\t', '\n', '\r']) {
+            bail!("Result tag {tag_str:?} contains a tab or line break")
+        }
 
         let i8_ptr_ty = context
             .iw_context()
@@ -32,7 +36,7 @@ impl QirCodegenExtension {
             .ptr_type(Default::default())
             .as_basic_type_enum();
         let tag_ptr = {
-            let x = emit_value(context, &ConstString::new(tag_str).into())?;
+            let x = emit_value(context, &ConstString::new(tag_str.clone()).into())?;
             if x.get_type() == i8_ptr_ty {
                 x
             } else {
@@ -66,23 +70,39 @@ impl QirCodegenExtension {
                 args.outputs.finish(context.builder(), [])
             }
             ResultOpDef::Int | ResultOpDef::UInt => {
-                let [mut val] = args
+                let [val] = args
                     .inputs
                     .try_into()
-                    .map_err(|_| anyhow!("result_bool expects one input"))?;
-                let i64_ty = context.iw_context().i64_type();
-                if val.get_type() != i64_ty.into() {
-                    val = if op == ResultOpDef::Int {
-                        context
-                            .builder()
-                            .build_int_s_extend(val.into_int_value(), i64_ty, "")
-                    } else {
-                        context
-                            .builder()
-                            .build_int_z_extend(val.into_int_value(), i64_ty, "")
-                    }?
-                    .into();
+                    .map_err(|_| anyhow!("result_int expects one input"))?;
+                let ResultArgs::Simple(SimpleArgs::Int(log_width)) = result_op.args else {
+                    bail!("Integer result {tag_str:?} has no width")
+                };
+                let width = 1u32 << log_width;
+                // QIR records integers as i64; wider values cannot be
+                // recorded without losing bits
+                ensure!(
+                    width <= 64,
+                    "Result {tag_str:?} is a {width}-bit integer, but at most 64-bit integers can be recorded"
+                );
+                let mut val = val.into_int_value();
+                // integers narrower than a byte are stored in an i8 whose
+                // high bits must be ignored
+                let stored_width = val.get_type().get_bit_width();
+                ensure!(
+                    stored_width >= width,
+                    "Result {tag_str:?} expects a {width}-bit integer, got {}",
+                    val.get_type()
+                );
+                if stored_width > width {
+                    let int_ty = context.iw_context().custom_width_int_type(width);
+                    val = context.builder().build_int_truncate(val, int_ty, "")?;
                 }
+                let i64_ty = context.iw_context().i64_type();
+                let val = match op {
+                    _ if width == 64 => val,
+                    ResultOpDef::Int => context.builder().build_int_s_extend(val, i64_ty, "")?,
+                    _ => context.builder().build_int_z_extend(val, i64_ty, "")?,
+                };
                 let print_fn_ty = context
                     .iw_context()
                     .void_type()
@@ -128,14 +148,16 @@ impl QirCodegenExtension {
 #[cfg(test)]
 mod test {
     use hugr::ops::OpType;
+    use hugr_llvm::inkwell::context::Context;
     use hugr_llvm::{
         check_emission,
         test::{TestContext, llvm_ctx},
     };
     use rstest::rstest;
 
-    use tket_qsystem::extension::result::ResultOpDef;
+    use tket_qsystem::extension::result::{ResultOp, ResultOpDef};
 
+    use crate::CompileArgs;
     use crate::qir::{QirCodegenExtension, QirPreludeCodegen};
     use crate::test::single_op_hugr;
 
@@ -143,7 +165,7 @@ mod test {
     fn ctx(mut llvm_ctx: TestContext) -> TestContext {
         llvm_ctx.add_extensions(|builder| {
             builder
-                .add_extension(QirCodegenExtension)
+                .add_extension(QirCodegenExtension::default())
                 .add_prelude_extensions(QirPreludeCodegen)
                 .add_default_int_extensions()
                 .add_float_extensions()
@@ -165,4 +187,32 @@ mod test {
             check_emission!(hugr, ctx);
         })
     }
+
+    #[test]
+    fn tag_with_tab() {
+        let mut hugr = single_op_hugr(ResultOp::new_bool("a\tb").into());
+        let context = Context::create();
+        let err = CompileArgs::default()
+            .compile(&mut hugr, &context)
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("tab or line break"), "{err:#}");
+    }
+
+    #[rstest]
+    #[case(ResultOp::new_int("n", 0), &["= trunc i8 %\"2_01\" to i1", "= sext i1 %1 to i64"])]
+    #[case(ResultOp::new_uint("n", 2), &["= trunc i8 %\"2_01\" to i4", "= zext i4 %1 to i64"])]
+    #[case(ResultOp::new_int("n", 3), &["= sext i8 %\"2_01\" to i64"])]
+    #[case(ResultOp::new_int("n", 5), &["= sext i32 %\"2_01\" to i64"])]
+    #[case(ResultOp::new_uint("n", 6), &["@__quantum__rt__int_record_output(i64 %\"2_01\""])]
+    fn int_widths(#[case] op: ResultOp, #[case] expected: &[&str]) {
+        let hugr = single_op_hugr(op.into());
+        let context = Context::create();
+        let module = CompileArgs::default()
+            .hugr_to_llvm(&hugr, &context)
+            .unwrap();
+        let ir = module.print_to_string().to_string();
+        for line in expected {
+            assert!(ir.contains(line), "{ir}");
+        }
+    }
 }
@@ -1,13 +1,27 @@
-use crate::qir::{
-    emit_qis_gate_finish, emit_qis_measure_to_result, emit_qis_qalloc, emit_qis_qfree,
-    emit_qis_read_result,
-};
+use crate::qir::{emit_qis_gate_finish, emit_qis_measure_to_result, emit_qis_read_result};
+use std::f64::consts::PI;
+
 use anyhow::{Result, bail};
 use hugr::{HugrView, Node, ops::ExtensionOp};
 use hugr_llvm::emit::{EmitFuncContext, EmitOpArgs};
+use hugr_llvm::inkwell::values::BasicValueEnum;
 
 use super::QirCodegenExtension;
 
+/// Converts a rotation, lowered to a number of half-turns, to the radians
+/// expected by the QIS rotation gates.
+fn radians<'c, H: HugrView<Node = Node>>(
+    context: &mut EmitFuncContext<'c, '_, H>,
+    rotation: BasicValueEnum<'c>,
+) -> Result<BasicValueEnum<'c>> {
+    let half_turns = rotation.into_float_value();
+    let pi = half_turns.get_type().const_float(PI);
+    Ok(context
+        .builder()
+        .build_float_mul(half_turns, pi, "radians")?
+        .into())
+}
+
 impl QirCodegenExtension {
     #[allow(non_snake_case)]
     pub fn emit_tket_op<'c, H: HugrView<Node = Node>>(
@@ -95,27 +109,36 @@ impl QirCodegenExtension {
                 args.inputs,
                 args.outputs,
             ),
-            Rx => emit_qis_gate_finish(
-                context,
-                "__quantum__qis__rx__body",
-                &args.inputs[1..2],
-                &args.inputs[0..1],
-                args.outputs,
-            ),
-            Ry => emit_qis_gate_finish(
-                context,
-                "__quantum__qis__ry__body",
-                &args.inputs[1..2],
-                &args.inputs[0..1],
-                args.outputs,
-            ),
-            Rz => emit_qis_gate_finish(
-                context,
-                "__quantum__qis__rz__body",
-                &args.inputs[1..2],
-                &args.inputs[0..1],
-                args.outputs,
-            ),
+            Rx => {
+                let angle = radians(context, args.inputs[1])?;
+                emit_qis_gate_finish(
+                    context,
+                    "__quantum__qis__rx__body",
+                    [angle],
+                    &args.inputs[0..1],
+                    args.outputs,
+                )
+            }
+            Ry => {
+                let angle = radians(context, args.inputs[1])?;
+                emit_qis_gate_finish(
+                    context,
+                    "__quantum__qis__ry__body",
+                    [angle],
+                    &args.inputs[0..1],
+                    args.outputs,
+                )
+            }
+            Rz => {
+                let angle = radians(context, args.inputs[1])?;
+                emit_qis_gate_finish(
+                    context,
+                    "__quantum__qis__rz__body",
+                    [angle],
+                    &args.inputs[0..1],
+                    args.outputs,
+                )
+            }
             Reset => emit_qis_gate_finish(
                 context,
                 "__quantum__qis__reset__body",
@@ -135,15 +158,15 @@ impl QirCodegenExtension {
                 let qb = args.inputs[0];
                 // i.e. Result*
                 let result = emit_qis_measure_to_result(context, qb)?;
-                emit_qis_qfree(context, qb)?;
+                self.emit_qfree(context, qb)?;
                 let result_i1 = emit_qis_read_result(context, result)?;
                 args.outputs.finish(context.builder(), [result_i1])
             }
             QAlloc => {
-                let qb = emit_qis_qalloc(context)?;
+                let qb = self.emit_qalloc(context)?;
                 args.outputs.finish(context.builder(), [qb])
             }
-            QFree => emit_qis_qfree(context, args.inputs[0]),
+            QFree => self.emit_qfree(context, args.inputs[0]),
             _ => bail!("Unknown op: {op:?}"),
         }
     }
@@ -159,7 +182,7 @@ mod test {
     use rstest::rstest;
     use tket::TketOp;
 
-    use crate::qir::boolcodegenextension_workaround::BoolCodegenExtension;
+    use crate::qir::bool_ext::BoolCodegenExtension;
     use crate::test::single_op_hugr;
     use crate::{
         qir::{QirCodegenExtension, QirPreludeCodegen},
@@ -170,7 +193,7 @@ mod test {
     fn ctx(mut llvm_ctx: TestContext) -> TestContext {
         llvm_ctx.add_extensions(|builder| {
             builder
-                .add_extension(QirCodegenExtension)
+                .add_extension(QirCodegenExtension::default())
                 .add_prelude_extensions(QirPreludeCodegen)
                 .add_extension(RotationCodegenExtension::new(QirPreludeCodegen))
                 .add_extension(BoolCodegenExtension)
@@ -0,0 +1,80 @@
+use anyhow::Result;
+use hugr::{HugrView, Node, ops::ExtensionOp};
+use hugr_llvm::emit::{EmitFuncContext, EmitOpArgs};
+use tket_qsystem::extension::utils::UtilsOp;
+
+use super::QirCodegenExtension;
+
+impl QirCodegenExtension {
+    pub fn emit_utils_op<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        args: EmitOpArgs<'c, '_, ExtensionOp, H>,
+        op: UtilsOp,
+    ) -> Result<()> {
+        match op {
+            UtilsOp::GetCurrentShot => {
+                // fn get_current_shot() -> i64
+                let shot_t = context.iw_context().i64_type().fn_type(&[], false);
+                let shot_func = context.get_extern_func(&self.current_shot_func, shot_t)?;
+                let shot = context
+                    .builder()
+                    .build_call(shot_func, &[], "shot")?
+                    .try_as_basic_value()
+                    .unwrap_left();
+                args.outputs.finish(context.builder(), [shot])
+            }
+            _ => anyhow::bail!("Unknown op: {op:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hugr::ops::OpType;
+    use hugr_llvm::{
+        check_emission,
+        test::{TestContext, llvm_ctx},
+    };
+    use rstest::rstest;
+
+    use tket_qsystem::extension::utils::UtilsOp;
+
+    use crate::qir::{QirCodegenExtension, QirPreludeCodegen};
+    use crate::test::single_op_hugr;
+
+    #[rstest::fixture]
+    fn ctx(mut llvm_ctx: TestContext) -> TestContext {
+        llvm_ctx.add_extensions(|builder| {
+            builder
+                .add_extension(QirCodegenExtension::default())
+                .add_prelude_extensions(QirPreludeCodegen)
+                .add_default_int_extensions()
+        });
+        llvm_ctx
+    }
+
+    #[rstest]
+    #[case(UtilsOp::GetCurrentShot)]
+    fn emit(ctx: TestContext, #[case] op: impl Into<OpType>) {
+        let op = op.into();
+        let mut insta = insta::Settings::clone_current();
+        insta.set_snapshot_suffix(format!("{}_{}", insta.snapshot_suffix().unwrap_or(""), op));
+        insta.bind(|| {
+            let hugr = single_op_hugr(op);
+            check_emission!(hugr, ctx);
+        })
+    }
+
+    #[rstest]
+    fn emit_custom_current_shot_func(mut llvm_ctx: TestContext) {
+        llvm_ctx.add_extensions(|builder| {
+            builder
+                .add_extension(QirCodegenExtension::default().with_current_shot_func("shot_index"))
+                .add_prelude_extensions(QirPreludeCodegen)
+                .add_default_int_extensions()
+        });
+        let hugr = single_op_hugr(UtilsOp::GetCurrentShot.into());
+        check_emission!(hugr, llvm_ctx);
+    }
+}
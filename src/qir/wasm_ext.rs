@@ -0,0 +1,558 @@
+//! LLVM lowering implementations for the "tket.wasm" extension.
+//!
+//! WASM functions become declarations of external functions with the `wasm`
+//! attribute, called directly when the function they are looked up from is
+//! known statically. WASM contexts are identified by their `usize` id and
+//! obtained and disposed of by calls to the runtime.
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD;
+use hugr::IncomingPort;
+use hugr::extension::prelude::option_type;
+use hugr::extension::simple_op::MakeExtensionOp as _;
+use hugr::llvm::custom::CodegenExtension;
+use hugr::llvm::emit::EmitOpArgs;
+use hugr::llvm::emit::func::EmitFuncContext;
+use hugr::llvm::types::TypingSession;
+use hugr::ops::ExtensionOp;
+use hugr::types::{CustomType, Type, TypeRow, TypeRowRV};
+use hugr::{HugrView, Node};
+use inkwell::attributes::AttributeLoc;
+use inkwell::context::Context;
+use inkwell::types::{BasicMetadataTypeEnum, BasicType as _, BasicTypeEnum, FunctionType};
+use inkwell::values::{
+    BasicMetadataValueEnum, BasicValueEnum, CallableValue, FunctionValue, StructValue,
+};
+use itertools::Itertools as _;
+use serde::Deserialize;
+use tket::hugr::{self, llvm::inkwell};
+use tket_qsystem::extension::classical_compute::wasm::{
+    self, ConstWasmModule, WasmOp, WasmOpDef, WasmType,
+};
+
+/// Default prefix of the runtime functions managing WASM contexts.
+pub const WASM_PREFIX: &str = "___wasm_";
+
+/// Name of the module metadata listing the WASM modules used by the program.
+pub const WASM_MODULES_METADATA: &str = "wasm_modules";
+
+/// How the arguments of a WASM function are passed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmAbi {
+    /// The inputs of the function are its arguments.
+    #[default]
+    Plain,
+    /// The id of the context is passed before the inputs of the function.
+    Context,
+}
+
+/// What is recorded about the WASM modules used by the program in the
+/// [WASM_MODULES_METADATA] module metadata.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WasmModuleMetadata {
+    /// Nothing.
+    None,
+    /// The file name of each module.
+    #[default]
+    Reference,
+    /// The file name of each module, followed by its contents base64 encoded.
+    Embed,
+}
+
+/// Codegen extension for the `tket.wasm` extension.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmCodegenExtension {
+    /// Prefix of the runtime functions managing contexts.
+    prefix: String,
+    /// Prefix of the declarations of WASM functions.
+    func_prefix: String,
+    abi: WasmAbi,
+    module_metadata: WasmModuleMetadata,
+}
+
+impl Default for WasmCodegenExtension {
+    fn default() -> Self {
+        Self {
+            prefix: WASM_PREFIX.to_owned(),
+            func_prefix: String::new(),
+            abi: WasmAbi::default(),
+            module_metadata: WasmModuleMetadata::default(),
+        }
+    }
+}
+
+impl WasmCodegenExtension {
+    /// Uses `prefix` for the runtime functions instead of [WASM_PREFIX].
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Prepends `func_prefix` to the names of WASM functions.
+    pub fn with_func_prefix(mut self, func_prefix: impl Into<String>) -> Self {
+        self.func_prefix = func_prefix.into();
+        self
+    }
+
+    /// Sets how the arguments of WASM functions are passed.
+    pub fn with_abi(mut self, abi: WasmAbi) -> Self {
+        self.abi = abi;
+        self
+    }
+
+    /// Sets what is recorded about the WASM modules used by the program.
+    pub fn with_module_metadata(mut self, module_metadata: WasmModuleMetadata) -> Self {
+        self.module_metadata = module_metadata;
+        self
+    }
+
+    fn convert_type<'c>(
+        &self,
+        session: TypingSession<'c, '_>,
+        custom_type: &CustomType,
+    ) -> Result<BasicTypeEnum<'c>> {
+        let iw_ctx = session.iw_context();
+        let llvm_types = |row: TypeRowRV| -> Result<Vec<BasicTypeEnum<'c>>> {
+            concrete_row(row)?
+                .iter()
+                .map(|ty| session.llvm_type(ty))
+                .collect()
+        };
+        Ok(match WasmType::try_from(custom_type.clone())? {
+            WasmType::Module => iw_ctx.struct_type(&[], false).into(),
+            WasmType::Context => iw_ctx.i64_type().into(),
+            WasmType::Func { inputs, outputs } => self
+                .func_type(iw_ctx, &llvm_types(inputs)?, &llvm_types(outputs)?)
+                .ptr_type(Default::default())
+                .into(),
+            WasmType::Result { outputs } => result_type(iw_ctx, &llvm_types(outputs)?).into(),
+            WasmType::_Unreachable(x, _) => match x {},
+        })
+    }
+
+    /// The type of the declaration of a WASM function.
+    fn func_type<'c>(
+        &self,
+        context: &'c Context,
+        inputs: &[BasicTypeEnum<'c>],
+        outputs: &[BasicTypeEnum<'c>],
+    ) -> FunctionType<'c> {
+        let mut params: Vec<BasicMetadataTypeEnum> = Vec::new();
+        if self.abi == WasmAbi::Context {
+            params.push(context.i64_type().into());
+        }
+        params.extend(inputs.iter().map(|&ty| BasicMetadataTypeEnum::from(ty)));
+        match outputs {
+            [] => context.void_type().fn_type(&params, false),
+            [output] => output.fn_type(&params, false),
+            outputs => context.struct_type(outputs, false).fn_type(&params, false),
+        }
+    }
+}
+
+impl CodegenExtension for WasmCodegenExtension {
+    fn add_extension<'a, H: HugrView<Node = Node> + 'a>(
+        self,
+        builder: hugr::llvm::CodegenExtsBuilder<'a, H>,
+    ) -> hugr::llvm::CodegenExtsBuilder<'a, H>
+    where
+        Self: 'a,
+    {
+        let (type_ext, const_ext) = (self.clone(), self.clone());
+        let type_keys = [
+            wasm::MODULE_TYPE_NAME.to_owned(),
+            wasm::CONTEXT_TYPE_NAME.to_owned(),
+            wasm::FUNC_TYPE_NAME.to_owned(),
+            wasm::RESULT_TYPE_NAME.to_owned(),
+        ];
+        type_keys
+            .into_iter()
+            .fold(builder, |builder, name| {
+                let type_ext = type_ext.clone();
+                builder.custom_type((wasm::EXTENSION_ID, name), move |session, custom_type| {
+                    type_ext.convert_type(session, custom_type)
+                })
+            })
+            .custom_const::<ConstWasmModule>(move |context, module| {
+                const_ext.emit_const_module(context, module)
+            })
+            .simple_extension_op::<WasmOpDef>(move |context, args, _| {
+                WasmEmitter(context, &self).emit(args)
+            })
+    }
+}
+
+/// The row of a WASM function signature, which must not contain row
+/// variables once the program is monomorphized.
+fn concrete_row(row: TypeRowRV) -> Result<TypeRow> {
+    TypeRow::try_from(row).map_err(|_| anyhow!("WASM signatures must not contain row variables"))
+}
+
+/// The representation of `tket.wasm.result`: the id of the context followed
+/// by the outputs of the call.
+fn result_type<'c>(
+    context: &'c Context,
+    outputs: &[BasicTypeEnum<'c>],
+) -> inkwell::types::StructType<'c> {
+    let fields: Vec<_> = [context.i64_type().into()]
+        .into_iter()
+        .chain(outputs.iter().copied())
+        .collect();
+    context.struct_type(&fields, false)
+}
+
+impl WasmCodegenExtension {
+    /// Records the module in the module metadata, and returns the (empty)
+    /// module value.
+    fn emit_const_module<'c, H: HugrView<Node = Node>>(
+        &self,
+        context: &mut EmitFuncContext<'c, '_, H>,
+        module: &ConstWasmModule,
+    ) -> Result<BasicValueEnum<'c>> {
+        let iw_ctx = context.iw_context();
+        let module_value = iw_ctx.const_struct(&[], false).into();
+        if self.module_metadata == WasmModuleMetadata::None {
+            return Ok(module_value);
+        }
+        let filename = module.module_filename.as_str();
+        let llvm_module = context.get_current_module();
+        let recorded = llvm_module
+            .get_global_metadata(WASM_MODULES_METADATA)
+            .iter()
+            .any(|node| {
+                matches!(node.get_node_values().first(),
+                    Some(BasicMetadataValueEnum::MetadataValue(name))
+                        if name.get_string_value().and_then(|s| s.to_str().ok()) == Some(filename))
+            });
+        if recorded {
+            return Ok(module_value);
+        }
+        let mut values = vec![iw_ctx.metadata_string(filename).into()];
+        if self.module_metadata == WasmModuleMetadata::Embed {
+            let bytes = std::fs::read(filename)
+                .with_context(|| format!("Failed to read WASM module {filename}"))?;
+            values.push(iw_ctx.metadata_string(&STANDARD.encode(bytes)).into());
+        }
+        llvm_module
+            .add_global_metadata(WASM_MODULES_METADATA, &iw_ctx.metadata_node(&values))
+            .map_err(|e| anyhow!(e))?;
+        Ok(module_value)
+    }
+}
+
+/// Lower the `tket.wasm` extension.
+struct WasmEmitter<'c, 'd, 'e, H: HugrView<Node = Node>>(
+    &'d mut EmitFuncContext<'c, 'e, H>,
+    &'d WasmCodegenExtension,
+);
+
+impl<'c, H: HugrView<Node = Node>> WasmEmitter<'c, '_, '_, H> {
+    fn iw_context(&self) -> &'c Context {
+        self.0.iw_context()
+    }
+
+    fn llvm_types(&self, row: &TypeRow) -> Result<Vec<BasicTypeEnum<'c>>> {
+        row.iter().map(|ty| self.0.llvm_type(ty)).collect()
+    }
+
+    /// Declares the runtime function `{prefix}{name}`.
+    fn runtime_func(&self, name: &str, fn_type: FunctionType<'c>) -> Result<FunctionValue<'c>> {
+        self.0
+            .get_extern_func(format!("{}{name}", self.1.prefix), fn_type)
+    }
+
+    /// Declares the WASM function that `op` looks up, if it is a lookup.
+    fn lookup_func(&self, op: &WasmOp) -> Result<Option<FunctionValue<'c>>> {
+        let (name, inputs, outputs) = match op {
+            WasmOp::LookupByName {
+                name,
+                inputs,
+                outputs,
+            } => (name.clone(), inputs, outputs),
+            WasmOp::LookupById {
+                id,
+                inputs,
+                outputs,
+            } => (format!("func_{id}"), inputs, outputs),
+            _ => return Ok(None),
+        };
+        let inputs = self.llvm_types(&concrete_row(inputs.clone())?)?;
+        let outputs = self.llvm_types(&concrete_row(outputs.clone())?)?;
+        let fn_type = self.1.func_type(self.iw_context(), &inputs, &outputs);
+        let func = self
+            .0
+            .get_extern_func(format!("{}{name}", self.1.func_prefix), fn_type)?;
+        func.add_attribute(
+            AttributeLoc::Function,
+            self.iw_context().create_string_attribute("wasm", ""),
+        );
+        Ok(Some(func))
+    }
+
+    /// The function called by the `call` op `args`: the WASM function itself
+    /// if it is looked up in the same region, or else the function pointer.
+    fn callee(&self, args: &EmitOpArgs<'c, '_, ExtensionOp, H>) -> Result<CallableValue<'c>> {
+        let lookup = args
+            .node()
+            .single_linked_output(IncomingPort::from(1))
+            .and_then(|(src, _)| src.as_extension_op().cloned())
+            .and_then(|op| WasmOp::from_extension_op(&op).ok());
+        if let Some(func) = lookup
+            .map(|op| self.lookup_func(&op))
+            .transpose()?
+            .flatten()
+        {
+            return Ok(func.into());
+        }
+        CallableValue::try_from(args.inputs[1].into_pointer_value())
+            .map_err(|_| anyhow!("WASM function is not a function pointer"))
+    }
+
+    fn emit_get_context(&self, args: EmitOpArgs<'c, '_, ExtensionOp, H>) -> Result<()> {
+        let [id] = args
+            .inputs
+            .try_into()
+            .map_err(|_| anyhow!("get_context expects an id"))?;
+        let iw_ctx = self.iw_context();
+        // The runtime returns false if the context is already in use.
+        let get_context = self.runtime_func(
+            "get_context",
+            iw_ctx
+                .bool_type()
+                .fn_type(&[iw_ctx.i64_type().into()], false),
+        )?;
+        let ok = self
+            .0
+            .builder()
+            .build_call(get_context, &[id.into()], "")?
+            .try_as_basic_value()
+            .unwrap_left()
+            .into_int_value();
+        let option_ty = self
+            .0
+            .llvm_sum_type(option_type(Type::from(WasmType::Context)))?;
+        let some: BasicValueEnum = option_ty.build_tag(self.0.builder(), 1, vec![id])?.into();
+        let none: BasicValueEnum = option_ty.build_tag(self.0.builder(), 0, vec![])?.into();
+        let context = self.0.builder().build_select(ok, some, none, "")?;
+        args.outputs.finish(self.0.builder(), [context])
+    }
+
+    fn emit_call(&self, args: EmitOpArgs<'c, '_, ExtensionOp, H>, outputs: &TypeRow) -> Result<()> {
+        let callee = self.callee(&args)?;
+        let context = args.inputs[0];
+        let call_args: Vec<BasicMetadataValueEnum> = (self.1.abi == WasmAbi::Context)
+            .then_some(context)
+            .into_iter()
+            .chain(args.inputs[2..].iter().copied())
+            .map_into()
+            .collect();
+        let builder = self.0.builder();
+        let ret = builder
+            .build_call(callee, &call_args, "")?
+            .try_as_basic_value();
+        let output_values = match (outputs.len(), ret.left()) {
+            (0, _) => vec![],
+            (1, Some(value)) => vec![value],
+            (n, Some(BasicValueEnum::StructValue(value))) => (0..n as u32)
+                .map(|i| Ok(builder.build_extract_value(value, i, "")?))
+                .collect::<Result<_>>()?,
+            _ => bail!("Unexpected return value of WASM function"),
+        };
+        let result_ty = result_type(self.iw_context(), &self.llvm_types(outputs)?);
+        let mut result: StructValue = result_ty.get_undef();
+        for (i, value) in [context].into_iter().chain(output_values).enumerate() {
+            result = builder
+                .build_insert_value(result, value, i as u32, "")?
+                .into_struct_value();
+        }
+        args.outputs.finish(builder, [result.into()])
+    }
+
+    fn emit_read_result(
+        &self,
+        args: EmitOpArgs<'c, '_, ExtensionOp, H>,
+        outputs: &TypeRow,
+    ) -> Result<()> {
+        let result = args.inputs[0].into_struct_value();
+        let builder = self.0.builder();
+        let values = (0..=outputs.len() as u32)
+            .map(|i| Ok(builder.build_extract_value(result, i, "")?))
+            .collect::<Result<Vec<_>>>()?;
+        args.outputs.finish(builder, values)
+    }
+
+    fn emit(&self, args: EmitOpArgs<'c, '_, ExtensionOp, H>) -> Result<()> {
+        let op = WasmOp::from_extension_op(&args.node())?;
+        match &op {
+            WasmOp::GetContext => self.emit_get_context(args),
+            WasmOp::DisposeContext => {
+                let iw_ctx = self.iw_context();
+                let dispose_context = self.runtime_func(
+                    "dispose_context",
+                    iw_ctx
+                        .void_type()
+                        .fn_type(&[iw_ctx.i64_type().into()], false),
+                )?;
+                self.0
+                    .builder()
+                    .build_call(dispose_context, &[args.inputs[0].into()], "")?;
+                args.outputs.finish(self.0.builder(), [])
+            }
+            WasmOp::LookupById { .. } | WasmOp::LookupByName { .. } => {
+                let func = self
+                    .lookup_func(&op)?
+                    .ok_or_else(|| anyhow!("Expected a lookup"))?;
+                let ptr = func.as_global_value().as_pointer_value();
+                args.outputs.finish(self.0.builder(), [ptr.into()])
+            }
+            WasmOp::Call { outputs, .. } => self.emit_call(args, outputs),
+            WasmOp::ReadResult { outputs } => self.emit_read_result(args, outputs),
+            WasmOp::_Unreachable(x, _) => match *x {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write as _;
+
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::{ConstUsize, UnwrapBuilder as _};
+    use hugr::extension::simple_op::MakeRegisteredOp as _;
+    use hugr::llvm::check_emission;
+    use hugr::llvm::test::{TestContext, llvm_ctx};
+    use hugr::ops::Value;
+    use hugr::std_extensions::arithmetic::int_types::{ConstInt, int_type};
+    use hugr::types::Signature;
+    use hugr::{Hugr, type_row};
+    use rstest::rstest;
+    use tket_qsystem::extension::classical_compute::wasm::{ConstWasmBuilder as _, WasmOpBuilder};
+    use tket_qsystem::extension::result::ResultOp;
+
+    use super::*;
+    use crate::CompileArgs;
+    use crate::qir::QirPreludeCodegen;
+    use crate::target::description::TargetDescription;
+    use crate::test::single_op_hugr;
+
+    fn add_inputs() -> TypeRow {
+        vec![int_type(6), int_type(6)].into()
+    }
+
+    fn add_outputs() -> TypeRow {
+        vec![int_type(6)].into()
+    }
+
+    #[rstest]
+    #[case::get_context(1, WasmOp::GetContext)]
+    #[case::dispose_context(2, WasmOp::DisposeContext)]
+    #[case::lookup_by_name(3, WasmOp::LookupByName {
+        name: "add".into(),
+        inputs: add_inputs().into(),
+        outputs: add_outputs().into(),
+    })]
+    #[case::lookup_by_id(4, WasmOp::LookupById {
+        id: 3,
+        inputs: add_inputs().into(),
+        outputs: add_outputs().into(),
+    })]
+    #[case::call(5, WasmOp::Call { inputs: add_inputs(), outputs: add_outputs() })]
+    #[case::call_no_outputs(6, WasmOp::Call { inputs: type_row![], outputs: type_row![] })]
+    #[case::read_result(7, WasmOp::ReadResult { outputs: add_outputs() })]
+    fn emit_wasm_codegen(
+        #[case] _i: i32,
+        #[with(_i)] mut llvm_ctx: TestContext,
+        #[case] op: WasmOp,
+    ) {
+        llvm_ctx.add_extensions(|ceb| {
+            ceb.add_extension(WasmCodegenExtension::default())
+                .add_prelude_extensions(QirPreludeCodegen)
+                .add_default_int_extensions()
+        });
+        let hugr = single_op_hugr(op.to_extension_op().unwrap().into());
+        check_emission!(hugr, llvm_ctx);
+    }
+
+    /// Adds two numbers with the WASM function "add" of `wasm_file` and
+    /// records the sum as "sum".
+    fn add_hugr(wasm_file: &str) -> Hugr {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let id = func.add_load_value(ConstUsize::new(0));
+        let maybe_context = func.add_get_context(id).unwrap();
+        let [context] = func
+            .build_unwrap_sum(1, option_type(Type::from(WasmType::Context)), maybe_context)
+            .unwrap();
+        let wasm_module = func.add_const_module(wasm_file).unwrap();
+        let add = func
+            .add_lookup_by_name("add", add_inputs(), add_outputs(), wasm_module)
+            .unwrap();
+        let args = [1, 2].map(|i| func.add_load_value(Value::from(ConstInt::new_s(6, i).unwrap())));
+        let result = func.add_call(context, add, args).unwrap();
+        let (context, sum) = func.add_read_result(result).unwrap();
+        func.add_dispose_context(context).unwrap();
+        func.add_dataflow_op(ResultOp::new_int("sum", 6), sum)
+            .unwrap();
+        func.finish_with_outputs([]).unwrap();
+        module.finish_hugr().unwrap()
+    }
+
+    fn compile_add(wasm: &str, wasm_file: &str) -> Result<String> {
+        let desc = format!(r#"{{"wasm": {wasm}}}"#);
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(&desc).unwrap()),
+            ..Default::default()
+        };
+        let context = Context::create();
+        let module = args.compile(&mut add_hugr(wasm_file), &context)?;
+        module.verify().unwrap();
+        Ok(module.print_to_string().to_string())
+    }
+
+    #[rstest]
+    #[case::plain("{}", "call i64 @add(i64 1, i64 2)")]
+    #[case::context(r#"{"abi": "context"}"#, "declare i64 @add(i64, i64, i64)")]
+    #[case::func_prefix(r#"{"func_prefix": "wasm_"}"#, "call i64 @wasm_add(i64 1, i64 2)")]
+    fn calls(#[case] wasm: &str, #[case] call: &str) {
+        let ir = compile_add(wasm, "adder.wasm").unwrap();
+        assert!(ir.contains(call), "{ir}");
+        assert!(ir.contains(r#"= { "wasm" }"#), "{ir}");
+        assert!(ir.contains("call i1 @___wasm_get_context(i64 0)"), "{ir}");
+        assert!(
+            ir.contains("call void @___wasm_dispose_context(i64"),
+            "{ir}"
+        );
+    }
+
+    #[rstest]
+    #[case::none(r#"{"module_metadata": "none"}"#, None)]
+    #[case::reference("{}", Some(r#"!{!"adder.wasm"}"#))]
+    fn module_metadata(#[case] wasm: &str, #[case] expected: Option<&str>) {
+        let ir = compile_add(wasm, "adder.wasm").unwrap();
+        assert_eq!(ir.contains("!wasm_modules"), expected.is_some(), "{ir}");
+        if let Some(expected) = expected {
+            assert!(ir.contains(expected), "{ir}");
+        }
+    }
+
+    #[test]
+    fn embed_module() {
+        let mut file = tempfile::Builder::new().suffix(".wasm").tempfile().unwrap();
+        file.write_all(b"\0asm").unwrap();
+        let path = file.path().to_str().unwrap();
+        let ir = compile_add(r#"{"module_metadata": "embed"}"#, path).unwrap();
+        assert!(
+            ir.contains(&format!(r#"!{{!"{path}", !"AGFzbQ=="}}"#)),
+            "{ir}"
+        );
+
+        let err = compile_add(r#"{"module_metadata": "embed"}"#, "missing.wasm").unwrap_err();
+        assert!(format!("{err:#}").contains("missing.wasm"), "{err:#}");
+    }
+}
@@ -0,0 +1,559 @@
+//! A reference interpreter for HUGRs.
+//!
+//! The interpreter evaluates a HUGR directly, without lowering it, so that
+//! the results of compiled programs can be checked against the semantics of
+//! the HUGR they were compiled from (see [crate::difftest]). Quantum
+//! operations are simulated with a [QuantumBackend] of the runtime, but are
+//! mapped to gates here independently of the QIR lowering.
+//!
+//! Randomness of the program is seeded by `NewRNGContext` exactly as in the
+//! runtime, so classical values derived from it agree with those of the
+//! compiled program.
+
+mod ops;
+
+use std::collections::HashMap;
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use hugr::extension::prelude::{ConstError, ConstString, ConstUsize};
+use hugr::ops::{OpType, Value as ConstValue};
+use hugr::std_extensions::arithmetic::float_types::ConstF64;
+use hugr::std_extensions::arithmetic::int_types::ConstInt;
+use hugr::{HugrView, IncomingPort, Node, OutgoingPort};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
+use tket::extension::bool::ConstBool;
+use tket::extension::rotation::ConstRotation;
+
+use crate::find_hugr_entry_point;
+use crate::results::{Shot, ShotOutput};
+use crate::run::RunConfig;
+use crate::runtime::{ProgramRng, QuantumBackend};
+
+/// A value on a wire.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// A sum value, including tuples and `bool_t`.
+    Sum {
+        tag: usize,
+        values: Vec<Value>,
+    },
+    /// A `tket.bool`.
+    Bool(bool),
+    /// An integer of any width, zero-extended to 64 bits.
+    Int(u64),
+    Float(f64),
+    /// A rotation, in half-turns.
+    Rotation(f64),
+    Qubit(usize),
+    Future(Box<Value>),
+    RngContext,
+    Function(Node),
+    String(String),
+    Error(String),
+}
+
+impl Value {
+    pub fn unit() -> Self {
+        Self::tuple(vec![])
+    }
+
+    pub fn tuple(values: Vec<Value>) -> Self {
+        Self::Sum { tag: 0, values }
+    }
+
+    /// A `bool_t` value.
+    pub fn from_bool(b: bool) -> Self {
+        Self::Sum {
+            tag: b as usize,
+            values: vec![],
+        }
+    }
+
+    fn into_sum(self) -> Result<(usize, Vec<Value>)> {
+        match self {
+            Self::Sum { tag, values } => Ok((tag, values)),
+            _ => bail!("Expected a sum, got {self:?}"),
+        }
+    }
+
+    /// Reads a `bool_t` value.
+    fn as_bool(&self) -> Result<bool> {
+        match self {
+            Self::Sum { tag, values } if *tag < 2 && values.is_empty() => Ok(*tag == 1),
+            _ => bail!("Expected a bool, got {self:?}"),
+        }
+    }
+
+    fn as_opaque_bool(&self) -> Result<bool> {
+        match self {
+            Self::Bool(b) => Ok(*b),
+            _ => bail!("Expected a tket.bool, got {self:?}"),
+        }
+    }
+
+    fn as_int(&self) -> Result<u64> {
+        match self {
+            Self::Int(v) => Ok(*v),
+            _ => bail!("Expected an integer, got {self:?}"),
+        }
+    }
+
+    fn as_float(&self) -> Result<f64> {
+        match self {
+            Self::Float(v) => Ok(*v),
+            _ => bail!("Expected a float, got {self:?}"),
+        }
+    }
+
+    fn as_rotation(&self) -> Result<f64> {
+        match self {
+            Self::Rotation(v) => Ok(*v),
+            _ => bail!("Expected a rotation, got {self:?}"),
+        }
+    }
+
+    pub(crate) fn as_qubit(&self) -> Result<usize> {
+        match self {
+            Self::Qubit(q) => Ok(*q),
+            _ => bail!("Expected a qubit, got {self:?}"),
+        }
+    }
+
+    fn from_const(value: &ConstValue) -> Result<Self> {
+        match value {
+            ConstValue::Sum(sum) => Ok(Self::Sum {
+                tag: sum.tag,
+                values: sum
+                    .values
+                    .iter()
+                    .map(Self::from_const)
+                    .collect::<Result<_>>()?,
+            }),
+            ConstValue::Extension { e } => {
+                let c = e.value();
+                if let Some(c) = c.downcast_ref::<ConstInt>() {
+                    Ok(Self::Int(c.value_u()))
+                } else if let Some(c) = c.downcast_ref::<ConstF64>() {
+                    Ok(Self::Float(c.value()))
+                } else if let Some(c) = c.downcast_ref::<ConstUsize>() {
+                    Ok(Self::Int(c.value()))
+                } else if let Some(c) = c.downcast_ref::<ConstRotation>() {
+                    Ok(Self::Rotation(c.half_turns()))
+                } else if let Some(c) = c.downcast_ref::<ConstBool>() {
+                    Ok(Self::Bool(c.value()))
+                } else if let Some(c) = c.downcast_ref::<ConstString>() {
+                    Ok(Self::String(c.value().to_string()))
+                } else if let Some(c) = c.downcast_ref::<ConstError>() {
+                    Ok(Self::Error(c.message.clone()))
+                } else {
+                    bail!("Constant {} is not supported", c.name())
+                }
+            }
+            ConstValue::Function { .. } => bail!("Function constants are not supported"),
+        }
+    }
+}
+
+/// Runs the entry point of `hugr` for the configured number of shots.
+///
+/// Shots are seeded as in [crate::runtime::run_shots].
+pub fn run_reference(hugr: &impl HugrView<Node = Node>, config: &RunConfig) -> Result<ShotOutput> {
+    let entry = find_hugr_entry_point(hugr)?;
+    let mut seeds = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    let mut output = ShotOutput {
+        headers: vec![
+            ("schema_id".into(), "labeled".into()),
+            ("schema_version".into(), "1.0".into()),
+        ],
+        shots: vec![],
+    };
+    for n in 0..config.shots {
+        let mut interpreter = Interpreter::new(hugr, config.backend.create(), seeds.random());
+        interpreter.shot_index = n;
+        interpreter
+            .call(entry, vec![])
+            .with_context(|| format!("Reference interpreter failed in shot {n}"))?;
+        output.shots.push(interpreter.shot);
+    }
+    Ok(output)
+}
+
+/// Calls `func` with qubits `0..num_qubits` as its inputs, applying its
+/// quantum operations to `backend`.
+pub(crate) fn call_with_qubits(
+    hugr: &impl HugrView<Node = Node>,
+    func: Node,
+    num_qubits: usize,
+    mut backend: Box<dyn QuantumBackend>,
+) -> Result<Vec<Value>> {
+    backend.ensure_qubits(num_qubits)?;
+    let mut interpreter = Interpreter::new(hugr, backend, 0);
+    interpreter.next_qubit = num_qubits;
+    interpreter.call(func, (0..num_qubits).map(Value::Qubit).collect())
+}
+
+/// State of a single shot.
+struct Interpreter<'h, H> {
+    hugr: &'h H,
+    /// The values of evaluated output ports.
+    values: HashMap<(Node, OutgoingPort), Value>,
+    /// Evaluation order of the children of dataflow parents.
+    schedules: HashMap<Node, Vec<Node>>,
+    backend: Box<dyn QuantumBackend>,
+    /// Randomness used by measurements.
+    rng: StdRng,
+    /// Randomness exposed to the program through `tket.qsystem.random`.
+    program_rng: ProgramRng,
+    rng_context: bool,
+    free_qubits: Vec<usize>,
+    next_qubit: usize,
+    /// Index of the shot, returned by `tket.qsystem.utils.GetCurrentShot`.
+    shot_index: u64,
+    shot: Shot,
+}
+
+impl<'h, H: HugrView<Node = Node>> Interpreter<'h, H> {
+    fn new(hugr: &'h H, backend: Box<dyn QuantumBackend>, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let program_rng = ProgramRng::seed_from_u64(rng.random());
+        Self {
+            hugr,
+            values: HashMap::new(),
+            schedules: HashMap::new(),
+            backend,
+            rng,
+            program_rng,
+            rng_context: false,
+            free_qubits: vec![],
+            next_qubit: 0,
+            shot_index: 0,
+            shot: Shot::default(),
+        }
+    }
+
+    fn call(&mut self, func: Node, inputs: Vec<Value>) -> Result<Vec<Value>> {
+        match self.hugr.get_optype(func) {
+            OpType::FuncDefn(_) => self.eval_region(func, inputs),
+            OpType::FuncDecl(decl) => bail!("Function \"{}\" has no definition", decl.func_name()),
+            op => bail!("Cannot call {op}"),
+        }
+    }
+
+    /// Evaluates the dataflow region below `parent`.
+    fn eval_region(&mut self, parent: Node, inputs: Vec<Value>) -> Result<Vec<Value>> {
+        let [input, output] = self
+            .hugr
+            .get_io(parent)
+            .ok_or_else(|| anyhow!("{parent} is not a dataflow parent"))?;
+        for (i, value) in inputs.into_iter().enumerate() {
+            self.values.insert((input, i.into()), value);
+        }
+        for node in self.schedule(parent)? {
+            self.eval_node(node)?;
+        }
+        self.inputs(output)
+    }
+
+    /// The children of `parent` in topological order.
+    fn schedule(&mut self, parent: Node) -> Result<Vec<Node>> {
+        if let Some(schedule) = self.schedules.get(&parent) {
+            return Ok(schedule.clone());
+        }
+        let hugr = self.hugr;
+        let is_sibling = |n: &Node| hugr.get_parent(*n) == Some(parent);
+        let mut in_degree: HashMap<Node, usize> = hugr
+            .children(parent)
+            .map(|n| (n, hugr.input_neighbours(n).filter(is_sibling).count()))
+            .collect();
+        let mut ready: Vec<Node> = hugr
+            .children(parent)
+            .filter(|n| in_degree[n] == 0)
+            .collect();
+        let mut schedule = vec![];
+        while let Some(node) = ready.pop() {
+            schedule.push(node);
+            for next in hugr.output_neighbours(node).filter(is_sibling) {
+                let degree = in_degree.get_mut(&next).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push(next);
+                }
+            }
+        }
+        ensure!(
+            schedule.len() == in_degree.len(),
+            "Region {parent} contains a cycle"
+        );
+        self.schedules.insert(parent, schedule.clone());
+        Ok(schedule)
+    }
+
+    fn input(&self, node: Node, port: usize) -> Result<Value> {
+        let (src, src_port) = self
+            .hugr
+            .single_linked_output(node, IncomingPort::from(port))
+            .ok_or_else(|| anyhow!("Input {port} of {node} is not connected"))?;
+        self.values
+            .get(&(src, src_port))
+            .cloned()
+            .ok_or_else(|| anyhow!("Output {src_port} of {src} was not evaluated"))
+    }
+
+    fn inputs(&self, node: Node) -> Result<Vec<Value>> {
+        (0..self.hugr.get_optype(node).value_input_count())
+            .map(|port| self.input(node, port))
+            .collect()
+    }
+
+    fn static_source(&self, node: Node) -> Result<Node> {
+        self.hugr
+            .static_source(node)
+            .ok_or_else(|| anyhow!("{node} has no static input"))
+    }
+
+    fn eval_node(&mut self, node: Node) -> Result<()> {
+        let hugr = self.hugr;
+        let optype = hugr.get_optype(node);
+        let outputs = match optype {
+            OpType::Input(_)
+            | OpType::Output(_)
+            | OpType::Const(_)
+            | OpType::FuncDefn(_)
+            | OpType::FuncDecl(_)
+            | OpType::AliasDecl(_)
+            | OpType::AliasDefn(_) => return Ok(()),
+            OpType::DFG(_) => {
+                let inputs = self.inputs(node)?;
+                self.eval_region(node, inputs)?
+            }
+            OpType::Call(_) => {
+                let inputs = self.inputs(node)?;
+                self.call(self.static_source(node)?, inputs)?
+            }
+            OpType::CallIndirect(_) => {
+                let mut inputs = self.inputs(node)?;
+                let Value::Function(func) = inputs.remove(0) else {
+                    bail!("Expected a function");
+                };
+                self.call(func, inputs)?
+            }
+            OpType::LoadFunction(_) => vec![Value::Function(self.static_source(node)?)],
+            OpType::LoadConstant(_) => {
+                let konst = self.static_source(node)?;
+                let value = hugr
+                    .get_optype(konst)
+                    .as_const()
+                    .ok_or_else(|| anyhow!("{konst} is not a constant"))?
+                    .value();
+                vec![Value::from_const(value)?]
+            }
+            OpType::Tag(tag) => vec![Value::Sum {
+                tag: tag.tag,
+                values: self.inputs(node)?,
+            }],
+            OpType::Conditional(_) => {
+                let mut inputs = self.inputs(node)?;
+                let (tag, mut values) = inputs.remove(0).into_sum()?;
+                let case = hugr
+                    .children(node)
+                    .nth(tag)
+                    .ok_or_else(|| anyhow!("Conditional {node} has no case {tag}"))?;
+                values.extend(inputs);
+                self.eval_region(case, values)?
+            }
+            OpType::TailLoop(_) => {
+                let mut inputs = self.inputs(node)?;
+                loop {
+                    let mut outputs = self.eval_region(node, inputs)?;
+                    let (tag, mut values) = outputs.remove(0).into_sum()?;
+                    values.extend(outputs);
+                    match tag {
+                        0 => inputs = values,
+                        1 => break values,
+                        _ => bail!("TailLoop {node} produced tag {tag}"),
+                    }
+                }
+            }
+            OpType::CFG(_) => {
+                let inputs = self.inputs(node)?;
+                self.eval_cfg(node, inputs)?
+            }
+            OpType::ExtensionOp(op) => {
+                let inputs = self.inputs(node)?;
+                self.eval_extension_op(op, inputs)
+                    .with_context(|| format!("Failed to evaluate {}", op.qualified_id()))?
+            }
+            _ => bail!("Operation {optype} is not supported by the reference interpreter"),
+        };
+        ensure!(
+            outputs.len() == optype.value_output_count(),
+            "{optype} produced {} outputs",
+            outputs.len()
+        );
+        for (i, value) in outputs.into_iter().enumerate() {
+            self.values.insert((node, i.into()), value);
+        }
+        Ok(())
+    }
+
+    fn eval_cfg(&mut self, cfg: Node, mut inputs: Vec<Value>) -> Result<Vec<Value>> {
+        let mut block = self
+            .hugr
+            .first_child(cfg)
+            .ok_or_else(|| anyhow!("CFG {cfg} has no entry block"))?;
+        loop {
+            match self.hugr.get_optype(block) {
+                OpType::ExitBlock(_) => return Ok(inputs),
+                OpType::DataflowBlock(_) => {
+                    let mut outputs = self.eval_region(block, inputs)?;
+                    let (tag, mut values) = outputs.remove(0).into_sum()?;
+                    values.extend(outputs);
+                    inputs = values;
+                    (block, _) = self
+                        .hugr
+                        .single_linked_input(block, OutgoingPort::from(tag))
+                        .ok_or_else(|| anyhow!("Block {block} has no successor {tag}"))?;
+                }
+                op => bail!("Unexpected {op} in CFG"),
+            }
+        }
+    }
+
+    fn allocate_qubit(&mut self) -> Result<usize> {
+        let qubit = match self.free_qubits.pop() {
+            Some(qubit) => qubit,
+            None => {
+                self.next_qubit += 1;
+                self.backend.ensure_qubits(self.next_qubit)?;
+                self.next_qubit - 1
+            }
+        };
+        Ok(qubit)
+    }
+
+    /// Frees a qubit, resetting it so that it can be reused.
+    fn free_qubit(&mut self, qubit: usize) -> Result<()> {
+        self.backend.reset(qubit, &mut self.rng)?;
+        self.free_qubits.push(qubit);
+        // reuse the lowest index first, as the compiled program does
+        self.free_qubits.sort_by(|a, b| b.cmp(a));
+        Ok(())
+    }
+
+    fn measure(&mut self, qubit: usize) -> Result<bool> {
+        self.backend.measure(qubit, &mut self.rng)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hugr::Hugr;
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::std_extensions::arithmetic::int_ops::IntOpDef;
+    use hugr::types::Signature;
+    use rstest::rstest;
+    use tket::TketOp;
+    use tket_qsystem::extension::result::ResultOp;
+
+    use super::*;
+    use crate::results::ResultValue;
+    use crate::runtime::SimulatorBackend;
+    use crate::test::{circuit_hugr, loop_hugr};
+
+    fn run(hugr: &Hugr, shots: u64) -> ShotOutput {
+        let config = RunConfig {
+            shots,
+            seed: Some(3),
+            backend: SimulatorBackend::StateVector,
+        };
+        run_reference(hugr, &config).unwrap()
+    }
+
+    /// The distinct values recorded under `tag`.
+    fn outcomes(output: &ShotOutput, tag: &str) -> Vec<ResultValue> {
+        let mut values: Vec<_> = output
+            .shots
+            .iter()
+            .flat_map(|shot| shot.get(tag).cloned())
+            .collect();
+        values.sort_by_key(|v| v.to_string());
+        values.dedup();
+        values
+    }
+
+    #[rstest]
+    #[case::x(1, vec![(TketOp::X, vec![0], None)], &[true])]
+    #[case::rx_half_turn(1, vec![(TketOp::Rx, vec![0], Some(1.0))], &[true])]
+    #[case::ry_quarter_turns(1, vec![(TketOp::Ry, vec![0], Some(0.5)), (TketOp::Ry, vec![0], Some(0.5))], &[true])]
+    #[case::v_v(1, vec![(TketOp::V, vec![0], None), (TketOp::V, vec![0], None)], &[true])]
+    #[case::hzh(1, vec![(TketOp::H, vec![0], None), (TketOp::Rz, vec![0], Some(1.0)), (TketOp::H, vec![0], None)], &[true])]
+    #[case::cx_control(2, vec![(TketOp::X, vec![0], None), (TketOp::CX, vec![0, 1], None)], &[true, true])]
+    #[case::cx_target(2, vec![(TketOp::X, vec![1], None), (TketOp::CX, vec![0, 1], None)], &[false, true])]
+    #[case::crz(2, vec![(TketOp::X, vec![0], None), (TketOp::H, vec![1], None), (TketOp::CRz, vec![0, 1], Some(1.0)), (TketOp::H, vec![1], None)], &[true, true])]
+    #[case::toffoli(3, vec![(TketOp::X, vec![0], None), (TketOp::X, vec![1], None), (TketOp::Toffoli, vec![0, 1, 2], None)], &[true, true, true])]
+    #[case::toffoli_one_control(3, vec![(TketOp::X, vec![1], None), (TketOp::Toffoli, vec![0, 1, 2], None)], &[false, true, false])]
+    fn deterministic(
+        #[case] num_qubits: usize,
+        #[case] ops: Vec<(TketOp, Vec<usize>, Option<f64>)>,
+        #[case] expected: &[bool],
+    ) {
+        let output = run(&circuit_hugr(num_qubits, &ops), 20);
+        for (i, &bit) in expected.iter().enumerate() {
+            assert_eq!(
+                outcomes(&output, &i.to_string()),
+                [ResultValue::Bool(bit)],
+                "qubit {i}"
+            );
+        }
+    }
+
+    #[test]
+    fn bell() {
+        let hugr = circuit_hugr(
+            2,
+            &[(TketOp::H, vec![0], None), (TketOp::CX, vec![0, 1], None)],
+        );
+        let output = run(&hugr, 50);
+        assert_eq!(outcomes(&output, "0").len(), 2);
+        for shot in &output.shots {
+            assert_eq!(shot.get("0").next(), shot.get("1").next());
+        }
+    }
+
+    #[test]
+    fn control_flow() {
+        let output = run(&loop_hugr(), 2);
+        let expected = StdRng::seed_from_u64(42).random_range(0..1000u32);
+        for shot in &output.shots {
+            let n: Vec<_> = shot.get("n").cloned().collect();
+            assert_eq!(n, [3, 2, 1].map(ResultValue::UInt));
+            assert_eq!(
+                shot.get("r").collect::<Vec<_>>(),
+                [&ResultValue::UInt(expected as u64)]
+            );
+        }
+    }
+
+    #[test]
+    fn panic() {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let one = func.add_load_value(ConstInt::new_u(6, 1).unwrap());
+        let zero = func.add_load_value(ConstInt::new_u(6, 0).unwrap());
+        let div = func
+            .add_dataflow_op(IntOpDef::idiv_u.with_log_width(6), [one, zero])
+            .unwrap();
+        func.add_dataflow_op(ResultOp::new_uint("q", 6), div.outputs())
+            .unwrap();
+        func.finish_with_outputs([]).unwrap();
+        let hugr = module.finish_hugr().unwrap();
+        let err = run_reference(&hugr, &RunConfig::default()).unwrap_err();
+        assert!(format!("{err:#}").contains("panicked"), "{err:#}");
+    }
+}
@@ -0,0 +1,567 @@
+//! Semantics of the extension operations supported by the reference
+//! interpreter.
+
+use std::f64::consts::PI;
+
+use anyhow::{Result, bail, ensure};
+use hugr::extension::prelude::{
+    Barrier, EXIT_OP_ID, MakeTuple, Noop, PANIC_OP_ID, PRELUDE_ID, PRINT_OP_ID, UnpackTuple,
+};
+use hugr::extension::simple_op::MakeExtensionOp as _;
+use hugr::ops::ExtensionOp;
+use hugr::std_extensions::arithmetic::conversions::{ConvertOpDef, ConvertOpType};
+use hugr::std_extensions::arithmetic::float_ops::FloatOps;
+use hugr::std_extensions::arithmetic::int_ops::{ConcreteIntOp, IntOpDef};
+use hugr::std_extensions::logic::LogicOp;
+use hugr::{HugrView, Node};
+use rand::{Rng as _, SeedableRng as _};
+use tket::TketOp;
+use tket::extension::bool::BoolOp;
+use tket::extension::rotation::RotationOp;
+use tket_qsystem::extension::futures::FutureOpDef;
+use tket_qsystem::extension::qsystem::{self, QSystemOp};
+use tket_qsystem::extension::random::RandomOp;
+use tket_qsystem::extension::result::ResultOp;
+use tket_qsystem::extension::utils::UtilsOp;
+
+use super::{Interpreter, Value};
+use crate::results::{ResultType, ResultValue};
+use crate::runtime::{Gate, ProgramRng, advance_program_rng, random_program_float};
+
+/// The low `width` bits of `v`.
+fn mask(v: u64, width: u32) -> u64 {
+    if width >= 64 {
+        v
+    } else {
+        v & ((1 << width) - 1)
+    }
+}
+
+/// `v` as a signed integer of `width` bits.
+fn sign_extend(v: u64, width: u32) -> i64 {
+    if width >= 64 {
+        v as i64
+    } else {
+        ((v << (64 - width)) as i64) >> (64 - width)
+    }
+}
+
+/// A value of a `sum_with_error` type.
+fn ok_or_error(ok: bool, values: impl FnOnce() -> Vec<Value>, error: &str) -> Value {
+    if ok {
+        Value::Sum {
+            tag: 1,
+            values: values(),
+        }
+    } else {
+        Value::Sum {
+            tag: 0,
+            values: vec![Value::Error(error.to_string())],
+        }
+    }
+}
+
+impl<H: HugrView<Node = Node>> Interpreter<'_, H> {
+    pub(super) fn eval_extension_op(
+        &mut self,
+        op: &ExtensionOp,
+        inputs: Vec<Value>,
+    ) -> Result<Vec<Value>> {
+        if let Ok(op) = TketOp::from_extension_op(op) {
+            self.eval_tket_op(op, inputs)
+        } else if let Ok(op) = QSystemOp::from_extension_op(op) {
+            self.eval_qsystem_op(op, inputs)
+        } else if let Ok(op) = ResultOp::from_extension_op(op) {
+            self.eval_result_op(op, &inputs)
+        } else if let Ok(op) = RandomOp::from_extension_op(op) {
+            self.eval_random_op(op, inputs)
+        } else if let Ok(UtilsOp::GetCurrentShot) = UtilsOp::from_extension_op(op) {
+            Ok(vec![Value::Int(self.shot_index)])
+        } else if let Ok(op) = RotationOp::from_extension_op(op) {
+            eval_rotation_op(op, &inputs)
+        } else if let Ok(op) = BoolOp::from_extension_op(op) {
+            eval_bool_op(op, &inputs)
+        } else if let Ok(op) = FutureOpDef::from_extension_op(op) {
+            eval_future_op(op, inputs)
+        } else if let Ok(op) = ConcreteIntOp::from_extension_op(op) {
+            eval_int_op(&op, &inputs)
+        } else if let Ok(op) = FloatOps::from_extension_op(op) {
+            eval_float_op(op, &inputs)
+        } else if let Ok(op) = ConvertOpType::from_extension_op(op) {
+            eval_convert_op(&op, &inputs)
+        } else if let Ok(op) = LogicOp::from_extension_op(op) {
+            eval_logic_op(op, &inputs)
+        } else if op.extension_id() == &PRELUDE_ID {
+            eval_prelude_op(op, inputs)
+        } else if op.extension_id() == &qsystem::EXTENSION_ID
+            && op.unqualified_id() == qsystem::RUNTIME_BARRIER_NAME
+        {
+            Ok(inputs)
+        } else {
+            bail!("Operation is not supported by the reference interpreter")
+        }
+    }
+
+    fn apply(&mut self, gate: Gate, qubits: &[usize]) -> Result<()> {
+        self.backend.apply(gate, qubits)
+    }
+
+    fn eval_tket_op(&mut self, op: TketOp, inputs: Vec<Value>) -> Result<Vec<Value>> {
+        use TketOp::*;
+        let q = |i: usize| inputs[i].as_qubit();
+        let radians = |i: usize| Ok::<_, anyhow::Error>(inputs[i].as_rotation()? * PI);
+        match op {
+            H => self.apply(Gate::H, &[q(0)?])?,
+            X => self.apply(Gate::X, &[q(0)?])?,
+            Y => self.apply(Gate::Y, &[q(0)?])?,
+            Z => self.apply(Gate::Z, &[q(0)?])?,
+            S => self.apply(Gate::S, &[q(0)?])?,
+            Sdg => self.apply(Gate::Sdg, &[q(0)?])?,
+            T => self.apply(Gate::T, &[q(0)?])?,
+            Tdg => self.apply(Gate::Tdg, &[q(0)?])?,
+            V => self.apply(Gate::Rx(PI / 2.0), &[q(0)?])?,
+            Vdg => self.apply(Gate::Rx(-PI / 2.0), &[q(0)?])?,
+            Rx => self.apply(Gate::Rx(radians(1)?), &[q(0)?])?,
+            Ry => self.apply(Gate::Ry(radians(1)?), &[q(0)?])?,
+            Rz => self.apply(Gate::Rz(radians(1)?), &[q(0)?])?,
+            CX => self.apply(Gate::CX, &[q(0)?, q(1)?])?,
+            CY => self.apply(Gate::CY, &[q(0)?, q(1)?])?,
+            CZ => self.apply(Gate::CZ, &[q(0)?, q(1)?])?,
+            CRz => {
+                let (c, t, theta) = (q(0)?, q(1)?, radians(2)?);
+                self.apply(Gate::Rz(theta / 2.0), &[t])?;
+                self.apply(Gate::CX, &[c, t])?;
+                self.apply(Gate::Rz(-theta / 2.0), &[t])?;
+                self.apply(Gate::CX, &[c, t])?;
+            }
+            Toffoli => {
+                let (a, b, t) = (q(0)?, q(1)?, q(2)?);
+                for (gate, qubits) in [
+                    (Gate::H, vec![t]),
+                    (Gate::CX, vec![b, t]),
+                    (Gate::Tdg, vec![t]),
+                    (Gate::CX, vec![a, t]),
+                    (Gate::T, vec![t]),
+                    (Gate::CX, vec![b, t]),
+                    (Gate::Tdg, vec![t]),
+                    (Gate::CX, vec![a, t]),
+                    (Gate::T, vec![b]),
+                    (Gate::T, vec![t]),
+                    (Gate::H, vec![t]),
+                    (Gate::CX, vec![a, b]),
+                    (Gate::T, vec![a]),
+                    (Gate::Tdg, vec![b]),
+                    (Gate::CX, vec![a, b]),
+                ] {
+                    self.apply(gate, &qubits)?;
+                }
+            }
+            Reset => self.backend.reset(q(0)?, &mut self.rng)?,
+            Measure => {
+                let bit = self.measure(q(0)?)?;
+                return Ok(vec![inputs[0].clone(), Value::from_bool(bit)]);
+            }
+            MeasureFree => {
+                let qubit = q(0)?;
+                let bit = self.measure(qubit)?;
+                self.free_qubit(qubit)?;
+                return Ok(vec![Value::Bool(bit)]);
+            }
+            QAlloc => return Ok(vec![Value::Qubit(self.allocate_qubit()?)]),
+            TryQAlloc => {
+                let qubit = Value::Qubit(self.allocate_qubit()?);
+                return Ok(vec![ok_or_error(true, || vec![qubit], "")]);
+            }
+            QFree => {
+                self.free_qubit(q(0)?)?;
+                return Ok(vec![]);
+            }
+            _ => bail!("Operation is not supported by the reference interpreter"),
+        }
+        // the remaining operations return their qubits
+        Ok(inputs
+            .into_iter()
+            .filter(|v| matches!(v, Value::Qubit(_)))
+            .collect())
+    }
+
+    fn eval_qsystem_op(&mut self, op: QSystemOp, inputs: Vec<Value>) -> Result<Vec<Value>> {
+        use QSystemOp::*;
+        let q = |i: usize| inputs[i].as_qubit();
+        let f = |i: usize| inputs[i].as_float();
+        Ok(match op {
+            Rz => {
+                self.apply(Gate::Rz(f(1)?), &[q(0)?])?;
+                vec![inputs[0].clone()]
+            }
+            PhasedX => {
+                self.apply(Gate::PhasedX(f(1)?, f(2)?), &[q(0)?])?;
+                vec![inputs[0].clone()]
+            }
+            ZZPhase => {
+                self.apply(Gate::Rzz(f(2)?), &[q(0)?, q(1)?])?;
+                vec![inputs[0].clone(), inputs[1].clone()]
+            }
+            Measure | LazyMeasure | LazyMeasureLeaked => {
+                let qubit = q(0)?;
+                let bit = self.measure(qubit)?;
+                self.free_qubit(qubit)?;
+                vec![match op {
+                    Measure => Value::Bool(bit),
+                    LazyMeasure => Value::Future(Box::new(Value::from_bool(bit))),
+                    // the simulators do not model leakage
+                    _ => Value::Future(Box::new(Value::Int(bit as u64))),
+                }]
+            }
+            MeasureReset | LazyMeasureReset => {
+                let qubit = q(0)?;
+                let bit = self.measure(qubit)?;
+                self.backend.reset(qubit, &mut self.rng)?;
+                vec![
+                    inputs[0].clone(),
+                    match op {
+                        MeasureReset => Value::Bool(bit),
+                        _ => Value::Future(Box::new(Value::from_bool(bit))),
+                    },
+                ]
+            }
+            Reset => {
+                self.backend.reset(q(0)?, &mut self.rng)?;
+                vec![inputs[0].clone()]
+            }
+            TryQAlloc => {
+                let qubit = Value::Qubit(self.allocate_qubit()?);
+                vec![ok_or_error(true, || vec![qubit], "")]
+            }
+            QFree => {
+                self.free_qubit(q(0)?)?;
+                vec![]
+            }
+            _ => bail!("Operation is not supported by the reference interpreter"),
+        })
+    }
+
+    fn eval_result_op(&mut self, op: ResultOp, inputs: &[Value]) -> Result<Vec<Value>> {
+        let value = match ResultType::from_result_op(&op)? {
+            ResultType::Bool => ResultValue::Bool(inputs[0].as_bool()?),
+            ResultType::Int {
+                width,
+                signed: true,
+            } => ResultValue::Int(sign_extend(inputs[0].as_int()?, width)),
+            ResultType::Int { signed: false, .. } => ResultValue::UInt(inputs[0].as_int()?),
+            ResultType::F64 => ResultValue::Double(inputs[0].as_float()?),
+        };
+        self.shot.results.push((op.tag, value));
+        Ok(vec![])
+    }
+
+    fn eval_random_op(&mut self, op: RandomOp, inputs: Vec<Value>) -> Result<Vec<Value>> {
+        Ok(match op {
+            RandomOp::NewRNGContext => {
+                // only one context may exist at a time
+                let ok = !self.rng_context;
+                if ok {
+                    self.program_rng = ProgramRng::seed_from_u64(inputs[0].as_int()?);
+                    self.rng_context = true;
+                }
+                vec![match ok {
+                    true => Value::Sum {
+                        tag: 1,
+                        values: vec![Value::RngContext],
+                    },
+                    false => Value::Sum {
+                        tag: 0,
+                        values: vec![],
+                    },
+                }]
+            }
+            RandomOp::DeleteRNGContext => {
+                self.rng_context = false;
+                vec![]
+            }
+            RandomOp::RandomInt => {
+                let value = self.program_rng.random::<u32>();
+                vec![Value::Int(value.into()), Value::RngContext]
+            }
+            RandomOp::RandomIntBounded => {
+                let bound = inputs[1].as_int()? as u32;
+                ensure!(bound > 0, "random_int_bounded called with bound 0");
+                let value = self.program_rng.random_range(0..bound);
+                vec![Value::Int(value.into()), Value::RngContext]
+            }
+            RandomOp::RandomFloat => {
+                let value = random_program_float(&mut self.program_rng);
+                vec![Value::Float(value), Value::RngContext]
+            }
+            RandomOp::RandomAdvance => {
+                let delta = inputs[1].as_int()? as i64;
+                advance_program_rng(&mut self.program_rng, delta);
+                vec![Value::RngContext]
+            }
+            _ => bail!("Operation is not supported by the reference interpreter"),
+        })
+    }
+}
+
+fn eval_rotation_op(op: RotationOp, inputs: &[Value]) -> Result<Vec<Value>> {
+    Ok(vec![match op {
+        RotationOp::from_halfturns => {
+            let half_turns = inputs[0].as_float()?;
+            ok_or_error(
+                half_turns.is_finite(),
+                || vec![Value::Rotation(half_turns)],
+                "",
+            )
+        }
+        RotationOp::from_halfturns_unchecked => {
+            let half_turns = inputs[0].as_float()?;
+            ensure!(half_turns.is_finite(), "Program panicked: invalid rotation");
+            Value::Rotation(half_turns)
+        }
+        RotationOp::to_halfturns => Value::Float(inputs[0].as_rotation()?),
+        RotationOp::radd => Value::Rotation(inputs[0].as_rotation()? + inputs[1].as_rotation()?),
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    }])
+}
+
+fn eval_bool_op(op: BoolOp, inputs: &[Value]) -> Result<Vec<Value>> {
+    let b = |i: usize| inputs[i].as_opaque_bool();
+    Ok(vec![match op {
+        BoolOp::read => Value::from_bool(b(0)?),
+        BoolOp::make_opaque => Value::Bool(inputs[0].as_bool()?),
+        BoolOp::eq => Value::Bool(b(0)? == b(1)?),
+        BoolOp::not => Value::Bool(!b(0)?),
+        BoolOp::and => Value::Bool(b(0)? && b(1)?),
+        BoolOp::or => Value::Bool(b(0)? || b(1)?),
+        BoolOp::xor => Value::Bool(b(0)? ^ b(1)?),
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    }])
+}
+
+fn eval_future_op(op: FutureOpDef, inputs: Vec<Value>) -> Result<Vec<Value>> {
+    let [Value::Future(value)] = <[Value; 1]>::try_from(inputs).unwrap_or([Value::unit()]) else {
+        bail!("Expected a future");
+    };
+    Ok(match op {
+        FutureOpDef::Read => vec![*value],
+        FutureOpDef::Dup => vec![Value::Future(value.clone()), Value::Future(value)],
+        FutureOpDef::Free => vec![],
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    })
+}
+
+fn eval_int_op(op: &ConcreteIntOp, inputs: &[Value]) -> Result<Vec<Value>> {
+    use IntOpDef::*;
+    let width = 1u32 << op.log_widths[0];
+    let a = inputs[0].as_int()?;
+    let b = || inputs[1].as_int();
+    let (sa, sb) = (sign_extend(a, width), || {
+        Ok::<_, anyhow::Error>(sign_extend(b()?, width))
+    });
+    let int = |v: u64| Value::Int(mask(v, width));
+    let divmod = |signed: bool, checked: bool| -> Result<Option<(u64, u64)>> {
+        let m = b()?;
+        if m == 0 {
+            ensure!(checked, "Program panicked: division by zero");
+            return Ok(None);
+        }
+        // the divisor is unsigned, and the remainder non-negative
+        let n = if signed { sa as i128 } else { a as i128 };
+        let (q, r) = (n.div_euclid(m as i128), n.rem_euclid(m as i128));
+        Ok(Some((mask(q as u64, width), r as u64)))
+    };
+    let checked = |result: Option<Vec<Value>>| match result {
+        Some(values) => ok_or_error(true, || values, ""),
+        None => ok_or_error(false, Vec::new, "division by zero"),
+    };
+    Ok(match op.def {
+        iwiden_u => vec![Value::Int(a)],
+        iwiden_s => vec![Value::Int(mask(sa as u64, 1 << op.log_widths[1]))],
+        inarrow_u => {
+            let to = 1 << op.log_widths[1];
+            vec![ok_or_error(
+                mask(a, to) == a,
+                || vec![Value::Int(a)],
+                "integer too large",
+            )]
+        }
+        inarrow_s => {
+            let to = 1 << op.log_widths[1];
+            let narrow = mask(sa as u64, to);
+            vec![ok_or_error(
+                sign_extend(narrow, to) == sa,
+                || vec![Value::Int(narrow)],
+                "integer too large",
+            )]
+        }
+        ieq => vec![Value::from_bool(a == b()?)],
+        ine => vec![Value::from_bool(a != b()?)],
+        ilt_u => vec![Value::from_bool(a < b()?)],
+        ilt_s => vec![Value::from_bool(sa < sb()?)],
+        igt_u => vec![Value::from_bool(a > b()?)],
+        igt_s => vec![Value::from_bool(sa > sb()?)],
+        ile_u => vec![Value::from_bool(a <= b()?)],
+        ile_s => vec![Value::from_bool(sa <= sb()?)],
+        ige_u => vec![Value::from_bool(a >= b()?)],
+        ige_s => vec![Value::from_bool(sa >= sb()?)],
+        imax_u => vec![Value::Int(a.max(b()?))],
+        imax_s => vec![int(sa.max(sb()?) as u64)],
+        imin_u => vec![Value::Int(a.min(b()?))],
+        imin_s => vec![int(sa.min(sb()?) as u64)],
+        iadd => vec![int(a.wrapping_add(b()?))],
+        isub => vec![int(a.wrapping_sub(b()?))],
+        ineg => vec![int(a.wrapping_neg())],
+        imul => vec![int(a.wrapping_mul(b()?))],
+        ipow => vec![int(a.wrapping_pow(b()?.min(u32::MAX.into()) as u32))],
+        iabs => vec![int(sa.wrapping_abs() as u64)],
+        iand => vec![Value::Int(a & b()?)],
+        ior => vec![Value::Int(a | b()?)],
+        ixor => vec![Value::Int(a ^ b()?)],
+        inot => vec![int(!a)],
+        ishl => vec![int(a.checked_shl(b()? as u32).unwrap_or(0))],
+        ishr => vec![Value::Int(a.checked_shr(b()? as u32).unwrap_or(0))],
+        irotl | irotr => {
+            let k = (b()? % width as u64) as u32;
+            let k = if op.def == irotl {
+                k
+            } else {
+                (width - k) % width
+            };
+            let rotated = if k == 0 {
+                a
+            } else {
+                (a << k) | (a >> (width - k))
+            };
+            vec![int(rotated)]
+        }
+        iu_to_s => {
+            ensure!(
+                sign_extend(a, width) >= 0,
+                "Program panicked: integer too large"
+            );
+            vec![Value::Int(a)]
+        }
+        is_to_u => {
+            ensure!(sa >= 0, "Program panicked: negative integer");
+            vec![Value::Int(a)]
+        }
+        idivmod_u | idivmod_s => {
+            let (q, r) = divmod(op.def == idivmod_s, false)?.unwrap();
+            vec![Value::Int(q), Value::Int(r)]
+        }
+        idiv_u | idiv_s => vec![Value::Int(divmod(op.def == idiv_s, false)?.unwrap().0)],
+        imod_u | imod_s => vec![Value::Int(divmod(op.def == imod_s, false)?.unwrap().1)],
+        idivmod_checked_u | idivmod_checked_s => {
+            let result = divmod(op.def == idivmod_checked_s, true)?;
+            vec![checked(
+                result.map(|(q, r)| vec![Value::Int(q), Value::Int(r)]),
+            )]
+        }
+        idiv_checked_u | idiv_checked_s => {
+            let result = divmod(op.def == idiv_checked_s, true)?;
+            vec![checked(result.map(|(q, _)| vec![Value::Int(q)]))]
+        }
+        imod_checked_u | imod_checked_s => {
+            let result = divmod(op.def == imod_checked_s, true)?;
+            vec![checked(result.map(|(_, r)| vec![Value::Int(r)]))]
+        }
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    })
+}
+
+fn eval_float_op(op: FloatOps, inputs: &[Value]) -> Result<Vec<Value>> {
+    use FloatOps::*;
+    let a = inputs[0].as_float()?;
+    let b = || inputs[1].as_float();
+    Ok(vec![match op {
+        feq => Value::from_bool(a == b()?),
+        fne => Value::from_bool(a != b()?),
+        flt => Value::from_bool(a < b()?),
+        fgt => Value::from_bool(a > b()?),
+        fle => Value::from_bool(a <= b()?),
+        fge => Value::from_bool(a >= b()?),
+        fmax => Value::Float(a.max(b()?)),
+        fmin => Value::Float(a.min(b()?)),
+        fadd => Value::Float(a + b()?),
+        fsub => Value::Float(a - b()?),
+        fneg => Value::Float(-a),
+        fabs => Value::Float(a.abs()),
+        fmul => Value::Float(a * b()?),
+        fdiv => Value::Float(a / b()?),
+        fpow => Value::Float(a.powf(b()?)),
+        ffloor => Value::Float(a.floor()),
+        fceil => Value::Float(a.ceil()),
+        fround => Value::Float(a.round()),
+        ftostring => Value::String(a.to_string()),
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    }])
+}
+
+fn eval_convert_op(op: &ConvertOpType, inputs: &[Value]) -> Result<Vec<Value>> {
+    use ConvertOpDef::*;
+    let width = || 1u32 << op.log_widths()[0];
+    Ok(vec![match op.def() {
+        trunc_u | trunc_s => {
+            let f = inputs[0].as_float()?.trunc();
+            let (min, max) = if *op.def() == trunc_u {
+                (0.0, 2f64.powi(width() as i32))
+            } else {
+                let half = 2f64.powi(width() as i32 - 1);
+                (-half, half)
+            };
+            let ok = f.is_finite() && min <= f && f < max;
+            let int = if *op.def() == trunc_u {
+                f as u64
+            } else {
+                f as i64 as u64
+            };
+            ok_or_error(
+                ok,
+                || vec![Value::Int(mask(int, width()))],
+                "float out of range",
+            )
+        }
+        convert_u => Value::Float(inputs[0].as_int()? as f64),
+        convert_s => Value::Float(sign_extend(inputs[0].as_int()?, width()) as f64),
+        itobool => Value::from_bool(inputs[0].as_int()? == 1),
+        ifrombool => Value::Int(inputs[0].as_bool()? as u64),
+        itostring_u => Value::String(inputs[0].as_int()?.to_string()),
+        itostring_s => Value::String(sign_extend(inputs[0].as_int()?, width()).to_string()),
+        itousize | ifromusize => Value::Int(inputs[0].as_int()?),
+        bytecast_int64_to_float64 => Value::Float(f64::from_bits(inputs[0].as_int()?)),
+        bytecast_float64_to_int64 => Value::Int(inputs[0].as_float()?.to_bits()),
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    }])
+}
+
+fn eval_logic_op(op: LogicOp, inputs: &[Value]) -> Result<Vec<Value>> {
+    let b = |i: usize| inputs[i].as_bool();
+    Ok(vec![Value::from_bool(match op {
+        LogicOp::And => b(0)? && b(1)?,
+        LogicOp::Or => b(0)? || b(1)?,
+        LogicOp::Eq => b(0)? == b(1)?,
+        LogicOp::Not => !b(0)?,
+        LogicOp::Xor => b(0)? ^ b(1)?,
+        _ => bail!("Operation is not supported by the reference interpreter"),
+    })])
+}
+
+fn eval_prelude_op(op: &ExtensionOp, mut inputs: Vec<Value>) -> Result<Vec<Value>> {
+    if MakeTuple::from_extension_op(op).is_ok() {
+        Ok(vec![Value::tuple(inputs)])
+    } else if UnpackTuple::from_extension_op(op).is_ok() {
+        Ok(inputs.remove(0).into_sum()?.1)
+    } else if Noop::from_extension_op(op).is_ok() || Barrier::from_extension_op(op).is_ok() {
+        Ok(inputs)
+    } else if op.unqualified_id() == PRINT_OP_ID {
+        Ok(vec![])
+    } else if op.unqualified_id() == PANIC_OP_ID || op.unqualified_id() == EXIT_OP_ID {
+        let message = match inputs.first() {
+            Some(Value::Error(message)) => message.as_str(),
+            _ => "",
+        };
+        bail!("Program panicked: {message}")
+    } else {
+        bail!("Operation is not supported by the reference interpreter")
+    }
+}
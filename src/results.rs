@@ -0,0 +1,234 @@
+//! Parsing of the labeled shot output produced by the QIR record-output
+//! functions.
+//!
+//! The output consists of tab separated records: `HEADER` records followed
+//! by one `START` ... `END` block per shot, each containing `OUTPUT` records
+//! such as `OUTPUT BOOL true c`.
+
+pub mod manifest;
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use serde::Serialize;
+
+pub use manifest::{ResultEntry, ResultManifest, ResultType};
+
+/// A single recorded value.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ResultValue {
+    Bool(bool),
+    Int(i64),
+    /// An `INT` record of an unsigned result, see [ShotOutput::check].
+    UInt(u64),
+    Double(f64),
+}
+
+impl std::fmt::Display for ResultValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultValue::Bool(v) => write!(f, "{v}"),
+            ResultValue::Int(v) => write!(f, "{v}"),
+            ResultValue::UInt(v) => write!(f, "{v}"),
+            ResultValue::Double(v) => write!(f, "{v:?}"),
+        }
+    }
+}
+
+/// The records of one shot.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Shot {
+    /// The recorded `(tag, value)` pairs, in output order. A tag recorded
+    /// more than once appears more than once.
+    pub results: Vec<(String, ResultValue)>,
+    /// The code of the `END` record.
+    pub exit_code: i64,
+}
+
+impl Shot {
+    /// All values recorded with `tag`.
+    pub fn get<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a ResultValue> + 'a {
+        self.results
+            .iter()
+            .filter(move |(t, _)| t == tag)
+            .map(|(_, v)| v)
+    }
+}
+
+/// Parsed shot output.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ShotOutput {
+    /// `HEADER` records as `(key, value)` pairs.
+    pub headers: Vec<(String, String)>,
+    pub shots: Vec<Shot>,
+}
+
+impl ShotOutput {
+    pub fn parse(output: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        let mut current: Option<Shot> = None;
+        for (i, line) in output.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            parse_record(line, &mut parsed, &mut current)
+                .with_context(|| format!("Invalid shot output on line {}: {line:?}", i + 1))?;
+        }
+        ensure!(current.is_none(), "Shot output ends inside a shot");
+        Ok(parsed)
+    }
+
+    /// Checks every record against the types declared in `manifest`, and
+    /// converts `INT` records of unsigned results to [ResultValue::UInt].
+    pub fn check(&mut self, manifest: &ResultManifest) -> Result<()> {
+        for (n, shot) in self.shots.iter_mut().enumerate() {
+            for (tag, value) in &mut shot.results {
+                *value = manifest
+                    .check(tag, value)
+                    .with_context(|| format!("Invalid result in shot {n}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_record(line: &str, parsed: &mut ShotOutput, current: &mut Option<Shot>) -> Result<()> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    match fields.as_slice() {
+        ["HEADER", key, value] => {
+            ensure!(
+                current.is_none() && parsed.shots.is_empty(),
+                "HEADER after the first shot"
+            );
+            parsed.headers.push((key.to_string(), value.to_string()));
+        }
+        ["START"] => {
+            ensure!(current.is_none(), "START inside a shot");
+            *current = Some(Shot::default());
+        }
+        ["OUTPUT", ty, value, tag] => {
+            let shot = current
+                .as_mut()
+                .ok_or_else(|| anyhow!("OUTPUT outside a shot"))?;
+            shot.results
+                .push((tag.to_string(), parse_value(ty, value)?));
+        }
+        ["END", code] => {
+            let mut shot = current
+                .take()
+                .ok_or_else(|| anyhow!("END outside a shot"))?;
+            shot.exit_code = code.parse()?;
+            parsed.shots.push(shot);
+        }
+        _ => bail!("unknown record"),
+    }
+    Ok(())
+}
+
+fn parse_value(ty: &str, value: &str) -> Result<ResultValue> {
+    Ok(match ty {
+        "BOOL" => ResultValue::Bool(value.parse()?),
+        // measurement results, as output by other QIR runtimes
+        "RESULT" => match value {
+            "0" => ResultValue::Bool(false),
+            "1" => ResultValue::Bool(true),
+            _ => bail!("invalid RESULT value"),
+        },
+        "INT" => ResultValue::Int(value.parse()?),
+        "DOUBLE" => ResultValue::Double(value.parse()?),
+        _ => bail!("unknown output type {ty}"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::bool_t;
+    use hugr::std_extensions::arithmetic::float_types::float64_type;
+    use hugr::std_extensions::arithmetic::int_types::int_type;
+    use hugr::types::Signature;
+    use rstest::rstest;
+    use tket_qsystem::extension::result::ResultOp;
+
+    use super::*;
+
+    const OUTPUT: &str = "HEADER\tschema_id\tlabeled\n\
+        HEADER\tschema_version\t1.0\n\
+        START\n\
+        OUTPUT\tBOOL\ttrue\tc\n\
+        OUTPUT\tINT\t-1\tn\n\
+        OUTPUT\tDOUBLE\t0.5\tx\n\
+        END\t0\n\
+        START\n\
+        OUTPUT\tRESULT\t0\tc\n\
+        OUTPUT\tINT\t-1\tu\n\
+        END\t0\n";
+
+    /// A program recording `c: bool`, `n: int<32>`, `u: uint<64>` and
+    /// `x: f64`.
+    fn manifest() -> ResultManifest {
+        let mut module = ModuleBuilder::new();
+        let inputs = vec![bool_t(), int_type(5), int_type(6), float64_type()];
+        let mut func = module
+            .define_function("main", Signature::new(inputs, vec![]))
+            .unwrap();
+        let [c, n, u, x] = func.input_wires_arr();
+        func.add_dataflow_op(ResultOp::new_bool("c"), [c]).unwrap();
+        func.add_dataflow_op(ResultOp::new_int("n", 5), [n])
+            .unwrap();
+        func.add_dataflow_op(ResultOp::new_uint("u", 6), [u])
+            .unwrap();
+        func.add_dataflow_op(ResultOp::new_f64("x"), [x]).unwrap();
+        func.finish_with_outputs([]).unwrap();
+        ResultManifest::from_hugr(&module.finish_hugr().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let output = ShotOutput::parse(OUTPUT).unwrap();
+        assert_eq!(output.headers[0], ("schema_id".into(), "labeled".into()));
+        assert_eq!(output.shots.len(), 2);
+        assert_eq!(
+            output.shots[0].get("n").collect::<Vec<_>>(),
+            [&ResultValue::Int(-1)]
+        );
+        assert_eq!(
+            output.shots[1].get("c").collect::<Vec<_>>(),
+            [&ResultValue::Bool(false)]
+        );
+    }
+
+    #[test]
+    fn check() {
+        let mut output = ShotOutput::parse(OUTPUT).unwrap();
+        output.check(&manifest()).unwrap();
+        assert_eq!(
+            output.shots[1].get("u").collect::<Vec<_>>(),
+            [&ResultValue::UInt(u64::MAX)]
+        );
+    }
+
+    #[rstest]
+    #[case("OUTPUT\tBOOL\ttrue\tc\n", "outside a shot")]
+    #[case("START\nSTART\n", "START inside a shot")]
+    #[case("START\nOUTPUT\tFLOAT\t1\tx\nEND\t0\n", "unknown output type")]
+    #[case("START\nOUTPUT\tBOOL\t1\tc\nEND\t0\n", "line 2")]
+    #[case("START\nOUTPUT\tINT\t1\tn\n", "ends inside a shot")]
+    #[case("START\nEND\t0\nHEADER\ta\tb\n", "HEADER after the first shot")]
+    fn parse_invalid(#[case] output: &str, #[case] expected: &str) {
+        let err = ShotOutput::parse(output).unwrap_err();
+        assert!(format!("{err:#}").contains(expected), "{err:#}");
+    }
+
+    #[rstest]
+    #[case("OUTPUT\tINT\t1\td", "not in the manifest")]
+    #[case("OUTPUT\tINT\t1\tc", "expected BOOL")]
+    #[case("OUTPUT\tBOOL\ttrue\tn", "expected INT")]
+    #[case("OUTPUT\tINT\t2147483648\tn", "out of range")]
+    #[case("OUTPUT\tINT\t1\tx", "expected DOUBLE")]
+    fn check_invalid(#[case] record: &str, #[case] expected: &str) {
+        let mut output = ShotOutput::parse(&format!("START\n{record}\nEND\t0\n")).unwrap();
+        let err = output.check(&manifest()).unwrap_err();
+        assert!(format!("{err:#}").contains(expected), "{err:#}");
+    }
+}
@@ -0,0 +1,365 @@
+//! The tags and types of the results a program records.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use anyhow::{Result, anyhow, bail, ensure};
+use hugr::extension::simple_op::MakeExtensionOp as _;
+use hugr::{HugrView, Node, NodeIndex as _};
+use serde::{Deserialize, Serialize};
+use tket_qsystem::extension::result::{ResultArgs, ResultOp, ResultOpDef, SimpleArgs};
+
+use super::ResultValue;
+use crate::find_hugr_entry_point;
+
+/// The type of a recorded result.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ResultType {
+    Bool,
+    Int {
+        /// Width in bits.
+        width: u32,
+        signed: bool,
+    },
+    F64,
+}
+
+impl ResultType {
+    /// The type recorded by a `tket.result` operation.
+    pub fn from_result_op(op: &ResultOp) -> Result<Self> {
+        if let ResultArgs::Array(..) = op.args {
+            bail!("Array result \"{}\" is not supported", op.tag);
+        }
+        Self::element_type(op)
+    }
+
+    /// The type recorded by a `tket.result` operation, or the type of the
+    /// elements of an array result.
+    fn element_type(op: &ResultOp) -> Result<Self> {
+        let log_width = match &op.args {
+            ResultArgs::Simple(SimpleArgs::Int(log_width))
+            | ResultArgs::Array(SimpleArgs::Int(log_width), _) => Some(*log_width),
+            ResultArgs::Simple(SimpleArgs::Basic) | ResultArgs::Array(SimpleArgs::Basic, _) => None,
+        };
+        let int = |signed| {
+            let log_width = log_width.ok_or_else(|| anyhow!("Missing width of \"{}\"", op.tag))?;
+            Ok(Self::Int {
+                width: 1 << log_width,
+                signed,
+            })
+        };
+        match op.result_op {
+            ResultOpDef::Bool | ResultOpDef::ArrBool => Ok(Self::Bool),
+            ResultOpDef::Int | ResultOpDef::ArrInt => int(true),
+            ResultOpDef::UInt | ResultOpDef::ArrUInt => int(false),
+            ResultOpDef::F64 | ResultOpDef::ArrF64 => Ok(Self::F64),
+            other => bail!("Result operation {other:?} is not supported"),
+        }
+    }
+
+    /// Checks that `value`, as parsed from the shot output, has this type,
+    /// returning it with its signedness resolved.
+    pub fn check(&self, value: &ResultValue) -> Result<ResultValue> {
+        Ok(match (self, value) {
+            (Self::Bool, ResultValue::Bool(_)) | (Self::F64, ResultValue::Double(_)) => {
+                value.clone()
+            }
+            (Self::Int { width, signed }, ResultValue::Int(v)) => {
+                let v = *v;
+                if *signed {
+                    ensure!(
+                        *width >= 64 || (v >> (width - 1)) == 0 || (v >> (width - 1)) == -1,
+                        "{v} is out of range for int<{width}>"
+                    );
+                    ResultValue::Int(v)
+                } else {
+                    // unsigned values are zero-extended to i64, and so may
+                    // be output as negative numbers
+                    let v = v as u64;
+                    ensure!(
+                        *width >= 64 || v >> width == 0,
+                        "{v} is out of range for uint<{width}>"
+                    );
+                    ResultValue::UInt(v)
+                }
+            }
+            (Self::Int { signed: false, .. }, ResultValue::UInt(_)) => value.clone(),
+            _ => bail!("expected {}, got {value:?}", self.output_type()),
+        })
+    }
+
+    /// The type name used in `OUTPUT` records.
+    pub fn output_type(&self) -> &'static str {
+        match self {
+            Self::Bool => "BOOL",
+            Self::Int { .. } => "INT",
+            Self::F64 => "DOUBLE",
+        }
+    }
+}
+
+impl fmt::Display for ResultType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool => write!(f, "bool"),
+            Self::Int {
+                width,
+                signed: true,
+            } => write!(f, "int<{width}>"),
+            Self::Int {
+                width,
+                signed: false,
+            } => write!(f, "uint<{width}>"),
+            Self::F64 => write!(f, "f64"),
+        }
+    }
+}
+
+/// A recorded result.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultEntry {
+    pub tag: String,
+    /// The type of the result, or of its elements for an array.
+    #[serde(flatten)]
+    pub ty: ResultType,
+    /// The length of an array result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub array_length: Option<u64>,
+    /// The index of the `tket.result` node recording the result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<usize>,
+}
+
+impl ResultEntry {
+    pub fn new(tag: String, ty: ResultType) -> Self {
+        Self {
+            tag,
+            ty,
+            array_length: None,
+            node: None,
+        }
+    }
+
+    /// The name of the recorded type, e.g. `int<32>` or `array<3, bool>`.
+    pub fn type_name(&self) -> String {
+        match self.array_length {
+            Some(length) => format!("array<{length}, {}>", self.ty),
+            None => self.ty.to_string(),
+        }
+    }
+
+    /// The result recorded by the `tket.result` operation at `node`.
+    pub fn from_result_op(op: &ResultOp, node: Node) -> Result<Self> {
+        let array_length = match op.args {
+            ResultArgs::Array(_, length) => Some(length),
+            ResultArgs::Simple(_) => None,
+        };
+        Ok(Self {
+            tag: op.tag.clone(),
+            ty: ResultType::element_type(op)?,
+            array_length,
+            node: Some(node.index()),
+        })
+    }
+}
+
+/// The results a program may record.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultManifest {
+    pub results: Vec<ResultEntry>,
+}
+
+impl ResultManifest {
+    /// Collects the results recorded by `tket.result` operations in the HUGR,
+    /// with one entry per operation.
+    pub fn from_hugr(hugr: &impl HugrView<Node = Node>) -> Result<Self> {
+        Self::from_nodes(hugr, hugr.nodes())
+    }
+
+    /// Collects the results recorded by the entry point of the HUGR and the
+    /// functions it calls, leaving out those of functions removed as dead
+    /// code by compilation.
+    pub fn from_entry_point(hugr: &impl HugrView<Node = Node>) -> Result<Self> {
+        let entry_point = find_hugr_entry_point(hugr)?;
+        let mut funcs = vec![entry_point];
+        let mut seen = BTreeSet::from([entry_point]);
+        let mut nodes = Vec::new();
+        while let Some(func) = funcs.pop() {
+            for node in hugr.descendants(func) {
+                nodes.push(node);
+                if let Some(callee) = hugr.static_source(node)
+                    && hugr.get_optype(callee).is_func_defn()
+                    && seen.insert(callee)
+                {
+                    funcs.push(callee);
+                }
+            }
+        }
+        nodes.sort_by_key(|node| node.index());
+        Self::from_nodes(hugr, nodes)
+    }
+
+    fn from_nodes(
+        hugr: &impl HugrView<Node = Node>,
+        nodes: impl IntoIterator<Item = Node>,
+    ) -> Result<Self> {
+        let mut manifest = Self::default();
+        for node in nodes {
+            let Some(op) = hugr.get_optype(node).as_extension_op() else {
+                continue;
+            };
+            let Ok(op) = ResultOp::from_extension_op(op) else {
+                continue;
+            };
+            manifest
+                .results
+                .push(ResultEntry::from_result_op(&op, node)?);
+        }
+        Ok(manifest)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Adds a result, unless an identical one is already present.
+    pub fn add(&mut self, tag: String, ty: ResultType) {
+        let entry = ResultEntry::new(tag, ty);
+        if !self.results.contains(&entry) {
+            self.results.push(entry);
+        }
+    }
+
+    /// The tags recorded with more than one type, with the names of their
+    /// types.
+    pub fn conflicting_tags(&self) -> Vec<(&str, Vec<String>)> {
+        let mut types: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+        for entry in &self.results {
+            types
+                .entry(&entry.tag)
+                .or_default()
+                .insert(entry.type_name());
+        }
+        types
+            .into_iter()
+            .filter(|(_, types)| types.len() > 1)
+            .map(|(tag, types)| (tag, types.into_iter().collect()))
+            .collect()
+    }
+
+    /// The types recorded under `tag`, the element types for arrays.
+    pub fn types<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = ResultType> + 'a {
+        self.results
+            .iter()
+            .filter(move |e| e.tag == tag)
+            .map(|e| e.ty)
+    }
+
+    /// Checks a value recorded under `tag` against the manifest, returning
+    /// it with its signedness resolved.
+    pub fn check(&self, tag: &str, value: &ResultValue) -> Result<ResultValue> {
+        let mut types = self.types(tag).peekable();
+        ensure!(
+            types.peek().is_some(),
+            "tag \"{tag}\" is not in the manifest"
+        );
+        let mut err = None;
+        for ty in types {
+            match ty.check(value) {
+                Ok(value) => return Ok(value),
+                Err(e) => err = Some(e),
+            }
+        }
+        Err(err.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::single_op_hugr;
+
+    #[test]
+    fn json_roundtrip() {
+        let mut manifest = ResultManifest::default();
+        manifest.add("c".into(), ResultType::Bool);
+        manifest.add(
+            "n".into(),
+            ResultType::Int {
+                width: 64,
+                signed: true,
+            },
+        );
+        manifest.add("c".into(), ResultType::Bool);
+        assert_eq!(manifest.results.len(), 2);
+        let json = manifest.to_json().unwrap();
+        assert!(json.contains(r#""type": "int""#), "{json}");
+        assert_eq!(ResultManifest::from_json(&json).unwrap(), manifest);
+    }
+
+    #[test]
+    fn from_hugr() {
+        let op = ResultOp::new_int("xs", 5).array_op(3);
+        let hugr = single_op_hugr(op.into());
+        let manifest = ResultManifest::from_hugr(&hugr).unwrap();
+        let [entry] = manifest.results.as_slice() else {
+            panic!("expected one result, got {manifest:?}")
+        };
+        assert_eq!(
+            entry.ty,
+            ResultType::Int {
+                width: 32,
+                signed: true
+            }
+        );
+        assert_eq!(entry.array_length, Some(3));
+        let node = hugr
+            .nodes()
+            .find(|n| hugr.get_optype(*n).as_extension_op().is_some())
+            .unwrap();
+        assert_eq!(entry.node, Some(node.index()));
+        let json = manifest.to_json().unwrap();
+        assert!(json.contains(r#""array_length": 3"#), "{json}");
+        assert_eq!(ResultManifest::from_json(&json).unwrap(), manifest);
+        assert!(ResultType::from_result_op(&ResultOp::new_bool("c").array_op(2)).is_err());
+    }
+
+    #[test]
+    fn conflicting_tags() {
+        let mut manifest = ResultManifest::default();
+        manifest.add("c".into(), ResultType::Bool);
+        manifest.add(
+            "n".into(),
+            ResultType::Int {
+                width: 8,
+                signed: false,
+            },
+        );
+        manifest.add("c".into(), ResultType::F64);
+        manifest.results.push(ResultEntry {
+            array_length: Some(2),
+            ..ResultEntry::new(
+                "n".into(),
+                ResultType::Int {
+                    width: 8,
+                    signed: false,
+                },
+            )
+        });
+        assert_eq!(
+            manifest.conflicting_tags(),
+            [
+                ("c", vec!["bool".to_string(), "f64".to_string()]),
+                (
+                    "n",
+                    vec!["array<2, uint<8>>".to_string(), "uint<8>".to_string()]
+                )
+            ]
+        );
+    }
+}
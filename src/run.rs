@@ -0,0 +1,481 @@
+//! Local execution of compiled QIR modules.
+//!
+//! The module is JIT compiled with LLVM's MCJIT and its QIS and runtime
+//! calls are bound to the implementations in [crate::runtime]. Barriers are
+//! defined as empty functions instead.
+
+use anyhow::{Result, anyhow, bail, ensure};
+use hugr::Hugr;
+use inkwell::OptimizationLevel;
+use inkwell::attributes::{Attribute, AttributeLoc};
+use inkwell::context::Context;
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::module::Module;
+use inkwell::targets::{InitializationConfig, Target, TargetMachine};
+use inkwell::values::FunctionValue;
+
+use crate::CompileArgs;
+use crate::inkwell;
+pub use crate::runtime::RunConfig;
+use crate::runtime::{is_barrier, run_shots, runtime_function};
+use crate::target::CompileTarget;
+
+/// Compiles a HUGR for the host and runs it.
+pub fn run_hugr(hugr: &mut Hugr, args: &CompileArgs, config: &RunConfig) -> Result<String> {
+    let args = CompileArgs {
+        target: CompileTarget::Native,
+        target_options: Default::default(),
+        ..args.clone()
+    };
+    let context = Context::create();
+    let module = args.compile(hugr, &context)?;
+    run_module(&module, config)
+}
+
+/// Runs the entry point of a module compiled for the host, returning the
+/// records of all shots in the QIR labeled output format.
+pub fn run_module(module: &Module, config: &RunConfig) -> Result<String> {
+    let host = TargetMachine::get_default_triple();
+    ensure!(
+        module.get_triple().as_str().to_bytes().is_empty() || module.get_triple() == host,
+        "Module targets {} but can only be run on the host ({host})",
+        module.get_triple()
+    );
+    let entry = entry_point(module)?;
+    ensure!(
+        entry.count_params() == 0,
+        "Entry point must not take parameters"
+    );
+
+    Target::initialize_native(&InitializationConfig::default()).map_err(|e| anyhow!(e))?;
+    ExecutionEngine::link_in_mc_jit();
+    // The engine takes ownership of the module, so execute a copy.
+    let module = module.clone();
+    // `abort` unwinds through the program back to the runtime.
+    let uwtable = module
+        .get_context()
+        .create_enum_attribute(Attribute::get_named_enum_kind_id("uwtable"), 0);
+    let builder = module.get_context().create_builder();
+    for func in module.get_functions() {
+        if is_barrier(&func.get_name().to_string_lossy()) && func.count_basic_blocks() == 0 {
+            // a barrier does nothing, whichever qubits it is called with
+            let block = module.get_context().append_basic_block(func, "entry");
+            builder.position_at_end(block);
+            builder.build_return(None)?;
+        }
+        if func.count_basic_blocks() > 0 {
+            func.add_attribute(AttributeLoc::Function, uwtable);
+        }
+    }
+    let engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .map_err(|e| anyhow!("Failed to create execution engine: {e}"))?;
+    for func in module.get_functions() {
+        if func.count_basic_blocks() > 0 {
+            continue;
+        }
+        let name = func.get_name().to_string_lossy();
+        if let Some(addr) = runtime_function(&name) {
+            engine.add_global_mapping(&func, addr);
+        } else if !name.starts_with("llvm.") {
+            bail!("Function \"{name}\" is not provided by the runtime");
+        }
+    }
+    let name = entry.get_name().to_string_lossy();
+    let addr = engine
+        .get_function_address(&name)
+        .map_err(|e| anyhow!("Failed to compile entry point: {e}"))?;
+    // SAFETY: the entry point was checked to take no parameters; its return
+    // value, if any, is ignored.
+    let entry: extern "C-unwind" fn() = unsafe { std::mem::transmute(addr) };
+
+    run_shots(|| entry(), config)
+}
+
+/// The function marked with the `entry_point` attribute.
+pub(crate) fn entry_point<'c>(module: &Module<'c>) -> Result<FunctionValue<'c>> {
+    module
+        .get_functions()
+        .find(|f| {
+            f.get_string_attribute(AttributeLoc::Function, "entry_point")
+                .is_some()
+        })
+        .ok_or_else(|| anyhow!("Module has no entry point"))
+}
+
+#[cfg(test)]
+mod test {
+    use hugr::builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder};
+    use hugr::extension::prelude::{Barrier, qb_t};
+    use hugr::ops::Value;
+    use hugr::types::Signature;
+    use rstest::rstest;
+    use tket::TketOp;
+    use tket::extension::bool::{BoolOp, ConstBool, bool_type};
+    use tket_qsystem::extension::result::ResultOp;
+    use tket_qsystem::extension::utils::UtilsOp;
+
+    use super::*;
+    use crate::runtime::SimulatorBackend;
+    use crate::target::description::TargetDescription;
+    use crate::test::held_qubits_hugr;
+
+    /// A program allocating one qubit, applying `ops` and recording the
+    /// measurement as "c".
+    fn measure_hugr(ops: &[TketOp]) -> Hugr {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let mut qb = func
+            .add_dataflow_op(TketOp::QAlloc, [])
+            .unwrap()
+            .out_wire(0);
+        for op in ops {
+            qb = func.add_dataflow_op(*op, [qb]).unwrap().out_wire(0);
+        }
+        let bit = func
+            .add_dataflow_op(TketOp::MeasureFree, [qb])
+            .unwrap()
+            .out_wire(0);
+        let bit = func
+            .add_dataflow_op(BoolOp::read, [bit])
+            .unwrap()
+            .out_wire(0);
+        func.add_dataflow_op(ResultOp::new_bool("c"), [bit])
+            .unwrap();
+        func.finish_with_outputs([]).unwrap();
+        module.finish_hugr().unwrap()
+    }
+
+    fn try_run(ops: &[TketOp], shots: u64, backend: SimulatorBackend) -> Result<String> {
+        let config = RunConfig {
+            shots,
+            seed: Some(5),
+            backend,
+        };
+        run_hugr(&mut measure_hugr(ops), &CompileArgs::default(), &config)
+    }
+
+    fn run(ops: &[TketOp], shots: u64) -> String {
+        try_run(ops, shots, SimulatorBackend::StateVector).unwrap()
+    }
+
+    #[test]
+    fn output_format() {
+        assert_eq!(
+            run(&[TketOp::X], 2),
+            "HEADER\tschema_id\tlabeled\nHEADER\tschema_version\t1.0\n\
+             START\nOUTPUT\tBOOL\ttrue\tc\nEND\t0\n\
+             START\nOUTPUT\tBOOL\ttrue\tc\nEND\t0\n"
+        );
+    }
+
+    #[test]
+    fn result_record_output() {
+        let args = CompileArgs {
+            result_record_output: true,
+            ..Default::default()
+        };
+        let config = RunConfig {
+            shots: 1,
+            ..Default::default()
+        };
+        let output = run_hugr(&mut measure_hugr(&[TketOp::X]), &args, &config).unwrap();
+        assert!(
+            output.contains("START\nOUTPUT\tRESULT\t1\tc\nEND\t0\n"),
+            "{output}"
+        );
+    }
+
+    /// A panicking program fails the run rather than aborting the process.
+    #[test]
+    fn panic_fails_run() {
+        use hugr::extension::prelude::{UnwrapBuilder as _, option_type, usize_t};
+        use hugr::type_row;
+
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let none = func
+            .make_sum(0, [type_row![], vec![usize_t()].into()], [])
+            .unwrap();
+        let [_] = func
+            .build_unwrap_sum(1, option_type(usize_t()), none)
+            .unwrap();
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+
+        let config = RunConfig {
+            shots: 1,
+            ..Default::default()
+        };
+        let err = run_hugr(&mut hugr, &CompileArgs::default(), &config).unwrap_err();
+        assert!(format!("{err:#}").contains("Program panicked"), "{err:#}");
+    }
+
+    /// A panic ends the shot even inside a loop that never exits: the loop
+    /// reads a one-element array at the iteration count, which panics in the
+    /// second iteration.
+    #[test]
+    fn panic_in_loop_fails_run() {
+        use hugr::extension::prelude::{UnwrapBuilder as _, option_type};
+        use hugr::std_extensions::arithmetic::conversions::ConvertOpDef;
+        use hugr::std_extensions::arithmetic::int_ops::IntOpDef;
+        use hugr::std_extensions::arithmetic::int_types::{ConstInt, int_type};
+        use hugr::std_extensions::collections::array::ArrayOpBuilder as _;
+
+        let int = int_type(6);
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let start = func.add_load_value(ConstInt::new_u(6, 0).unwrap());
+        let mut tail_loop = func
+            .tail_loop_builder([], [(int.clone(), start)], vec![].into())
+            .unwrap();
+        let [n] = tail_loop.input_wires_arr();
+        let arr = tail_loop.add_new_array(int.clone(), [n]).unwrap();
+        let [index] = tail_loop
+            .add_dataflow_op(ConvertOpDef::itousize.without_log_width(), [n])
+            .unwrap()
+            .outputs_arr();
+        let (elem, arr) = tail_loop.add_array_get(int.clone(), 1, arr, index).unwrap();
+        let [elem] = tail_loop
+            .build_unwrap_sum(1, option_type(int.clone()), elem)
+            .unwrap();
+        tail_loop
+            .add_dataflow_op(ResultOp::new_uint("x", 6), [elem])
+            .unwrap();
+        tail_loop.add_array_discard(int, 1, arr).unwrap();
+        let one = tail_loop.add_load_value(ConstInt::new_u(6, 1).unwrap());
+        let [n] = tail_loop
+            .add_dataflow_op(IntOpDef::iadd.with_log_width(6), [n, one])
+            .unwrap()
+            .outputs_arr();
+        let done = tail_loop.add_load_value(Value::false_val());
+        tail_loop.finish_with_outputs(done, [n]).unwrap();
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+
+        let config = RunConfig {
+            shots: 1,
+            ..Default::default()
+        };
+        let err = run_hugr(&mut hugr, &CompileArgs::default(), &config).unwrap_err();
+        assert!(format!("{err:#}").contains("Program panicked"), "{err:#}");
+    }
+
+    /// A measurement in a loop keeps the outcome of each iteration: the loop
+    /// flips a qubit from one and measures a copy of it until it is one
+    /// again, carrying the outcomes of the last two iterations out of the
+    /// loop.
+    #[test]
+    fn measurement_future_in_loop() {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let qb = func
+            .add_dataflow_op(TketOp::QAlloc, [])
+            .unwrap()
+            .out_wire(0);
+        let qb = func.add_dataflow_op(TketOp::X, [qb]).unwrap().out_wire(0);
+        let bit = func.add_load_value(ConstBool::new(false));
+        let mut tail_loop = func
+            .tail_loop_builder(
+                [],
+                [(qb_t(), qb), (bool_type(), bit), (bool_type(), bit)],
+                vec![].into(),
+            )
+            .unwrap();
+        let [qb, _, last] = tail_loop.input_wires_arr();
+        let qb = tail_loop
+            .add_dataflow_op(TketOp::X, [qb])
+            .unwrap()
+            .out_wire(0);
+        let ancilla = tail_loop
+            .add_dataflow_op(TketOp::QAlloc, [])
+            .unwrap()
+            .out_wire(0);
+        let [qb, ancilla] = tail_loop
+            .add_dataflow_op(TketOp::CX, [qb, ancilla])
+            .unwrap()
+            .outputs_arr();
+        let bit = tail_loop
+            .add_dataflow_op(TketOp::MeasureFree, [ancilla])
+            .unwrap()
+            .out_wire(0);
+        let done = tail_loop
+            .add_dataflow_op(BoolOp::read, [bit])
+            .unwrap()
+            .out_wire(0);
+        let [qb, previous, last] = tail_loop
+            .finish_with_outputs(done, [qb, last, bit])
+            .unwrap()
+            .outputs_arr();
+        func.add_dataflow_op(TketOp::QFree, [qb]).unwrap();
+        for (bit, tag) in [(previous, "previous"), (last, "last")] {
+            let bit = func
+                .add_dataflow_op(BoolOp::read, [bit])
+                .unwrap()
+                .out_wire(0);
+            func.add_dataflow_op(ResultOp::new_bool(tag), [bit])
+                .unwrap();
+        }
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+        let config = RunConfig {
+            shots: 1,
+            ..Default::default()
+        };
+        let output = run_hugr(&mut hugr, &CompileArgs::default(), &config).unwrap();
+        for line in [
+            "OUTPUT\tBOOL\tfalse\tprevious\n",
+            "OUTPUT\tBOOL\ttrue\tlast\n",
+        ] {
+            assert!(output.contains(line), "{output}");
+        }
+    }
+
+    /// Barriers are accepted and do nothing. The two-qubit barrier is called
+    /// with both qubits, so it is defined as an empty function taking two
+    /// qubits rather than bound to the runtime.
+    #[test]
+    fn barrier() {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let qbs = [TketOp::QAlloc, TketOp::QAlloc]
+            .map(|op| func.add_dataflow_op(op, []).unwrap().out_wire(0));
+        let qb = func
+            .add_dataflow_op(TketOp::X, [qbs[0]])
+            .unwrap()
+            .out_wire(0);
+        let qbs = func
+            .add_dataflow_op(Barrier::new(vec![qb_t(), qb_t()]), [qb, qbs[1]])
+            .unwrap()
+            .outputs();
+        for (qb, tag) in qbs.zip(["a", "b"]) {
+            let bit = func
+                .add_dataflow_op(TketOp::MeasureFree, [qb])
+                .unwrap()
+                .out_wire(0);
+            let bit = func
+                .add_dataflow_op(BoolOp::read, [bit])
+                .unwrap()
+                .out_wire(0);
+            func.add_dataflow_op(ResultOp::new_bool(tag), [bit])
+                .unwrap();
+        }
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+        let config = RunConfig {
+            shots: 1,
+            ..Default::default()
+        };
+        let output = run_hugr(&mut hugr, &CompileArgs::default(), &config).unwrap();
+        for line in ["OUTPUT\tBOOL\ttrue\ta\n", "OUTPUT\tBOOL\tfalse\tb\n"] {
+            assert!(output.contains(line), "{output}");
+        }
+    }
+
+    /// With a qubit capacity, allocations in a loop succeed while fewer qubits
+    /// than the capacity are in use, in every shot.
+    #[rstest]
+    #[case(1, ["true", "false", "false"])]
+    #[case(2, ["true", "true", "false"])]
+    fn qubit_capacity_loop(#[case] max_qubits: u64, #[case] oks: [&str; 3]) {
+        let desc = format!(r#"{{"max_qubits": {max_qubits}, "check_qubit_capacity": true}}"#);
+        let args = CompileArgs {
+            target_description: Some(TargetDescription::from_json(&desc).unwrap()),
+            ..Default::default()
+        };
+        let config = RunConfig {
+            shots: 2,
+            ..Default::default()
+        };
+        let output = run_hugr(&mut held_qubits_hugr(), &args, &config).unwrap();
+        let recorded: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("OUTPUT\tBOOL\t"))
+            .collect();
+        let expected: Vec<_> = [oks; 2]
+            .iter()
+            .flatten()
+            .map(|ok| format!("{ok}\tok"))
+            .collect();
+        assert_eq!(recorded, expected, "{output}");
+    }
+
+    #[test]
+    fn current_shot() {
+        let mut module = ModuleBuilder::new();
+        let mut func = module
+            .define_function("main", Signature::new_endo(vec![]))
+            .unwrap();
+        let shot = func
+            .add_dataflow_op(UtilsOp::GetCurrentShot, [])
+            .unwrap()
+            .out_wire(0);
+        func.add_dataflow_op(ResultOp::new_uint("shot", 6), [shot])
+            .unwrap();
+        func.finish_with_outputs([]).unwrap();
+        let mut hugr = module.finish_hugr().unwrap();
+        let config = RunConfig {
+            shots: 3,
+            ..Default::default()
+        };
+        let output = run_hugr(&mut hugr, &CompileArgs::default(), &config).unwrap();
+        let shots: Vec<_> = output
+            .lines()
+            .filter_map(|line| line.strip_prefix("OUTPUT\tINT\t"))
+            .collect();
+        assert_eq!(shots, ["0\tshot", "1\tshot", "2\tshot"]);
+    }
+
+    #[rstest]
+    #[case(&[], 0)]
+    #[case(&[TketOp::X], 100)]
+    #[case(&[TketOp::H, TketOp::H], 0)]
+    #[case(&[TketOp::H, TketOp::S, TketOp::S, TketOp::H], 100)]
+    fn deterministic(
+        #[case] ops: &[TketOp],
+        #[case] ones: usize,
+        #[values(SimulatorBackend::StateVector, SimulatorBackend::Stabilizer)]
+        backend: SimulatorBackend,
+    ) {
+        let output = try_run(ops, 100, backend).unwrap();
+        assert_eq!(output.matches("\ttrue\t").count(), ones, "{output}");
+    }
+
+    #[test]
+    fn superposition() {
+        let output = run(&[TketOp::H], 200);
+        let ones = output.matches("\ttrue\t").count();
+        assert!((50..150).contains(&ones), "{ones}");
+        // runs are reproducible with a seed
+        assert_eq!(output, run(&[TketOp::H], 200));
+    }
+
+    #[test]
+    fn stabilizer_non_clifford() {
+        let err = try_run(&[TketOp::H, TketOp::T], 1, SimulatorBackend::Stabilizer).unwrap_err();
+        assert!(format!("{err:#}").contains("non-Clifford"), "{err:#}");
+        assert!(try_run(&[TketOp::H, TketOp::T], 1, SimulatorBackend::StateVector).is_ok());
+    }
+
+    #[test]
+    fn foreign_triple() {
+        let context = Context::create();
+        let module = CompileArgs::default()
+            .compile(&mut measure_hugr(&[]), &context)
+            .unwrap();
+        if module.get_triple() != TargetMachine::get_default_triple() {
+            let err = run_module(&module, &RunConfig::default()).unwrap_err();
+            assert!(err.to_string().contains("can only be run on the host"));
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod description;
+
+use anyhow::{Result, anyhow, ensure};
+use itertools::Itertools;
+
 use crate::inkwell::{
     OptimizationLevel,
     targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple},
@@ -11,6 +16,17 @@ pub enum CompileTarget {
     Native,
 }
 
+/// Overrides for the LLVM target machine implied by a [CompileTarget].
+#[derive(Clone, Debug, Default)]
+pub struct TargetMachineOptions {
+    /// Target triple, e.g. `x86_64-unknown-linux-gnu`.
+    pub triple: Option<String>,
+    /// Target CPU, e.g. `cortex-a72`.
+    pub cpu: Option<String>,
+    /// Comma separated list of target features, e.g. `+neon,-fp-armv8`.
+    pub features: Option<String>,
+}
+
 impl CompileTarget {
     pub fn initialise(&self) {
         match self {
@@ -22,28 +38,136 @@ impl CompileTarget {
             }
         }
     }
-    pub fn machine(self, level: OptimizationLevel) -> TargetMachine {
+
+    /// The target triple used when no triple is given explicitly.
+    pub fn default_triple(self) -> TargetTriple {
+        match self {
+            Self::Native => TargetMachine::get_default_triple(),
+            Self::QuantinuumHardware => TargetTriple::create("aarch64-unknown-linux-gnu"),
+        }
+    }
+
+    pub fn machine(
+        self,
+        options: &TargetMachineOptions,
+        level: OptimizationLevel,
+    ) -> Result<TargetMachine> {
         let reloc_mode = RelocMode::PIC;
         let code_model = CodeModel::Default;
-        match self {
-            Self::Native => Target::from_triple(&TargetMachine::get_default_triple())
-                .unwrap()
-                .create_target_machine(
-                    &TargetMachine::get_default_triple(),
-                    "",
-                    "",
-                    level,
-                    reloc_mode,
-                    code_model,
-                )
-                .unwrap(),
-            Self::QuantinuumHardware => {
-                let triple = TargetTriple::create("aarch64-unknown-linux-gnu");
-                Target::from_triple(&triple)
-                    .unwrap()
-                    .create_target_machine(&triple, "", "", level, reloc_mode, code_model)
-                    .unwrap()
+        let triple = match &options.triple {
+            Some(triple) => {
+                // an explicit triple may name any target LLVM was built with
+                Target::initialize_all(&InitializationConfig::default());
+                TargetTriple::create(triple)
             }
-        }
+            None => self.default_triple(),
+        };
+        let target = Target::from_triple(&triple).map_err(|e| {
+            anyhow!(
+                "Unsupported target triple \"{triple}\": {e}. Registered targets: {}",
+                registered_targets().join(", ")
+            )
+        })?;
+        let features = options.features.as_deref().unwrap_or("");
+        validate_features(features)?;
+        target
+            .create_target_machine(
+                &triple,
+                options.cpu.as_deref().unwrap_or(""),
+                features,
+                level,
+                reloc_mode,
+                code_model,
+            )
+            .ok_or_else(|| anyhow!("Failed to create target machine for \"{triple}\""))
+    }
+}
+
+/// Names of all targets registered with LLVM.
+pub fn registered_targets() -> Vec<String> {
+    std::iter::successors(Target::get_first(), Target::get_next)
+        .map(|t| t.get_name().to_string_lossy().into_owned())
+        .sorted()
+        .collect()
+}
+
+fn validate_features(features: &str) -> Result<()> {
+    for feature in features.split(',').filter(|f| !f.is_empty()) {
+        ensure!(
+            feature.len() > 1 && (feature.starts_with('+') || feature.starts_with('-')),
+            "Invalid target feature \"{feature}\": features must be of the form +name or -name"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(CompileTarget::QuantinuumHardware, None, "aarch64-unknown-linux-gnu")]
+    #[case(
+        CompileTarget::QuantinuumHardware,
+        Some("x86_64-unknown-linux-gnu"),
+        "x86_64-unknown-linux-gnu"
+    )]
+    #[case(
+        CompileTarget::Native,
+        Some("riscv64-unknown-linux-gnu"),
+        "riscv64-unknown-linux-gnu"
+    )]
+    fn machine_triple(
+        #[case] target: CompileTarget,
+        #[case] triple: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        target.initialise();
+        let options = TargetMachineOptions {
+            triple: triple.map(Into::into),
+            ..Default::default()
+        };
+        let machine = target.machine(&options, OptimizationLevel::None).unwrap();
+        assert_eq!(machine.get_triple().as_str().to_str().unwrap(), expected);
+    }
+
+    #[test]
+    fn machine_cpu_and_features() {
+        let options = TargetMachineOptions {
+            triple: Some("aarch64-unknown-linux-gnu".into()),
+            cpu: Some("cortex-a72".into()),
+            features: Some("+neon,-crypto".into()),
+        };
+        let machine = CompileTarget::QuantinuumHardware
+            .machine(&options, OptimizationLevel::None)
+            .unwrap();
+        assert_eq!(machine.get_cpu().to_str().unwrap(), "cortex-a72");
+        assert_eq!(
+            machine.get_feature_string().to_str().unwrap(),
+            "+neon,-crypto"
+        );
+    }
+
+    #[rstest]
+    #[case(Some("nonsense-unknown-unknown"), None, "Unsupported target triple")]
+    #[case(None, Some("neon"), "Invalid target feature")]
+    #[case(None, Some("+neon,,-"), "Invalid target feature")]
+    fn machine_invalid(
+        #[case] triple: Option<&str>,
+        #[case] features: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        CompileTarget::QuantinuumHardware.initialise();
+        let options = TargetMachineOptions {
+            triple: triple.map(Into::into),
+            features: features.map(Into::into),
+            ..Default::default()
+        };
+        let err = CompileTarget::QuantinuumHardware
+            .machine(&options, OptimizationLevel::None)
+            .unwrap_err();
+        assert!(err.to_string().contains(expected), "{err}");
     }
 }
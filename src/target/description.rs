@@ -0,0 +1,369 @@
+//! Target description files, declaring the capabilities and limits of a QIR
+//! consumer.
+
+use std::path::Path;
+
+use anyhow::{Context as _, Result, bail};
+use hugr::extension::{ExtensionId, prelude::PRELUDE_ID};
+use hugr::{HugrView, Node};
+use inkwell::attributes::AttributeLoc;
+use inkwell::module::Module;
+use inkwell::values::{CallSiteValue, InstructionOpcode};
+use serde::Deserialize;
+use tket::extension::{TKET_EXTENSION_ID, rotation::ROTATION_EXTENSION_ID};
+use tket_qsystem::extension::{futures, qsystem, result, utils};
+
+use crate::inkwell;
+use crate::qir::wasm_ext::WasmCodegenExtension;
+use crate::results::ResultManifest;
+
+/// Prefix of the QIS functions emitted by this crate.
+pub const QIS_PREFIX: &str = "__quantum__qis__";
+
+/// Extensions which are lowered to QIS and runtime calls rather than to
+/// classical operations, and so are not subject to
+/// [TargetDescription::classical_ops].
+const QUANTUM_EXTENSIONS: [&ExtensionId; 7] = [
+    &PRELUDE_ID,
+    &TKET_EXTENSION_ID,
+    &ROTATION_EXTENSION_ID,
+    &qsystem::EXTENSION_ID,
+    &futures::EXTENSION_ID,
+    &result::EXTENSION_ID,
+    &utils::EXTENSION_ID,
+];
+
+/// Capabilities and limits of a target, loaded from a JSON file.
+///
+/// All fields are optional; a missing field places no restriction on the
+/// program.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetDescription {
+    /// Name of the target, used in error messages.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// LLVM target triple.
+    #[serde(default)]
+    pub triple: Option<String>,
+    /// LLVM target CPU.
+    #[serde(default)]
+    pub cpu: Option<String>,
+    /// LLVM target features.
+    #[serde(default)]
+    pub features: Option<String>,
+    /// QIS operations supported by the target, without prefix and suffix,
+    /// e.g. `rz` for `__quantum__qis__rz__body`.
+    #[serde(default)]
+    pub native_gates: Option<Vec<String>>,
+    /// Maximum number of qubits a program may use.
+    #[serde(default)]
+    pub max_qubits: Option<u64>,
+    /// Whether qubit allocations beyond [Self::max_qubits] also fail at
+    /// runtime, as allocations in the program may.
+    #[serde(default)]
+    pub check_qubit_capacity: bool,
+    /// Maximum number of results a program may use.
+    #[serde(default)]
+    pub max_results: Option<u64>,
+    /// HUGR extensions whose classical operations the target supports, e.g.
+    /// `arithmetic.int`.
+    #[serde(default)]
+    pub classical_ops: Option<Vec<String>>,
+    /// Prefix to use for QIS functions instead of `__quantum__qis__`.
+    #[serde(default)]
+    pub qis_prefix: Option<String>,
+    /// Value of the `qir_profiles` attribute of the entry point.
+    #[serde(default)]
+    pub qir_profile: Option<String>,
+    /// Characters allowed in result tags, e.g. `a-zA-Z0-9_`, where `x-y` is
+    /// a range.
+    #[serde(default)]
+    pub result_tag_chars: Option<String>,
+    /// Maximum length of a result tag in bytes.
+    #[serde(default)]
+    pub max_result_tag_length: Option<usize>,
+    /// Prefix to use for the runtime functions of the random extension
+    /// instead of `___random_`.
+    #[serde(default)]
+    pub random_prefix: Option<String>,
+    /// Whether the RNG context is an object created by the runtime with
+    /// `new_context` and passed to every random function.
+    #[serde(default)]
+    pub stateful_rng_context: bool,
+    /// Runtime function returning the index of the current shot, instead of
+    /// `get_current_shot`.
+    #[serde(default)]
+    pub current_shot_function: Option<String>,
+    /// Naming and calling convention of WASM functions, and what is recorded
+    /// about WASM modules.
+    #[serde(default)]
+    pub wasm: Option<WasmCodegenExtension>,
+    /// Whether runtime barriers are dropped instead of lowered to
+    /// `__quantum__qis__barrier{n}__body` calls.
+    #[serde(default)]
+    pub drop_barriers: bool,
+}
+
+impl TargetDescription {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read target file {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("Invalid target file {}", path.display()))
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("custom")
+    }
+
+    /// Fails if the HUGR contains classical operations from extensions not
+    /// listed in [Self::classical_ops].
+    pub fn check_classical_ops(&self, hugr: &impl HugrView<Node = Node>) -> Result<()> {
+        let Some(classical_ops) = &self.classical_ops else {
+            return Ok(());
+        };
+        for node in hugr.nodes() {
+            let Some(op) = hugr.get_optype(node).as_extension_op() else {
+                continue;
+            };
+            let ext = op.def().extension_id();
+            if QUANTUM_EXTENSIONS.contains(&ext) || classical_ops.contains(&ext.to_string()) {
+                continue;
+            }
+            bail!(
+                "Operation {} from extension {ext} is not supported by target \"{}\"",
+                op.def().name(),
+                self.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails if a result tag in the HUGR is longer than
+    /// [Self::max_result_tag_length] or contains characters outside of
+    /// [Self::result_tag_chars].
+    pub fn check_result_tags(&self, hugr: &impl HugrView<Node = Node>) -> Result<()> {
+        if self.result_tag_chars.is_none() && self.max_result_tag_length.is_none() {
+            return Ok(());
+        }
+        for entry in ResultManifest::from_hugr(hugr)?.results {
+            let tag = &entry.tag;
+            if let Some(max_length) = self.max_result_tag_length
+                && tag.len() > max_length
+            {
+                bail!(
+                    "Result tag \"{tag}\" is longer than the {max_length} bytes supported by target \"{}\"",
+                    self.name()
+                );
+            }
+            if let Some(chars) = &self.result_tag_chars
+                && let Some(c) = tag.chars().find(|c| !char_set_contains(chars, *c))
+            {
+                bail!(
+                    "Result tag \"{tag}\" contains {c:?}, which is not supported by target \"{}\"",
+                    self.name()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if the program uses more qubits or results than the target
+    /// provides.
+    pub fn check_counts(&self, qubit_count: u64, result_count: u64) -> Result<()> {
+        if let Some(max_qubits) = self.max_qubits
+            && qubit_count > max_qubits
+        {
+            bail!(
+                "Program requires {qubit_count} qubits but target \"{}\" supports at most {max_qubits}",
+                self.name()
+            );
+        }
+        if let Some(max_results) = self.max_results
+            && result_count > max_results
+        {
+            bail!(
+                "Program requires {result_count} results but target \"{}\" supports at most {max_results}",
+                self.name()
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails if the module calls QIS operations outside of
+    /// [Self::native_gates].
+    pub fn check_native_gates(&self, module: &Module) -> Result<()> {
+        let Some(native_gates) = &self.native_gates else {
+            return Ok(());
+        };
+        for func in module.get_functions() {
+            for ins in func
+                .get_basic_blocks()
+                .iter()
+                .flat_map(|b| b.get_instructions())
+            {
+                if ins.get_opcode() != InstructionOpcode::Call {
+                    continue;
+                }
+                let Some(callee) = CallSiteValue::try_from(ins)
+                    .ok()
+                    .and_then(|call| call.get_called_fn_value())
+                else {
+                    continue;
+                };
+                let name = callee.get_name().to_string_lossy();
+                let Some(gate) = qis_operation_name(&name) else {
+                    continue;
+                };
+                if !native_gates.iter().any(|g| g == gate) {
+                    bail!(
+                        "QIS operation \"{gate}\" is not in the native gate set of target \"{}\"",
+                        self.name()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames QIS functions to use [Self::qis_prefix] and sets the QIR
+    /// profile of the entry point.
+    pub fn apply(&self, module: &Module) {
+        for func in module.get_functions() {
+            if let Some(prefix) = &self.qis_prefix {
+                let name = func.get_name().to_string_lossy().into_owned();
+                if let Some(rest) = name.strip_prefix(QIS_PREFIX) {
+                    func.as_global_value().set_name(&format!("{prefix}{rest}"));
+                }
+            }
+            if let Some(profile) = &self.qir_profile
+                && func
+                    .get_string_attribute(AttributeLoc::Function, "entry_point")
+                    .is_some()
+            {
+                func.add_attribute(
+                    AttributeLoc::Function,
+                    module
+                        .get_context()
+                        .create_string_attribute("qir_profiles", profile),
+                );
+            }
+        }
+    }
+}
+
+/// Whether `c` is in a character set such as `a-zA-Z0-9_`.
+fn char_set_contains(set: &str, c: char) -> bool {
+    let chars: Vec<char> = set.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if i + 2 < chars.len() && chars[i + 1] == '-' {
+            if (chars[i]..=chars[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if chars[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+/// The operation name of a QIS function, e.g. `rz` for
+/// `__quantum__qis__rz__body`. Barriers on any number of qubits are named
+/// `barrier`.
+fn qis_operation_name(func_name: &str) -> Option<&str> {
+    let rest = func_name.strip_prefix(QIS_PREFIX)?;
+    let op = rest.rsplit_once("__").map_or(rest, |(op, _)| op);
+    match op.strip_prefix("barrier") {
+        Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => Some("barrier"),
+        _ => Some(op),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use tket_qsystem::extension::result::ResultOp;
+
+    use super::*;
+    use crate::test::single_op_hugr;
+
+    #[rstest]
+    #[case("__quantum__qis__rz__body", Some("rz"))]
+    #[case("__quantum__qis__read_result__body", Some("read_result"))]
+    #[case("__quantum__qis__s__adj", Some("s"))]
+    #[case("__quantum__qis__barrier3__body", Some("barrier"))]
+    #[case("__quantum__rt__qubit_allocate", None)]
+    fn qis_operation(#[case] func_name: &str, #[case] expected: Option<&str>) {
+        assert_eq!(qis_operation_name(func_name), expected);
+    }
+
+    #[test]
+    fn from_json() {
+        let desc = TargetDescription::from_json(
+            r#"{
+                "name": "small",
+                "triple": "x86_64-unknown-linux-gnu",
+                "native_gates": ["rz", "phasedx", "rzz", "mz", "read_result", "reset"],
+                "max_qubits": 2,
+                "max_results": 4,
+                "classical_ops": ["arithmetic.int"],
+                "qis_prefix": "__small__qis__",
+                "qir_profile": "adaptive_profile"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(desc.name(), "small");
+        assert_eq!(desc.max_qubits, Some(2));
+        assert!(desc.check_counts(2, 4).is_ok());
+        assert!(desc.check_counts(3, 4).is_err());
+        assert!(desc.check_counts(2, 5).is_err());
+        // qubits are still counted when they are also checked at runtime
+        let desc = TargetDescription {
+            check_qubit_capacity: true,
+            ..desc
+        };
+        assert!(desc.check_counts(3, 4).is_err());
+    }
+
+    #[rstest]
+    #[case("a-z", 'q', true)]
+    #[case("a-z", 'Q', false)]
+    #[case("a-zA-Z0-9_", '_', true)]
+    #[case("a-zA-Z0-9_", '-', false)]
+    #[case("_-", '-', true)]
+    fn char_set(#[case] set: &str, #[case] c: char, #[case] expected: bool) {
+        assert_eq!(char_set_contains(set, c), expected);
+    }
+
+    #[rstest]
+    #[case("c_0", None)]
+    #[case("c-0", Some("contains '-'"))]
+    #[case("c_00000", Some("longer than the 6 bytes"))]
+    fn result_tags(#[case] tag: &str, #[case] expected: Option<&str>) {
+        let desc = TargetDescription::from_json(
+            r#"{"result_tag_chars": "a-z0-9_", "max_result_tag_length": 6}"#,
+        )
+        .unwrap();
+        let hugr = single_op_hugr(ResultOp::new_bool(tag).into());
+        match (desc.check_result_tags(&hugr), expected) {
+            (Ok(()), None) => {}
+            (Err(err), Some(expected)) => assert!(err.to_string().contains(expected), "{err}"),
+            (result, _) => panic!("unexpected {result:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(TargetDescription::from_json(r#"{"max_qbits": 2}"#).is_err());
+    }
+}
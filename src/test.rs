@@ -1,9 +1,23 @@
 use hugr::{
-    Hugr,
-    builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder},
-    ops::{OpTrait, OpType},
-    types::PolyFuncType,
+    Hugr, Wire,
+    builder::{Dataflow, DataflowSubContainer, HugrBuilder, ModuleBuilder, SubContainer as _},
+    extension::{
+        prelude::{UnwrapBuilder as _, bool_t, option_type, qb_t},
+        simple_op::MakeRegisteredOp as _,
+    },
+    ops::{DataflowOpTrait as _, OpTrait, OpType, Tag, Value},
+    std_extensions::arithmetic::{
+        int_ops::IntOpDef,
+        int_types::{ConstInt, int_type},
+    },
+    type_row,
+    types::{PolyFuncType, Signature, Type, TypeEnum, TypeRow},
 };
+use tket::TketOp;
+use tket::extension::bool::BoolOp;
+use tket::extension::rotation::ConstRotation;
+use tket_qsystem::extension::random::RandomOp;
+use tket_qsystem::extension::result::ResultOp;
 
 pub fn single_op_hugr(op: OpType) -> Hugr {
     let Some(sig) = op.dataflow_signature() else {
@@ -23,3 +37,224 @@ pub fn single_op_hugr(op: OpType) -> Hugr {
     };
     module_builder.finish_hugr().unwrap()
 }
+
+/// Allocates `num_qubits` qubits, applies `ops` and records the
+/// measurement of each qubit `i` as "i".
+pub fn circuit_hugr(num_qubits: usize, ops: &[(TketOp, Vec<usize>, Option<f64>)]) -> Hugr {
+    let mut module = ModuleBuilder::new();
+    let mut func = module
+        .define_function("main", Signature::new_endo(vec![]))
+        .unwrap();
+    let mut qbs: Vec<_> = (0..num_qubits)
+        .map(|_| {
+            func.add_dataflow_op(TketOp::QAlloc, [])
+                .unwrap()
+                .out_wire(0)
+        })
+        .collect();
+    apply_ops(&mut func, &mut qbs, ops);
+    for (i, qb) in qbs.into_iter().enumerate() {
+        let bit = func
+            .add_dataflow_op(TketOp::MeasureFree, [qb])
+            .unwrap()
+            .out_wire(0);
+        let bit = func
+            .add_dataflow_op(BoolOp::read, [bit])
+            .unwrap()
+            .out_wire(0);
+        func.add_dataflow_op(ResultOp::new_bool(i.to_string()), [bit])
+            .unwrap();
+    }
+    func.finish_with_outputs([]).unwrap();
+    module.finish_hugr().unwrap()
+}
+
+/// A measurement-free function of `num_qubits` qubits applying `ops`, and
+/// returning the qubits in the order given by `outputs`.
+pub fn fragment_hugr(
+    num_qubits: usize,
+    ops: &[(TketOp, Vec<usize>, Option<f64>)],
+    outputs: &[usize],
+) -> Hugr {
+    let mut module = ModuleBuilder::new();
+    let mut func = module
+        .define_function("main", Signature::new_endo(vec![qb_t(); num_qubits]))
+        .unwrap();
+    let mut qbs: Vec<_> = func.input_wires().collect();
+    apply_ops(&mut func, &mut qbs, ops);
+    func.finish_with_outputs(outputs.iter().map(|&i| qbs[i]))
+        .unwrap();
+    module.finish_hugr().unwrap()
+}
+
+fn apply_ops(
+    func: &mut impl Dataflow,
+    qbs: &mut [Wire],
+    ops: &[(TketOp, Vec<usize>, Option<f64>)],
+) {
+    for (op, targets, angle) in ops {
+        let mut inputs: Vec<_> = targets.iter().map(|&i| qbs[i]).collect();
+        if let Some(angle) = angle {
+            inputs.push(func.add_load_value(ConstRotation::new(*angle).unwrap()));
+        }
+        let outs = func.add_dataflow_op(*op, inputs).unwrap();
+        for (i, &t) in targets.iter().enumerate() {
+            qbs[t] = outs.out_wire(i);
+        }
+    }
+}
+
+/// Records a counter in each iteration of a loop counting down from 3,
+/// and a random number from an RNG context seeded with 42.
+pub fn loop_hugr() -> Hugr {
+    let mut module = ModuleBuilder::new();
+    let mut func = module
+        .define_function("main", Signature::new_endo(vec![]))
+        .unwrap();
+    let start = func.add_load_value(ConstInt::new_u(6, 3).unwrap());
+    let mut tail_loop = func
+        .tail_loop_builder([(int_type(6), start)], [], type_row![])
+        .unwrap();
+    let [n] = tail_loop.input_wires_arr();
+    tail_loop
+        .add_dataflow_op(ResultOp::new_uint("n", 6), [n])
+        .unwrap();
+    let one = tail_loop.add_load_value(ConstInt::new_u(6, 1).unwrap());
+    let [n] = tail_loop
+        .add_dataflow_op(IntOpDef::isub.with_log_width(6), [n, one])
+        .unwrap()
+        .outputs_arr();
+    let zero = tail_loop.add_load_value(ConstInt::new_u(6, 0).unwrap());
+    let [done] = tail_loop
+        .add_dataflow_op(IntOpDef::ieq.with_log_width(6), [n, zero])
+        .unwrap()
+        .outputs_arr();
+    let variants: Vec<TypeRow> = vec![vec![int_type(6)].into(), type_row![]];
+    let mut cond = tail_loop
+        .conditional_builder(
+            ([type_row![], type_row![]], done),
+            [(int_type(6), n)],
+            vec![Type::new_sum(variants.clone())].into(),
+        )
+        .unwrap();
+    for (tag, inputs) in [(0, 1), (1, 0)] {
+        let mut case = cond.case_builder(tag).unwrap();
+        let n = case.input_wires().take(inputs);
+        let control = case
+            .add_dataflow_op(Tag::new(tag, variants.clone()), n)
+            .unwrap();
+        case.finish_with_outputs(control.outputs()).unwrap();
+    }
+    let [control] = cond.finish_sub_container().unwrap().outputs_arr();
+    tail_loop.finish_with_outputs(control, []).unwrap();
+
+    let ctx = new_rng_context(&mut func, 42);
+    let bound = func.add_load_value(ConstInt::new_u(5, 1000).unwrap());
+    let [r, ctx] = func
+        .add_dataflow_op(RandomOp::RandomIntBounded, [ctx, bound])
+        .unwrap()
+        .outputs_arr();
+    func.add_dataflow_op(RandomOp::DeleteRNGContext, [ctx])
+        .unwrap();
+    func.add_dataflow_op(ResultOp::new_uint("r", 5), [r])
+        .unwrap();
+    func.finish_with_outputs([]).unwrap();
+    module.finish_hugr().unwrap()
+}
+
+/// Tries to allocate a qubit in each of three iterations of a loop, recording
+/// whether it succeeded as "ok". The loop holds on to the qubits of the last
+/// two iterations, so its single allocation site can have two qubits in use.
+pub fn held_qubits_hugr() -> Hugr {
+    let maybe_qb: Type = option_type(qb_t()).into();
+    let variants = [type_row![], vec![qb_t()].into()];
+    let mut module = ModuleBuilder::new();
+    let mut func = module
+        .define_function("main", Signature::new_endo(vec![]))
+        .unwrap();
+    let start = func.add_load_value(ConstInt::new_u(6, 3).unwrap());
+    let none = func.make_sum(0, variants.clone(), []).unwrap();
+    let older = func.make_sum(0, variants.clone(), []).unwrap();
+    let mut tail_loop = func
+        .tail_loop_builder(
+            [],
+            [
+                (int_type(6), start),
+                (maybe_qb.clone(), none),
+                (maybe_qb.clone(), older),
+            ],
+            type_row![],
+        )
+        .unwrap();
+    let [n, last, older] = tail_loop.input_wires_arr();
+    let [new] = tail_loop
+        .add_dataflow_op(TketOp::TryQAlloc, [])
+        .unwrap()
+        .outputs_arr();
+    let mut cond = tail_loop
+        .conditional_builder((variants.clone(), new), [], vec![bool_t(), maybe_qb].into())
+        .unwrap();
+    let mut none = cond.case_builder(0).unwrap();
+    let ok = none.add_load_value(Value::false_val());
+    let new = none.make_sum(0, variants.clone(), []).unwrap();
+    none.finish_with_outputs([ok, new]).unwrap();
+    let mut some = cond.case_builder(1).unwrap();
+    let [qb] = some.input_wires_arr();
+    let ok = some.add_load_value(Value::true_val());
+    let new = some.make_sum(1, variants, [qb]).unwrap();
+    some.finish_with_outputs([ok, new]).unwrap();
+    let [ok, new] = cond.finish_sub_container().unwrap().outputs_arr();
+    tail_loop
+        .add_dataflow_op(ResultOp::new_bool("ok"), [ok])
+        .unwrap();
+    free_maybe_qubit(&mut tail_loop, older);
+    let one = tail_loop.add_load_value(ConstInt::new_u(6, 1).unwrap());
+    let [n] = tail_loop
+        .add_dataflow_op(IntOpDef::isub.with_log_width(6), [n, one])
+        .unwrap()
+        .outputs_arr();
+    let zero = tail_loop.add_load_value(ConstInt::new_u(6, 0).unwrap());
+    let [done] = tail_loop
+        .add_dataflow_op(IntOpDef::ieq.with_log_width(6), [n, zero])
+        .unwrap()
+        .outputs_arr();
+    let [_, last, older] = tail_loop
+        .finish_with_outputs(done, [n, new, last])
+        .unwrap()
+        .outputs_arr();
+    free_maybe_qubit(&mut func, last);
+    free_maybe_qubit(&mut func, older);
+    func.finish_with_outputs([]).unwrap();
+    module.finish_hugr().unwrap()
+}
+
+/// Frees the qubit in an optional qubit, if there is one.
+fn free_maybe_qubit(func: &mut impl Dataflow, maybe_qb: Wire) {
+    let mut cond = func
+        .conditional_builder(
+            ([type_row![], vec![qb_t()].into()], maybe_qb),
+            [],
+            type_row![],
+        )
+        .unwrap();
+    cond.case_builder(0)
+        .unwrap()
+        .finish_with_outputs([])
+        .unwrap();
+    let mut some = cond.case_builder(1).unwrap();
+    let [qb] = some.input_wires_arr();
+    some.add_dataflow_op(TketOp::QFree, [qb]).unwrap();
+    some.finish_with_outputs([]).unwrap();
+    cond.finish_sub_container().unwrap();
+}
+
+fn new_rng_context(func: &mut impl Dataflow, seed: u64) -> Wire {
+    let op = RandomOp::NewRNGContext.to_extension_op().unwrap();
+    let TypeEnum::Sum(option) = op.signature().output()[0].as_type_enum().clone() else {
+        panic!("NewRNGContext does not return an option")
+    };
+    let seed = func.add_load_value(ConstInt::new_u(6, seed).unwrap());
+    let [ctx] = func.add_dataflow_op(op, [seed]).unwrap().outputs_arr();
+    let [ctx] = func.build_unwrap_sum(1, option, ctx).unwrap();
+    ctx
+}
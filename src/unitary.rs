@@ -0,0 +1,349 @@
+//! Unitary equivalence checking of compiled measurement-free fragments.
+//!
+//! A fragment is an entry point taking and returning only qubits. Its
+//! unitary is built once from the HUGR, using the gates applied by the
+//! [reference interpreter](crate::reference), and once from the
+//! `__quantum__qis__*` calls of the compiled entry function. The two must
+//! agree up to a global phase, which verifies gate lowerings and
+//! decompositions semantically.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use anyhow::{Context as _, Result, anyhow, bail, ensure};
+use hugr::extension::prelude::qb_t;
+use hugr::ops::OpType;
+use hugr::{Hugr, HugrView, Node};
+use inkwell::context::Context;
+use inkwell::module::Module;
+use inkwell::values::{BasicValue as _, BasicValueEnum, CallSiteValue, InstructionOpcode};
+use num_complex::Complex64;
+use rand::rngs::StdRng;
+
+use crate::reference::call_with_qubits;
+use crate::run::entry_point;
+use crate::runtime::statevector::StateVector;
+use crate::runtime::{Gate, QuantumBackend};
+use crate::{CompileArgs, find_hugr_entry_point, inkwell};
+
+/// Largest number of qubits of a fragment whose unitary is built.
+pub const MAX_QUBITS: usize = 10;
+
+const TOLERANCE: f64 = 1e-8;
+
+type Gates = Vec<(Gate, Vec<usize>)>;
+
+/// A sequence of gates on qubits `0..num_qubits`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Circuit {
+    pub num_qubits: usize,
+    pub gates: Gates,
+    /// The qubit returned as each output of the fragment.
+    pub outputs: Vec<usize>,
+}
+
+impl Circuit {
+    /// The circuit applied by the entry point of `hugr`.
+    pub fn from_hugr(hugr: &impl HugrView<Node = Node>) -> Result<Self> {
+        let entry = find_hugr_entry_point(hugr)?;
+        let OpType::FuncDefn(func) = hugr.get_optype(entry) else {
+            bail!("Entry point is not a function definition");
+        };
+        let sig = func.signature().body();
+        ensure!(
+            sig.input()
+                .iter()
+                .chain(sig.output().iter())
+                .all(|t| *t == qb_t()),
+            "Entry point must take and return only qubits"
+        );
+        let num_qubits = sig.input().len();
+        let gates = Rc::new(RefCell::new(vec![]));
+        let recorder = Recorder {
+            num_qubits,
+            gates: gates.clone(),
+        };
+        let outputs = call_with_qubits(hugr, entry, num_qubits, Box::new(recorder))?
+            .iter()
+            .map(|v| v.as_qubit())
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            num_qubits,
+            gates: gates.take(),
+            outputs,
+        })
+    }
+
+    /// The circuit applied by the entry point of an optimised module, whose
+    /// QIS calls must take constant angles and qubit parameters.
+    pub fn from_module(module: &Module) -> Result<Self> {
+        let entry = entry_point(module)?;
+        let params = entry.get_params();
+        let qubit = |value: Option<BasicValueEnum>| {
+            value
+                .and_then(|v| params.iter().position(|p| *p == v))
+                .ok_or_else(|| anyhow!("Expected a qubit parameter, got {value:?}"))
+        };
+        let mut circuit = Self {
+            num_qubits: params.len(),
+            gates: vec![],
+            outputs: vec![],
+        };
+        ensure!(
+            entry.count_basic_blocks() == 1,
+            "Entry point must not contain control flow"
+        );
+        let block = entry.get_first_basic_block().unwrap();
+        for ins in block.get_instructions() {
+            let operand = |i| ins.get_operand(i).and_then(|o| o.left());
+            match ins.get_opcode() {
+                InstructionOpcode::Call => {
+                    let call = CallSiteValue::try_from(ins).unwrap();
+                    let name = call
+                        .get_called_fn_value()
+                        .ok_or_else(|| anyhow!("Indirect call in entry point"))?
+                        .get_name()
+                        .to_string_lossy()
+                        .into_owned();
+                    let (mut angles, mut qubits) = (vec![], vec![]);
+                    for i in 0..call.count_arguments() {
+                        match operand(i) {
+                            Some(BasicValueEnum::FloatValue(angle)) => angles.push(
+                                angle
+                                    .get_constant()
+                                    .ok_or_else(|| anyhow!("Non-constant angle in {name}"))?
+                                    .0,
+                            ),
+                            arg => qubits.push(qubit(arg).with_context(|| format!("In {name}"))?),
+                        }
+                    }
+                    let gate = Gate::from_qis(&name, &angles)
+                        .ok_or_else(|| anyhow!("\"{name}\" is not a unitary gate"))?;
+                    circuit.gates.push((gate, qubits));
+                }
+                InstructionOpcode::InsertValue => {}
+                InstructionOpcode::Return => {
+                    let mut value = operand(0);
+                    // hugr-llvm builds returned structs in field order
+                    while let Some(ins) = value
+                        .and_then(|v| v.as_instruction_value())
+                        .filter(|ins| ins.get_opcode() == InstructionOpcode::InsertValue)
+                    {
+                        circuit
+                            .outputs
+                            .push(qubit(ins.get_operand(1).and_then(|o| o.left()))?);
+                        value = ins.get_operand(0).and_then(|o| o.left());
+                    }
+                    match value {
+                        Some(BasicValueEnum::StructValue(s)) if s.is_undef() => {
+                            circuit.outputs.reverse()
+                        }
+                        None => {}
+                        value => circuit.outputs.push(qubit(value)?),
+                    }
+                }
+                op => bail!("Unexpected {op:?} instruction in entry point"),
+            }
+        }
+        Ok(circuit)
+    }
+
+    /// The unitary of the circuit, as a list of columns, where qubit `k` of
+    /// a basis state index is input or output `k`.
+    pub fn unitary(&self) -> Result<Vec<Vec<Complex64>>> {
+        ensure!(
+            self.num_qubits <= MAX_QUBITS,
+            "Circuit has {} qubits, at most {MAX_QUBITS} are supported",
+            self.num_qubits
+        );
+        ensure!(
+            self.outputs.len() == self.num_qubits,
+            "Circuit returns {} of its {} qubits",
+            self.outputs.len(),
+            self.num_qubits
+        );
+        (0..1 << self.num_qubits)
+            .map(|input: usize| {
+                let mut state = StateVector::default();
+                state.ensure_qubits(self.num_qubits)?;
+                for q in (0..self.num_qubits).filter(|q| input >> q & 1 == 1) {
+                    state.apply(Gate::X, &[q])?;
+                }
+                for (gate, qubits) in &self.gates {
+                    state.apply(*gate, qubits)?;
+                }
+                let mut column = vec![Complex64::ZERO; 1 << self.num_qubits];
+                for (i, a) in state.amplitudes().iter().enumerate() {
+                    let output = self
+                        .outputs
+                        .iter()
+                        .enumerate()
+                        .map(|(k, &q)| (i >> q & 1) << k)
+                        .sum::<usize>();
+                    column[output] = *a;
+                }
+                Ok(column)
+            })
+            .collect()
+    }
+
+    /// Whether the circuits have the same unitary up to a global phase.
+    pub fn equivalent(&self, other: &Self) -> Result<bool> {
+        if self.num_qubits != other.num_qubits {
+            return Ok(false);
+        }
+        let (u, v) = (self.unitary()?, other.unitary()?);
+        let (u, v) = (u.concat(), v.concat());
+        let largest = (0..u.len())
+            .max_by(|&i, &j| u[i].norm_sqr().total_cmp(&u[j].norm_sqr()))
+            .unwrap();
+        let phase = v[largest] / u[largest];
+        Ok((phase.norm() - 1.0).abs() < TOLERANCE
+            && u.iter()
+                .zip(&v)
+                .all(|(a, b)| (a * phase - b).norm() < TOLERANCE))
+    }
+}
+
+/// Records the gates applied by the reference interpreter.
+struct Recorder {
+    num_qubits: usize,
+    gates: Rc<RefCell<Gates>>,
+}
+
+impl QuantumBackend for Recorder {
+    fn ensure_qubits(&mut self, num_qubits: usize) -> Result<()> {
+        ensure!(
+            num_qubits <= self.num_qubits,
+            "Fragment must not allocate qubits"
+        );
+        Ok(())
+    }
+
+    fn apply(&mut self, gate: Gate, qubits: &[usize]) -> Result<()> {
+        self.gates.borrow_mut().push((gate, qubits.to_vec()));
+        Ok(())
+    }
+
+    fn measure(&mut self, _qubit: usize, _rng: &mut StdRng) -> Result<bool> {
+        bail!("Fragment must be measurement-free")
+    }
+
+    fn reset(&mut self, _qubit: usize, _rng: &mut StdRng) -> Result<()> {
+        bail!("Fragment must not reset qubits")
+    }
+}
+
+/// Compiles the fragment `hugr` with `args` and checks that the emitted
+/// circuit is equivalent to the HUGR.
+pub fn check_unitary_equivalence(hugr: &Hugr, args: &CompileArgs) -> Result<()> {
+    let expected = Circuit::from_hugr(hugr).context("Invalid fragment")?;
+    let context = Context::create();
+    let module = args.compile(&mut hugr.clone(), &context)?;
+    let compiled = Circuit::from_module(&module).context("Invalid compiled fragment")?;
+    ensure!(
+        expected.equivalent(&compiled)?,
+        "Compiled circuit is not equivalent to the HUGR\nexpected: {expected:?}\ncompiled: {compiled:?}"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use tket::TketOp;
+
+    use super::*;
+    use crate::test::fragment_hugr;
+
+    fn args(qsystem_pass: bool) -> CompileArgs {
+        CompileArgs {
+            qsystem_pass,
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::h(1, TketOp::H, vec![0], None)]
+    #[case::x(1, TketOp::X, vec![0], None)]
+    #[case::y(1, TketOp::Y, vec![0], None)]
+    #[case::z(1, TketOp::Z, vec![0], None)]
+    #[case::s(1, TketOp::S, vec![0], None)]
+    #[case::sdg(1, TketOp::Sdg, vec![0], None)]
+    #[case::t(1, TketOp::T, vec![0], None)]
+    #[case::tdg(1, TketOp::Tdg, vec![0], None)]
+    #[case::rx(1, TketOp::Rx, vec![0], Some(0.3))]
+    #[case::ry(1, TketOp::Ry, vec![0], Some(-0.7))]
+    #[case::rz(1, TketOp::Rz, vec![0], Some(1.2))]
+    #[case::cx(2, TketOp::CX, vec![1, 0], None)]
+    #[case::cy(2, TketOp::CY, vec![0, 1], None)]
+    #[case::cz(2, TketOp::CZ, vec![0, 1], None)]
+    fn lowering(
+        #[case] num_qubits: usize,
+        #[case] op: TketOp,
+        #[case] qubits: Vec<usize>,
+        #[case] angle: Option<f64>,
+        #[values(false, true)] qsystem_pass: bool,
+    ) {
+        let outputs: Vec<_> = (0..num_qubits).collect();
+        let hugr = fragment_hugr(num_qubits, &[(op, qubits, angle)], &outputs);
+        check_unitary_equivalence(&hugr, &args(qsystem_pass)).unwrap();
+    }
+
+    #[rstest]
+    #[case::v(1, TketOp::V, vec![0], None)]
+    #[case::vdg(1, TketOp::Vdg, vec![0], None)]
+    #[case::crz(2, TketOp::CRz, vec![0, 1], Some(0.4))]
+    #[case::toffoli(3, TketOp::Toffoli, vec![2, 0, 1], None)]
+    fn decomposition(
+        #[case] num_qubits: usize,
+        #[case] op: TketOp,
+        #[case] qubits: Vec<usize>,
+        #[case] angle: Option<f64>,
+    ) {
+        let outputs: Vec<_> = (0..num_qubits).collect();
+        let hugr = fragment_hugr(num_qubits, &[(op, qubits, angle)], &outputs);
+        check_unitary_equivalence(&hugr, &args(true)).unwrap();
+    }
+
+    #[test]
+    fn permuted_outputs() {
+        let ops = [
+            (TketOp::H, vec![0], None),
+            (TketOp::CX, vec![0, 1], None),
+            (TketOp::Rz, vec![2], Some(0.25)),
+        ];
+        let hugr = fragment_hugr(3, &ops, &[2, 0, 1]);
+        check_unitary_equivalence(&hugr, &args(true)).unwrap();
+        let circuit = Circuit::from_hugr(&hugr).unwrap();
+        let swapped = Circuit {
+            outputs: vec![0, 2, 1],
+            ..circuit.clone()
+        };
+        assert!(!circuit.equivalent(&swapped).unwrap());
+    }
+
+    #[test]
+    fn global_phase() {
+        let circuit = |gates| Circuit {
+            num_qubits: 1,
+            gates,
+            outputs: vec![0],
+        };
+        let z = circuit(vec![(Gate::Z, vec![0])]);
+        let rz = circuit(vec![(Gate::Rz(std::f64::consts::PI), vec![0])]);
+        let s = circuit(vec![(Gate::S, vec![0])]);
+        assert!(z.equivalent(&rz).unwrap());
+        assert!(!z.equivalent(&s).unwrap());
+    }
+
+    #[test]
+    fn not_a_fragment() {
+        let hugr = crate::test::circuit_hugr(1, &[(TketOp::H, vec![0], None)]);
+        let err = check_unitary_equivalence(&hugr, &args(true)).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("must not allocate qubits"),
+            "{err:#}"
+        );
+    }
+}